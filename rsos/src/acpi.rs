@@ -0,0 +1,256 @@
+//! Just enough ACPI table parsing to power off and reset the machine.
+//!
+//! Only the FADT and the `\_S5` object in the DSDT are looked at; there is no
+//! AML interpreter, so the sleep type values are read straight from the
+//! package bytes the way most firmware encodes them.
+
+use conquer_once::spin::OnceCell;
+use core::ptr;
+use x86_64::instructions::port::Port;
+use x86_64::VirtAddr;
+
+static INFO: OnceCell<AcpiInfo> = OnceCell::uninit();
+
+const SDT_HEADER_SIZE: u64 = 36;
+/// SLP_EN bit of the PM1 control register.
+const SLP_EN: u16 = 1 << 13;
+/// SCI_EN bit of the PM1 control register; set once the firmware hands over to ACPI mode.
+const SCI_EN: u16 = 1;
+/// RESET_REG_SUP bit of the FADT flags.
+const RESET_REG_SUPPORTED: u32 = 1 << 10;
+/// Generic address structure space id for system I/O.
+const SYSTEM_IO: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    RsdpNotFound,
+    BadChecksum([u8; 4]),
+    FadtNotFound,
+    S5NotFound,
+    Uninitialized,
+    /// The soft-off or reset request was written but the machine kept running.
+    NoEffect,
+}
+
+/// Power management values extracted from the FADT and DSDT.
+#[derive(Debug, Clone, Copy)]
+pub struct AcpiInfo {
+    pm1a_control: u16,
+    pm1b_control: u16,
+    slp_typa: u16,
+    slp_typb: u16,
+    smi_command: u16,
+    acpi_enable: u8,
+    reset_port: Option<u16>,
+    reset_value: u8,
+}
+
+/// Locates the ACPI tables through the physical memory mapping and records
+/// what `soft_off` and `reset` need.
+pub fn init(physical_memory_offset: VirtAddr) -> Result<(), AcpiError> {
+    let memory = PhysicalMemory {
+        offset: physical_memory_offset,
+    };
+    let info = parse(&memory)?;
+    let _ = INFO.try_init_once(|| info);
+    Ok(())
+}
+
+/// Returns the parsed tables, or `None` if `init` has not succeeded.
+pub fn info() -> Option<&'static AcpiInfo> {
+    INFO.try_get().ok()
+}
+
+/// Requests the S5 (soft-off) sleep state. Returns only if the machine is still running.
+pub fn soft_off() -> Result<(), AcpiError> {
+    let info = info().ok_or(AcpiError::Uninitialized)?;
+    enable(info);
+    unsafe {
+        Port::<u16>::new(info.pm1a_control).write(info.slp_typa | SLP_EN);
+        if info.pm1b_control != 0 {
+            Port::<u16>::new(info.pm1b_control).write(info.slp_typb | SLP_EN);
+        }
+    }
+    settle();
+    Err(AcpiError::NoEffect)
+}
+
+/// Writes the FADT reset value to the reset register. Returns only if the machine is still running.
+pub fn reset() -> Result<(), AcpiError> {
+    let info = info().ok_or(AcpiError::Uninitialized)?;
+    if let Some(port) = info.reset_port {
+        unsafe { Port::<u8>::new(port).write(info.reset_value) };
+        settle();
+    }
+    Err(AcpiError::NoEffect)
+}
+
+/// Switches the chipset into ACPI mode if the firmware left it in legacy mode.
+fn enable(info: &AcpiInfo) {
+    let mut control = Port::<u16>::new(info.pm1a_control);
+    if unsafe { control.read() } & SCI_EN != 0 || info.smi_command == 0 || info.acpi_enable == 0 {
+        return;
+    }
+    unsafe { Port::<u8>::new(info.smi_command).write(info.acpi_enable) };
+    for _ in 0..1_000_000 {
+        if unsafe { control.read() } & SCI_EN != 0 {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Gives the hardware a moment to act on a power request before reporting failure.
+fn settle() {
+    for _ in 0..10_000_000 {
+        core::hint::spin_loop();
+    }
+}
+
+struct PhysicalMemory {
+    offset: VirtAddr,
+}
+
+impl PhysicalMemory {
+    fn read<T: Copy>(&self, phys: u64) -> T {
+        unsafe { ptr::read_unaligned((self.offset + phys).as_ptr::<T>()) }
+    }
+
+    fn bytes(&self, phys: u64, len: usize) -> &[u8] {
+        unsafe { core::slice::from_raw_parts((self.offset + phys).as_ptr::<u8>(), len) }
+    }
+
+    fn checksum_ok(&self, phys: u64, len: usize) -> bool {
+        self.bytes(phys, len)
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+            == 0
+    }
+}
+
+fn parse(memory: &PhysicalMemory) -> Result<AcpiInfo, AcpiError> {
+    let rsdp = find_rsdp(memory).ok_or(AcpiError::RsdpNotFound)?;
+    let fadt = find_fadt(memory, rsdp)?;
+    let fadt_len = memory.read::<u32>(fadt + 4) as u64;
+
+    let mut dsdt = memory.read::<u32>(fadt + 40) as u64;
+    if fadt_len >= 148 {
+        let x_dsdt = memory.read::<u64>(fadt + 140);
+        if x_dsdt != 0 {
+            dsdt = x_dsdt;
+        }
+    }
+    let (slp_typa, slp_typb) = find_s5(memory, dsdt)?;
+
+    let flags = memory.read::<u32>(fadt + 112);
+    let reset_port = if fadt_len >= 129
+        && flags & RESET_REG_SUPPORTED != 0
+        && memory.read::<u8>(fadt + 116) == SYSTEM_IO
+    {
+        Some(memory.read::<u64>(fadt + 120) as u16)
+    } else {
+        None
+    };
+
+    Ok(AcpiInfo {
+        pm1a_control: memory.read::<u32>(fadt + 64) as u16,
+        pm1b_control: memory.read::<u32>(fadt + 68) as u16,
+        slp_typa,
+        slp_typb,
+        smi_command: memory.read::<u32>(fadt + 48) as u16,
+        acpi_enable: memory.read::<u8>(fadt + 52),
+        reset_port,
+        reset_value: memory.read::<u8>(fadt + 128),
+    })
+}
+
+/// Searches the first KiB of the EBDA and the BIOS area for the RSDP signature.
+fn find_rsdp(memory: &PhysicalMemory) -> Option<u64> {
+    let ebda = (memory.read::<u16>(0x40e) as u64) << 4;
+    let regions = [(ebda, ebda + 0x400), (0xe0000, 0x100000)];
+    regions
+        .into_iter()
+        .filter(|(start, _)| *start != 0)
+        .flat_map(|(start, end)| (start..end).step_by(16))
+        .find(|addr| memory.bytes(*addr, 8) == b"RSD PTR " && memory.checksum_ok(*addr, 20))
+}
+
+fn find_fadt(memory: &PhysicalMemory, rsdp: u64) -> Result<u64, AcpiError> {
+    let revision = memory.read::<u8>(rsdp + 15);
+    let xsdt = if revision >= 2 {
+        memory.read::<u64>(rsdp + 24)
+    } else {
+        0
+    };
+    let (root, entry_size) = if xsdt != 0 {
+        (xsdt, 8)
+    } else {
+        (memory.read::<u32>(rsdp + 16) as u64, 4)
+    };
+    let root_len = checked_table(memory, root)?;
+
+    let entries = (root_len - SDT_HEADER_SIZE) / entry_size;
+    for i in 0..entries {
+        let entry = root + SDT_HEADER_SIZE + i * entry_size;
+        let table = if entry_size == 8 {
+            memory.read::<u64>(entry)
+        } else {
+            memory.read::<u32>(entry) as u64
+        };
+        if memory.bytes(table, 4) == b"FACP" {
+            checked_table(memory, table)?;
+            return Ok(table);
+        }
+    }
+    Err(AcpiError::FadtNotFound)
+}
+
+/// Validates a table's checksum and returns its length.
+fn checked_table(memory: &PhysicalMemory, table: u64) -> Result<u64, AcpiError> {
+    let len = memory.read::<u32>(table + 4) as u64;
+    if len < SDT_HEADER_SIZE || !memory.checksum_ok(table, len as usize) {
+        let mut signature = [0; 4];
+        signature.copy_from_slice(memory.bytes(table, 4));
+        return Err(AcpiError::BadChecksum(signature));
+    }
+    Ok(len)
+}
+
+/// Reads SLP_TYPa and SLP_TYPb from the `\_S5` package in the DSDT.
+fn find_s5(memory: &PhysicalMemory, dsdt: u64) -> Result<(u16, u16), AcpiError> {
+    const NAME_OP: u8 = 0x08;
+    const PACKAGE_OP: u8 = 0x12;
+    const BYTE_PREFIX: u8 = 0x0a;
+
+    let len = checked_table(memory, dsdt)? as usize;
+    let aml = &memory.bytes(dsdt, len)[SDT_HEADER_SIZE as usize..];
+
+    let start = aml
+        .windows(4)
+        .enumerate()
+        .filter(|(_, name)| *name == b"_S5_")
+        .map(|(i, _)| i)
+        .find(|&i| {
+            let named = (i >= 1 && aml[i - 1] == NAME_OP)
+                || (i >= 2 && aml[i - 2] == NAME_OP && aml[i - 1] == b'\\');
+            named && aml.get(i + 4) == Some(&PACKAGE_OP)
+        })
+        .ok_or(AcpiError::S5NotFound)?;
+
+    // skip the PkgLength encoding and the element count
+    let mut pos = start + 5;
+    let pkg_length = *aml.get(pos).ok_or(AcpiError::S5NotFound)?;
+    pos += usize::from(pkg_length >> 6) + 2;
+
+    let mut next_value = || {
+        if aml.get(pos) == Some(&BYTE_PREFIX) {
+            pos += 1;
+        }
+        let value = *aml.get(pos).ok_or(AcpiError::S5NotFound)?;
+        pos += 1;
+        Ok(u16::from(value) << 10)
+    };
+    let slp_typa = next_value()?;
+    let slp_typb = next_value()?;
+    Ok((slp_typa, slp_typb))
+}
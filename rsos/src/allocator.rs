@@ -17,10 +17,10 @@ pub mod fixed_size_block;
 pub mod linked_list;
 
 #[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+static ALLOCATOR: Locked<Heap> = Locked::new(Heap::Uninit);
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB, overridable with `heap_size=`
 
 pub struct Dummy;
 
@@ -38,19 +38,222 @@ impl<A> Locked<A> {
     pub fn lock(&self) -> spin::MutexGuard<A> {
         self.inner.lock()
     }
+
+    pub fn try_lock(&self) -> Option<spin::MutexGuard<'_, A>> {
+        self.inner.try_lock()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+}
+
+/// Current heap usage, or `None` if the allocator is locked by the caller's context.
+pub fn heap_stats() -> Option<HeapStats> {
+    ALLOCATOR.try_lock().map(|allocator| allocator.stats())
+}
+
+/// Reports the failed request and the heap state on serial and VGA, then panics
+/// so the regular panic path halts the kernel (or fails the running test).
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    let stats = heap_stats().unwrap_or(HeapStats { size: 0, used: 0 });
+    crate::serial_println!(
+        "ALLOCATION ERROR: size {} align {}; heap {} of {} bytes used",
+        layout.size(),
+        layout.align(),
+        stats.used,
+        stats.size
+    );
+    crate::try_println!(
+        "ALLOCATION ERROR: size {} align {}; heap {} of {} bytes used",
+        layout.size(),
+        layout.align(),
+        stats.used,
+        stats.size
+    );
+    panic!(
+        "allocation error: size {} align {}",
+        layout.size(),
+        layout.align()
+    );
+}
+
+/// Operations shared by the heap allocators, so the kernel heap can use any of them.
+pub trait HeapAllocator {
+    /// Hands the region to the allocator. The memory must be mapped and otherwise unused.
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize);
+
+    /// Returns a block for `layout`, or null if none is available.
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8;
+
+    /// Returns a block obtained from `alloc` with the same `layout`.
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout);
+
+    fn stats(&self) -> HeapStats;
+}
+
+/// Allocator used for the kernel heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Fastest, but memory is only reclaimed once every allocation has been freed.
+    Bump,
+    /// Keeps freed regions in a list and reuses them; allocation walks the list.
+    LinkedList,
+    /// Lists of freed blocks per size class, with a linked list heap for larger blocks.
+    FixedSizeBlock,
+}
+
+/// The kernel heap, which is empty until `init_heap_with` selects a strategy.
+pub enum Heap {
+    Uninit,
+    Bump(BumpAllocator),
+    LinkedList(LinkedListAllocator),
+    FixedSizeBlock(FixedSizeBlockAllocator),
+}
+
+impl Heap {
+    fn new(strategy: Strategy) -> Self {
+        match strategy {
+            Strategy::Bump => Heap::Bump(BumpAllocator::new()),
+            Strategy::LinkedList => Heap::LinkedList(LinkedListAllocator::new()),
+            Strategy::FixedSizeBlock => Heap::FixedSizeBlock(FixedSizeBlockAllocator::new()),
+        }
+    }
+
+    fn selected(&self) -> Option<&dyn HeapAllocator> {
+        match self {
+            Heap::Uninit => None,
+            Heap::Bump(allocator) => Some(allocator),
+            Heap::LinkedList(allocator) => Some(allocator),
+            Heap::FixedSizeBlock(allocator) => Some(allocator),
+        }
+    }
+
+    fn selected_mut(&mut self) -> Option<&mut dyn HeapAllocator> {
+        match self {
+            Heap::Uninit => None,
+            Heap::Bump(allocator) => Some(allocator),
+            Heap::LinkedList(allocator) => Some(allocator),
+            Heap::FixedSizeBlock(allocator) => Some(allocator),
+        }
+    }
+}
+
+impl HeapAllocator for Heap {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        if let Some(allocator) = self.selected_mut() {
+            allocator.init(heap_start, heap_size);
+        }
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        match self.selected_mut() {
+            Some(allocator) => allocator.alloc(layout),
+            None => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        self.selected_mut()
+            .expect("dealloc before heap initialization")
+            .dealloc(ptr, layout);
+    }
+
+    fn stats(&self) -> HeapStats {
+        self.selected()
+            .map_or(HeapStats { size: 0, used: 0 }, |allocator| {
+                allocator.stats()
+            })
+    }
+}
+
+/// Bytes of `GUARD_BYTE` placed after every allocation in debug builds, and at
+/// least this many before it, so overruns are caught when the memory is freed.
+const GUARD_SIZE: usize = 16;
+const GUARD_BYTE: u8 = 0xfd;
+
+/// Layout of the block backing a guarded allocation, and the offset of the caller's
+/// bytes within it.
+fn guarded_layout(layout: &Layout) -> (Layout, usize) {
+    let offset = align_up(GUARD_SIZE, layout.align());
+    let size = offset + layout.size() + GUARD_SIZE;
+    (
+        Layout::from_size_align(size, layout.align()).unwrap(),
+        offset,
+    )
+}
+
+/// Panics with the first overwritten address if `len` bytes at `guard` are not all `GUARD_BYTE`.
+unsafe fn check_guard(guard: *const u8, len: usize, ptr: *const u8, layout: &Layout) {
+    for i in 0..len {
+        let byte = guard.add(i);
+        if byte.read() != GUARD_BYTE {
+            panic!(
+                "heap corruption at {:p}: guard bytes around the {}-byte allocation at {:p} were overwritten",
+                byte,
+                layout.size(),
+                ptr
+            );
+        }
+    }
+}
+
+unsafe impl<A: HeapAllocator> GlobalAlloc for Locked<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !cfg!(debug_assertions) {
+            return self.lock().alloc(layout);
+        }
+
+        let (block_layout, offset) = guarded_layout(&layout);
+        let block = self.lock().alloc(block_layout);
+        if block.is_null() {
+            return block;
+        }
+        block.write_bytes(GUARD_BYTE, offset);
+        let ptr = block.add(offset);
+        ptr.add(layout.size()).write_bytes(GUARD_BYTE, GUARD_SIZE);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !cfg!(debug_assertions) {
+            return self.lock().dealloc(ptr, layout);
+        }
+
+        // checked before taking the lock, so the panic does not leave it held
+        let (block_layout, offset) = guarded_layout(&layout);
+        let block = ptr.sub(offset);
+        check_guard(block, offset, ptr, &layout);
+        check_guard(ptr.add(layout.size()), GUARD_SIZE, ptr, &layout);
+        self.lock().dealloc(block, block_layout);
+    }
 }
 
 pub fn align_up(addr: usize, align: usize) -> usize {
     (addr + align - 1) & !(align - 1)
 }
 
+/// Maps the heap and hands it to the fixed-size block allocator.
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> Result<(), MapToError<Size4KiB>> {
+    init_heap_with(Strategy::FixedSizeBlock, mapper, frame_allocator)
+}
+
+/// Maps the heap and hands it to the allocator for `strategy`. Must only be called once.
+pub fn init_heap_with(
+    strategy: Strategy,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let heap_size = crate::config::get().heap_size;
     let page_range = {
         let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
+        let heap_end = heap_start + heap_size - 1u64;
         let head_start_page = Page::containing_address(heap_start);
         let head_end_page = Page::containing_address(heap_end);
         Page::range_inclusive(head_start_page, head_end_page)
@@ -64,8 +267,10 @@ pub fn init_heap(
         unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
     }
 
+    let mut heap = ALLOCATOR.lock();
+    *heap = Heap::new(strategy);
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        heap.init(HEAP_START, heap_size);
     }
 
     Ok(())
@@ -80,3 +285,69 @@ unsafe impl GlobalAlloc for Dummy {
         panic!("dealloc should be never called")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Backing memory for allocators under test, separate from the kernel heap.
+    #[repr(align(4096))]
+    struct Arena([u8; 4096]);
+
+    static mut ARENA: Arena = Arena([0; 4096]);
+
+    unsafe fn arena_init(allocator: &mut impl HeapAllocator) {
+        let arena = core::ptr::addr_of_mut!(ARENA.0) as usize;
+        allocator.init(arena, 4096);
+    }
+
+    #[test_case]
+    fn test_bump_does_not_reuse_until_empty() {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let mut bump = BumpAllocator::new();
+        unsafe {
+            arena_init(&mut bump);
+            let kept = bump.alloc(layout);
+            let freed = bump.alloc(layout);
+            bump.dealloc(freed, layout);
+            let next = bump.alloc(layout);
+            assert!(!next.is_null());
+            assert_ne!(next, freed);
+            assert_eq!(bump.stats().used, 3 * 64);
+
+            bump.dealloc(next, layout);
+            bump.dealloc(kept, layout);
+            assert_eq!(bump.stats().used, 0);
+            assert_eq!(bump.alloc(layout), kept);
+
+            let too_big = Layout::from_size_align(4096, 8).unwrap();
+            assert!(bump.alloc(too_big).is_null());
+        }
+    }
+
+    #[test_case]
+    fn test_linked_list_reuses_freed_blocks() {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let mut list = LinkedListAllocator::new();
+        unsafe {
+            arena_init(&mut list);
+            let kept = list.alloc(layout);
+            let freed = list.alloc(layout);
+            assert!(!kept.is_null() && !freed.is_null());
+            assert_eq!(list.stats().used, 2 * 64);
+
+            list.dealloc(freed, layout);
+            assert_eq!(list.stats().used, 64);
+            assert_eq!(list.alloc(layout), freed);
+
+            // repeated alloc and free never runs out
+            for _ in 0..1000 {
+                let ptr = list.alloc(layout);
+                assert!(!ptr.is_null());
+                list.dealloc(ptr, layout);
+            }
+            assert_eq!(list.stats().used, 2 * 64);
+            assert_eq!(list.stats().size, 4096);
+        }
+    }
+}
@@ -1,5 +1,5 @@
-use super::{align_up, Locked};
-use alloc::alloc::{GlobalAlloc, Layout};
+use super::{align_up, HeapAllocator, HeapStats};
+use alloc::alloc::Layout;
 use core::ptr;
 
 pub struct BumpAllocator {
@@ -18,39 +18,42 @@ impl BumpAllocator {
             allocations: 0,
         }
     }
+}
 
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+impl HeapAllocator for BumpAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.heap_start = heap_start;
         self.heap_end = heap_start + heap_size;
         self.next = heap_start;
     }
-}
-
-unsafe impl GlobalAlloc for Locked<BumpAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut bump = self.lock();
 
-        let alloc_start = align_up(bump.next, layout.align());
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let alloc_start = align_up(self.next, layout.align());
         let alloc_end = match alloc_start.checked_add(layout.size()) {
             Some(end) => end,
             None => return ptr::null_mut(),
         };
 
-        if alloc_end > bump.heap_end {
+        if alloc_end > self.heap_end {
             ptr::null_mut() // out of memory
         } else {
-            bump.next = alloc_end;
-            bump.allocations += 1;
+            self.next = alloc_end;
+            self.allocations += 1;
             alloc_start as *mut u8
         }
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        let mut bump = self.lock();
+    unsafe fn dealloc(&mut self, _ptr: *mut u8, _layout: Layout) {
+        self.allocations -= 1;
+        if self.allocations == 0 {
+            self.next = self.heap_start;
+        }
+    }
 
-        bump.allocations -= 1;
-        if bump.allocations == 0 {
-            bump.next = bump.heap_start;
+    fn stats(&self) -> HeapStats {
+        HeapStats {
+            size: self.heap_end - self.heap_start,
+            used: self.next - self.heap_start,
         }
     }
 }
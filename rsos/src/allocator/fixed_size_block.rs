@@ -1,5 +1,5 @@
-use super::Locked;
-use alloc::alloc::{GlobalAlloc, Layout};
+use super::{HeapAllocator, HeapStats};
+use alloc::alloc::Layout;
 use core::ptr;
 use core::{mem, ptr::NonNull};
 
@@ -23,10 +23,6 @@ impl FixedSizeBlockAllocator {
         }
     }
 
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        self.fallback_allocator.init(heap_start, heap_size);
-    }
-
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
         match self.fallback_allocator.allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
@@ -40,13 +36,16 @@ fn list_index(layout: &Layout) -> Option<usize> {
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
 }
 
-unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut allocator = self.lock();
+impl HeapAllocator for FixedSizeBlockAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.fallback_allocator.init(heap_start, heap_size);
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
         match list_index(&layout) {
-            Some(index) => match allocator.list_heads[index].take() {
+            Some(index) => match self.list_heads[index].take() {
                 Some(node) => {
-                    allocator.list_heads[index] = node.next.take();
+                    self.list_heads[index] = node.next.take();
                     node as *mut ListNode as *mut u8
                 }
                 None => {
@@ -54,30 +53,37 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                     let block_size = BLOCK_SIZES[index];
                     let block_align = block_size;
                     let layout = Layout::from_size_align(block_size, block_align).unwrap();
-                    allocator.fallback_alloc(layout)
+                    self.fallback_alloc(layout)
                 }
             },
-            None => allocator.fallback_alloc(layout),
+            None => self.fallback_alloc(layout),
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let mut allocator = self.lock();
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
         match list_index(&layout) {
             Some(index) => {
                 let new_node = ListNode {
-                    next: allocator.list_heads[index].take(),
+                    next: self.list_heads[index].take(),
                 };
                 assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
                 assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
                 let new_node_ptr = ptr as *mut ListNode;
                 new_node_ptr.write(new_node);
-                allocator.list_heads[index] = Some(&mut *new_node_ptr);
+                self.list_heads[index] = Some(&mut *new_node_ptr);
             }
             None => {
                 let ptr = NonNull::new(ptr).unwrap();
-                allocator.fallback_allocator.deallocate(ptr, layout);
+                self.fallback_allocator.deallocate(ptr, layout);
             }
         }
     }
+
+    /// Bytes under management and bytes handed out, including freed blocks kept in the lists.
+    fn stats(&self) -> HeapStats {
+        HeapStats {
+            size: self.fallback_allocator.size(),
+            used: self.fallback_allocator.used(),
+        }
+    }
 }
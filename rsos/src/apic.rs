@@ -0,0 +1,183 @@
+use crate::interrupts::{InterruptIndex, PICS};
+use crate::memory;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::{
+    registers::model_specific::Msr,
+    structures::paging::{mapper::MapToError, FrameAllocator, Mapper, PageTableFlags, Size4KiB},
+    PhysAddr,
+};
+
+/// Vector the local APIC raises for spurious interrupts.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// Physical base of the first IO APIC. The firmware may place it elsewhere,
+/// but QEMU and most PCs use this address.
+const IO_APIC_BASE: u64 = 0xfec0_0000;
+const IA32_APIC_BASE_MSR: u32 = 0x1b;
+
+// local APIC register offsets
+const LAPIC_ID: u64 = 0x20;
+const LAPIC_EOI: u64 = 0xb0;
+const LAPIC_ICR_LOW: u64 = 0x300;
+const LAPIC_ICR_HIGH: u64 = 0x310;
+const LAPIC_SPURIOUS: u64 = 0xf0;
+const LAPIC_LVT_TIMER: u64 = 0x320;
+const LAPIC_TIMER_INITIAL_COUNT: u64 = 0x380;
+const LAPIC_TIMER_CURRENT_COUNT: u64 = 0x390;
+const LAPIC_TIMER_DIVIDE: u64 = 0x3e0;
+
+const LAPIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+
+// IO APIC register offsets
+const IO_APIC_REGSEL: u64 = 0x00;
+const IO_APIC_WINDOW: u64 = 0x10;
+const IO_APIC_REDIRECTION_TABLE: u32 = 0x10;
+
+/// ISA IRQ line of the PS/2 keyboard.
+const KEYBOARD_IRQ: u32 = 1;
+/// ISA IRQ line of the first serial port.
+const SERIAL_IRQ: u32 = 4;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static LAPIC_BASE: AtomicU64 = AtomicU64::new(0);
+static IO_APIC: AtomicU64 = AtomicU64::new(0);
+
+/// Returns `true` once `init` switched interrupt delivery to the APIC.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// Switches from the legacy PIC to the local APIC and IO APIC.
+///
+/// Must be called after `crate::init`, with interrupts enabled: the local APIC
+/// timer is calibrated against the PIT so that `time` keeps its tick frequency.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    use x86_64::instructions::interrupts;
+
+    assert!(
+        interrupts::are_enabled(),
+        "APIC timer calibration needs the PIT interrupt"
+    );
+
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+    let lapic_phys = unsafe { Msr::new(IA32_APIC_BASE_MSR).read() } & 0x000f_ffff_ffff_f000;
+    let (lapic, flush) = memory::map_physical_range(
+        PhysAddr::new(lapic_phys),
+        0x400,
+        flags,
+        mapper,
+        frame_allocator,
+    )?;
+    flush.flush();
+    let (io_apic, flush) = memory::map_physical_range(
+        PhysAddr::new(IO_APIC_BASE),
+        0x20,
+        flags,
+        mapper,
+        frame_allocator,
+    )?;
+    flush.flush();
+    LAPIC_BASE.store(lapic.as_u64(), Ordering::Release);
+    IO_APIC.store(io_apic.as_u64(), Ordering::Release);
+
+    unsafe {
+        lapic_write(
+            LAPIC_SPURIOUS,
+            LAPIC_SOFTWARE_ENABLE | u32::from(SPURIOUS_VECTOR),
+        );
+    }
+    let ticks_per_pit_tick = calibrate_timer();
+
+    interrupts::without_interrupts(|| unsafe {
+        PICS.lock().disable();
+        ENABLED.store(true, Ordering::Release);
+
+        lapic_write(
+            LAPIC_LVT_TIMER,
+            u32::from(InterruptIndex::Timer.as_u8()) | LVT_TIMER_PERIODIC,
+        );
+        lapic_write(LAPIC_TIMER_INITIAL_COUNT, ticks_per_pit_tick);
+
+        let lapic_id = lapic_read(LAPIC_ID) >> 24;
+        route_irq(KEYBOARD_IRQ, InterruptIndex::Keyboard.as_u8(), lapic_id);
+        route_irq(SERIAL_IRQ, InterruptIndex::Serial.as_u8(), lapic_id);
+    });
+
+    Ok(())
+}
+
+/// Signals the end of the current interrupt to the local APIC.
+pub fn end_of_interrupt() {
+    unsafe { lapic_write(LAPIC_EOI, 0) };
+}
+
+/// Sends an inter-processor interrupt with the given ICR low word.
+///
+/// The destination field is left zero, so `icr_low` should use a destination shorthand.
+pub(crate) fn send_ipi(icr_low: u32) {
+    unsafe {
+        lapic_write(LAPIC_ICR_HIGH, 0);
+        lapic_write(LAPIC_ICR_LOW, icr_low);
+        while lapic_read(LAPIC_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Counts local APIC timer ticks during one PIT tick.
+fn calibrate_timer() -> u32 {
+    use crate::time::uptime_ticks;
+    use x86_64::instructions::hlt;
+
+    unsafe {
+        lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        lapic_write(LAPIC_LVT_TIMER, LVT_MASKED);
+    }
+
+    // start measuring right after a tick
+    let start = uptime_ticks();
+    while uptime_ticks() == start {
+        hlt();
+    }
+    unsafe { lapic_write(LAPIC_TIMER_INITIAL_COUNT, u32::MAX) };
+
+    let start = uptime_ticks();
+    while uptime_ticks() == start {
+        hlt();
+    }
+    let elapsed = u32::MAX - unsafe { lapic_read(LAPIC_TIMER_CURRENT_COUNT) };
+    unsafe { lapic_write(LAPIC_TIMER_INITIAL_COUNT, 0) };
+
+    elapsed.max(1)
+}
+
+/// Routes an ISA IRQ through the IO APIC to `vector` on the given local APIC.
+unsafe fn route_irq(irq: u32, vector: u8, lapic_id: u32) {
+    let entry = IO_APIC_REDIRECTION_TABLE + irq * 2;
+    // fixed delivery, physical destination, edge triggered, active high, unmasked
+    io_apic_write(entry, u32::from(vector));
+    io_apic_write(entry + 1, lapic_id << 24);
+}
+
+unsafe fn lapic_read(offset: u64) -> u32 {
+    let base = LAPIC_BASE.load(Ordering::Acquire);
+    ((base + offset) as *const u32).read_volatile()
+}
+
+unsafe fn lapic_write(offset: u64, value: u32) {
+    let base = LAPIC_BASE.load(Ordering::Acquire);
+    ((base + offset) as *mut u32).write_volatile(value);
+}
+
+unsafe fn io_apic_write(register: u32, value: u32) {
+    let base = IO_APIC.load(Ordering::Acquire);
+    ((base + IO_APIC_REGSEL) as *mut u32).write_volatile(register);
+    ((base + IO_APIC_WINDOW) as *mut u32).write_volatile(value);
+}
@@ -0,0 +1,134 @@
+use crate::memory;
+use crate::serial::SERIAL1;
+use core::arch::asm;
+use core::fmt::{self, Write};
+use x86_64::VirtAddr;
+
+/// Frames walked at most, so a corrupted but mapped chain still ends.
+pub const MAX_FRAMES: usize = 32;
+
+/// The registers a backtrace starts from.
+#[derive(Debug, Clone, Copy)]
+pub struct Registers {
+    pub rip: u64,
+    pub rsp: u64,
+    pub rbp: u64,
+}
+
+impl Registers {
+    /// Reads the registers at the call site.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let (rip, rsp, rbp): (u64, u64, u64);
+        unsafe {
+            asm!(
+                "lea {}, [rip]",
+                "mov {}, rsp",
+                "mov {}, rbp",
+                out(reg) rip,
+                out(reg) rsp,
+                out(reg) rbp,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+        Registers { rip, rsp, rbp }
+    }
+}
+
+/// Return addresses found by following the saved frame pointers from `rbp`.
+///
+/// Relies on the target keeping frame pointers. Each frame is checked to be
+/// aligned and mapped before it is read, and the walk stops after `MAX_FRAMES`
+/// or as soon as a frame does not lie above the one before it.
+pub fn frames(rbp: u64) -> Frames {
+    Frames {
+        rbp,
+        remaining: MAX_FRAMES,
+    }
+}
+
+pub struct Frames {
+    rbp: u64,
+    remaining: usize,
+}
+
+impl Iterator for Frames {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.remaining == 0 || !frame_readable(self.rbp) {
+            return None;
+        }
+        self.remaining -= 1;
+
+        // [rbp] holds the caller's rbp and [rbp + 8] the return address
+        let frame = self.rbp as *const u64;
+        let (caller_rbp, return_address) = unsafe { (frame.read(), frame.add(1).read()) };
+        if return_address == 0 {
+            return None;
+        }
+        // The stack grows down, so callers' frames are always higher
+        self.rbp = if caller_rbp > self.rbp { caller_rbp } else { 0 };
+        Some(return_address)
+    }
+}
+
+fn frame_readable(rbp: u64) -> bool {
+    if rbp == 0 || !rbp.is_multiple_of(8) {
+        return false;
+    }
+    [Some(rbp), rbp.checked_add(8)].iter().all(|word| {
+        word.and_then(|addr| VirtAddr::try_new(addr).ok())
+            .is_some_and(memory::is_mapped)
+    })
+}
+
+/// Writes the registers and a backtrace starting from them, one address per line.
+pub fn write(out: &mut dyn Write, registers: &Registers) -> fmt::Result {
+    writeln!(
+        out,
+        "registers: rip={:#018x} rsp={:#018x} rbp={:#018x}",
+        registers.rip, registers.rsp, registers.rbp
+    )?;
+    writeln!(out, "backtrace:")?;
+    let mut count = 0;
+    for (index, address) in frames(registers.rbp).enumerate() {
+        writeln!(out, "  #{} {:#018x}", index, address)?;
+        count += 1;
+    }
+    if count == 0 {
+        writeln!(out, "  (no readable frames)")?;
+    }
+    Ok(())
+}
+
+/// Dumps the caller's registers and backtrace over serial, for panic handlers.
+///
+/// The serial port is unlocked first if it is held, since the panic may have
+/// happened while printing and the kernel halts afterwards anyway.
+#[inline(never)]
+pub fn dump() {
+    let registers = Registers::capture();
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut serial = SERIAL1.try_lock().unwrap_or_else(|| {
+            unsafe { SERIAL1.force_unlock() };
+            SERIAL1.lock()
+        });
+        let _ = write(&mut *serial, &registers);
+    });
+}
+
+#[test_case]
+fn test_backtrace_of_current_stack() {
+    let registers = Registers::capture();
+    assert!(frames(registers.rbp).next().is_some());
+    assert!(frames(registers.rbp).count() <= MAX_FRAMES);
+}
+
+#[test_case]
+fn test_rejects_bad_frame_pointers() {
+    assert_eq!(frames(0).count(), 0);
+    assert_eq!(frames(0x1001).count(), 0);
+    // non-canonical
+    assert_eq!(frames(0x8000_0000_0000_0000).count(), 0);
+}
@@ -0,0 +1,249 @@
+use crate::println;
+use conquer_once::spin::OnceCell;
+use pc_keyboard::layouts::{self, AnyLayout};
+
+/// Kernel command line, compiled in from `RSOS_CMDLINE` at build time.
+///
+/// The bootloader does not pass a command line, so this is the only source
+/// of boot parameters for now.
+pub const CMDLINE: &str = match option_env!("RSOS_CMDLINE") {
+    Some(cmdline) => cmdline,
+    None => "",
+};
+
+static CONFIG: OnceCell<Config> = OnceCell::uninit();
+
+/// Smallest `heap_size=` accepted: the heap must span at least one 4 KiB page.
+pub const MIN_HEAP_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyboardLayout {
+    Us104,
+    Uk105,
+    De105,
+    Jis109,
+    Azerty,
+    Colemak,
+    Dvorak104,
+}
+
+impl KeyboardLayout {
+    pub fn layout(self) -> AnyLayout {
+        match self {
+            KeyboardLayout::Us104 => AnyLayout::Us104Key(layouts::Us104Key),
+            KeyboardLayout::Uk105 => AnyLayout::Uk105Key(layouts::Uk105Key),
+            KeyboardLayout::De105 => AnyLayout::De105Key(layouts::De105Key),
+            KeyboardLayout::Jis109 => AnyLayout::Jis109Key(layouts::Jis109Key),
+            KeyboardLayout::Azerty => AnyLayout::Azerty(layouts::Azerty),
+            KeyboardLayout::Colemak => AnyLayout::Colemak(layouts::Colemak),
+            KeyboardLayout::Dvorak104 => AnyLayout::Dvorak104Key(layouts::Dvorak104Key),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptController {
+    /// Legacy 8259 PIC, kept as a fallback.
+    Pic,
+    Apic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub log_level: LogLevel,
+    pub heap_size: usize,
+    pub keyboard_layout: KeyboardLayout,
+    pub interrupt_controller: InterruptController,
+    /// Fixed seed for the software RNG, making random draws reproducible.
+    pub rng_seed: Option<u64>,
+}
+
+impl Config {
+    /// Whether messages of `level` pass the configured `log_level`.
+    pub fn logs(&self, level: LogLevel) -> bool {
+        level <= self.log_level
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            log_level: LogLevel::Info,
+            heap_size: crate::allocator::HEAP_SIZE,
+            keyboard_layout: KeyboardLayout::Us104,
+            interrupt_controller: InterruptController::Apic,
+            rng_seed: None,
+        }
+    }
+}
+
+/// Parses `CMDLINE` into the global config. Only the first call has an effect.
+pub fn init() {
+    let _ = CONFIG.try_init_once(|| parse(CMDLINE));
+}
+
+/// Returns the active config, or the defaults if `init` has not run yet.
+pub fn get() -> Config {
+    CONFIG.get().copied().unwrap_or_default()
+}
+
+/// Whether the active config logs messages of `level`; see `warn!`.
+pub fn enabled(level: LogLevel) -> bool {
+    get().logs(level)
+}
+
+/// Parses a space-separated list of `key=value` pairs.
+///
+/// Unknown keys and malformed values, including a `heap_size` below `MIN_HEAP_SIZE`,
+/// are reported and otherwise ignored. The reports honour `log_level` wherever it
+/// appears on the line.
+pub fn parse(cmdline: &str) -> Config {
+    let mut config = Config::default();
+    if let Some(level) = cmdline
+        .split_whitespace()
+        .rev()
+        .filter_map(|param| param.strip_prefix("log_level="))
+        .find_map(parse_log_level)
+    {
+        config.log_level = level;
+    }
+    let warn = config.logs(LogLevel::Warn);
+
+    for param in cmdline.split_whitespace() {
+        let (key, value) = match param.split_once('=') {
+            Some(pair) => pair,
+            None => {
+                if warn {
+                    println!("WARNING: ignoring malformed boot parameter '{}'", param);
+                }
+                continue;
+            }
+        };
+
+        let applied = match key {
+            "log_level" => parse_log_level(value).map(|level| config.log_level = level),
+            "heap_size" => parse_size(value)
+                .filter(|&size| size >= MIN_HEAP_SIZE)
+                .map(|size| config.heap_size = size),
+            "keyboard_layout" => {
+                parse_keyboard_layout(value).map(|layout| config.keyboard_layout = layout)
+            }
+            "interrupt_controller" => parse_interrupt_controller(value)
+                .map(|controller| config.interrupt_controller = controller),
+            "rng_seed" => parse_seed(value).map(|seed| config.rng_seed = Some(seed)),
+            _ => {
+                if warn {
+                    println!("WARNING: ignoring unknown boot parameter '{}'", key);
+                }
+                continue;
+            }
+        };
+
+        if applied.is_none() && warn {
+            println!(
+                "WARNING: invalid value '{}' for boot parameter '{}'",
+                value, key
+            );
+        }
+    }
+
+    config
+}
+
+fn parse_log_level(value: &str) -> Option<LogLevel> {
+    match value {
+        "error" => Some(LogLevel::Error),
+        "warn" => Some(LogLevel::Warn),
+        "info" => Some(LogLevel::Info),
+        "debug" => Some(LogLevel::Debug),
+        _ => None,
+    }
+}
+
+/// Parses a byte count with an optional `K` or `M` suffix.
+fn parse_size(value: &str) -> Option<usize> {
+    let (digits, multiplier) = match value.as_bytes().last()? {
+        b'K' | b'k' => (&value[..value.len() - 1], 1024),
+        b'M' | b'm' => (&value[..value.len() - 1], 1024 * 1024),
+        _ => (value, 1),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal `u64`.
+fn parse_seed(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn parse_keyboard_layout(value: &str) -> Option<KeyboardLayout> {
+    match value {
+        "us104" => Some(KeyboardLayout::Us104),
+        "uk105" => Some(KeyboardLayout::Uk105),
+        "de105" => Some(KeyboardLayout::De105),
+        "jis109" => Some(KeyboardLayout::Jis109),
+        "azerty" => Some(KeyboardLayout::Azerty),
+        "colemak" => Some(KeyboardLayout::Colemak),
+        "dvorak104" => Some(KeyboardLayout::Dvorak104),
+        _ => None,
+    }
+}
+
+fn parse_interrupt_controller(value: &str) -> Option<InterruptController> {
+    match value {
+        "pic" => Some(InterruptController::Pic),
+        "apic" => Some(InterruptController::Apic),
+        _ => None,
+    }
+}
+
+#[test_case]
+fn test_parse_cmdline() {
+    let config = parse("log_level=debug heap_size=200K keyboard_layout=uk105 bogus=1");
+    assert_eq!(config.log_level, LogLevel::Debug);
+    assert_eq!(config.heap_size, 200 * 1024);
+    assert_eq!(config.keyboard_layout, KeyboardLayout::Uk105);
+    assert_eq!(config.interrupt_controller, InterruptController::Apic);
+
+    let config = parse("interrupt_controller=pic");
+    assert_eq!(config.interrupt_controller, InterruptController::Pic);
+
+    assert_eq!(parse("rng_seed=1234").rng_seed, Some(1234));
+    assert_eq!(parse("rng_seed=0xff").rng_seed, Some(255));
+    assert_eq!(parse("rng_seed=x").rng_seed, None);
+}
+
+#[test_case]
+fn test_log_level_filters_messages() {
+    let config = Config::default();
+    assert!(config.logs(LogLevel::Warn));
+    assert!(config.logs(LogLevel::Info));
+    assert!(!config.logs(LogLevel::Debug));
+
+    let config = parse("bogus=1 log_level=error");
+    assert!(config.logs(LogLevel::Error));
+    assert!(!config.logs(LogLevel::Warn));
+}
+
+#[test_case]
+fn test_parse_invalid_value_keeps_default() {
+    let config = parse("heap_size=lots");
+    assert_eq!(config, Config::default());
+
+    assert_eq!(parse("heap_size=0").heap_size, crate::allocator::HEAP_SIZE);
+    assert_eq!(
+        parse("heap_size=4095").heap_size,
+        crate::allocator::HEAP_SIZE
+    );
+    assert_eq!(parse("heap_size=4K").heap_size, MIN_HEAP_SIZE);
+}
@@ -0,0 +1,71 @@
+use core::arch::x86_64::__cpuid;
+use lazy_static::lazy_static;
+
+/// CPU features that can be queried with `has_feature`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Tsc,
+    Msr,
+    Apic,
+    Sse,
+    Sse2,
+    X2Apic,
+    Rdrand,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Register {
+    Ecx,
+    Edx,
+}
+
+impl Feature {
+    /// Register and bit of CPUID leaf 1 that report this feature.
+    fn location(self) -> (Register, u32) {
+        match self {
+            Feature::Tsc => (Register::Edx, 4),
+            Feature::Msr => (Register::Edx, 5),
+            Feature::Apic => (Register::Edx, 9),
+            Feature::Sse => (Register::Edx, 25),
+            Feature::Sse2 => (Register::Edx, 26),
+            Feature::X2Apic => (Register::Ecx, 21),
+            Feature::Rdrand => (Register::Ecx, 30),
+        }
+    }
+}
+
+struct FeatureInfo {
+    ecx: u32,
+    edx: u32,
+}
+
+lazy_static! {
+    /// CPUID leaf 1, queried once on first use.
+    static ref FEATURE_INFO: FeatureInfo = {
+        // `__cpuid` is only `unsafe` on older toolchains
+        #[allow(unused_unsafe)]
+        let result = unsafe { __cpuid(0x1) };
+        FeatureInfo {
+            ecx: result.ecx,
+            edx: result.edx,
+        }
+    };
+}
+
+pub fn has_feature(feature: Feature) -> bool {
+    let (register, bit) = feature.location();
+    let value = match register {
+        Register::Ecx => FEATURE_INFO.ecx,
+        Register::Edx => FEATURE_INFO.edx,
+    };
+    value & (1 << bit) != 0
+}
+
+#[test_case]
+fn test_qemu_baseline_features() {
+    assert!(has_feature(Feature::Tsc));
+    assert!(has_feature(Feature::Msr));
+    assert!(has_feature(Feature::Apic));
+    assert!(has_feature(Feature::Sse));
+    assert!(has_feature(Feature::Sse2));
+}
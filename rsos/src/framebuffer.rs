@@ -0,0 +1,251 @@
+use core::fmt;
+use font::{GLYPH_HEIGHT, GLYPH_WIDTH};
+
+pub mod font;
+
+/// Byte order of a pixel in the framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgb,
+    Bgr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameBufferInfo {
+    pub width: usize,
+    pub height: usize,
+    /// Pixels per scanline, may be larger than `width`.
+    pub stride: usize,
+    pub bytes_per_pixel: usize,
+    pub pixel_format: PixelFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+    pub const WHITE: Rgb = Rgb::new(0xff, 0xff, 0xff);
+    pub const YELLOW: Rgb = Rgb::new(0xff, 0xff, 0x55);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r, g, b }
+    }
+}
+
+/// A linear framebuffer with at least three bytes per pixel.
+pub struct FrameBuffer<'a> {
+    buffer: &'a mut [u8],
+    info: FrameBufferInfo,
+}
+
+impl<'a> FrameBuffer<'a> {
+    pub fn new(buffer: &'a mut [u8], info: FrameBufferInfo) -> Self {
+        assert!(info.bytes_per_pixel >= 3);
+        assert!(info.stride >= info.width);
+        assert!(buffer.len() >= info.stride * info.height * info.bytes_per_pixel);
+        FrameBuffer { buffer, info }
+    }
+
+    pub fn info(&self) -> FrameBufferInfo {
+        self.info
+    }
+
+    /// Sets a pixel, ignoring coordinates outside the screen.
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        if x >= self.info.width || y >= self.info.height {
+            return;
+        }
+        let offset = (y * self.info.stride + x) * self.info.bytes_per_pixel;
+        let bytes = match self.info.pixel_format {
+            PixelFormat::Rgb => [color.r, color.g, color.b],
+            PixelFormat::Bgr => [color.b, color.g, color.r],
+        };
+        self.buffer[offset..offset + 3].copy_from_slice(&bytes);
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Rgb> {
+        if x >= self.info.width || y >= self.info.height {
+            return None;
+        }
+        let offset = (y * self.info.stride + x) * self.info.bytes_per_pixel;
+        let bytes = &self.buffer[offset..offset + 3];
+        Some(match self.info.pixel_format {
+            PixelFormat::Rgb => Rgb::new(bytes[0], bytes[1], bytes[2]),
+            PixelFormat::Bgr => Rgb::new(bytes[2], bytes[1], bytes[0]),
+        })
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        for row in y..y + height {
+            for col in x..x + width {
+                self.set_pixel(col, row, color);
+            }
+        }
+    }
+
+    /// Moves the whole image up by `rows` scanlines and fills the freed space.
+    pub fn scroll_up(&mut self, rows: usize, fill: Rgb) {
+        let line_bytes = self.info.stride * self.info.bytes_per_pixel;
+        let rows = rows.min(self.info.height);
+        let total = line_bytes * self.info.height;
+        self.buffer.copy_within(rows * line_bytes..total, 0);
+        let width = self.info.width;
+        self.fill_rect(0, self.info.height - rows, width, rows, fill);
+    }
+}
+
+/// Renders text with the 8x8 font, wrapping and scrolling like the VGA writer.
+pub struct FrameBufferWriter<'a> {
+    framebuffer: FrameBuffer<'a>,
+    column: usize,
+    row: usize,
+    foreground: Rgb,
+    background: Rgb,
+}
+
+impl<'a> FrameBufferWriter<'a> {
+    pub fn new(framebuffer: FrameBuffer<'a>) -> Self {
+        FrameBufferWriter {
+            framebuffer,
+            column: 0,
+            row: 0,
+            foreground: Rgb::YELLOW,
+            background: Rgb::BLACK,
+        }
+    }
+
+    pub fn set_colors(&mut self, foreground: Rgb, background: Rgb) {
+        self.foreground = foreground;
+        self.background = background;
+    }
+
+    pub fn framebuffer(&self) -> &FrameBuffer<'a> {
+        &self.framebuffer
+    }
+
+    fn columns(&self) -> usize {
+        self.framebuffer.info().width / GLYPH_WIDTH
+    }
+
+    fn rows(&self) -> usize {
+        self.framebuffer.info().height / GLYPH_HEIGHT
+    }
+
+    pub fn clear(&mut self) {
+        let info = self.framebuffer.info();
+        let background = self.background;
+        self.framebuffer
+            .fill_rect(0, 0, info.width, info.height, background);
+        self.column = 0;
+        self.row = 0;
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            byte => {
+                if self.column >= self.columns() {
+                    self.new_line();
+                }
+                self.draw_glyph(byte, self.column * GLYPH_WIDTH, self.row * GLYPH_HEIGHT);
+                self.column += 1;
+            }
+        }
+    }
+
+    pub fn write_string(&mut self, s: &str) {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+    }
+
+    fn draw_glyph(&mut self, byte: u8, x: usize, y: usize) {
+        let glyph = font::glyph(byte);
+        for (dy, bits) in glyph.iter().enumerate() {
+            for dx in 0..GLYPH_WIDTH {
+                let color = if bits & (1 << dx) != 0 {
+                    self.foreground
+                } else {
+                    self.background
+                };
+                self.framebuffer.set_pixel(x + dx, y + dy, color);
+            }
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows() {
+            self.row += 1;
+        } else {
+            let background = self.background;
+            self.framebuffer.scroll_up(GLYPH_HEIGHT, background);
+        }
+    }
+}
+
+impl fmt::Write for FrameBufferWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+const TEST_INFO: FrameBufferInfo = FrameBufferInfo {
+    width: 32,
+    height: 16,
+    stride: 32,
+    bytes_per_pixel: 4,
+    pixel_format: PixelFormat::Bgr,
+};
+
+#[cfg(test)]
+const TEST_BUFFER_SIZE: usize = 32 * 16 * 4;
+
+#[test_case]
+fn test_render_glyph() {
+    use core::fmt::Write;
+
+    let mut buffer = [0u8; TEST_BUFFER_SIZE];
+    let mut writer = FrameBufferWriter::new(FrameBuffer::new(&mut buffer, TEST_INFO));
+    writer.set_colors(Rgb::WHITE, Rgb::BLACK);
+    write!(writer, "A").unwrap();
+
+    let glyph = font::glyph(b'A');
+    for (y, bits) in glyph.iter().enumerate() {
+        for x in 0..GLYPH_WIDTH {
+            let expected = if bits & (1 << x) != 0 {
+                Rgb::WHITE
+            } else {
+                Rgb::BLACK
+            };
+            assert_eq!(writer.framebuffer().pixel(x, y), Some(expected));
+        }
+    }
+}
+
+#[test_case]
+fn test_wrap_and_scroll() {
+    let mut buffer = [0u8; TEST_BUFFER_SIZE];
+    let mut writer = FrameBufferWriter::new(FrameBuffer::new(&mut buffer, TEST_INFO));
+    writer.set_colors(Rgb::WHITE, Rgb::BLACK);
+
+    // four glyphs per row and two rows: the ninth glyph scrolls the first row away
+    writer.write_string("!!!!....|");
+    assert_eq!(writer.row, 1);
+    assert_eq!(writer.column, 1);
+
+    // the top row now shows the '.' glyphs, whose first scanline is empty
+    assert_eq!(writer.framebuffer().pixel(3, 0), Some(Rgb::BLACK));
+    // and the '|' glyph sits at the start of the bottom row
+    assert_eq!(
+        writer.framebuffer().pixel(3, GLYPH_HEIGHT),
+        Some(Rgb::WHITE)
+    );
+}
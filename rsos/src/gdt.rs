@@ -4,18 +4,36 @@ use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
+pub const GENERAL_PROTECTION_FAULT_IST_INDEX: u16 = 2;
+
+/// 20 KiB: the double fault handler only formats the stack frame and panics.
+pub const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+/// 20 KiB: the page fault handler prints CR2, the error code and the stack frame,
+/// which needs a few KiB of formatting machinery on top of the frame itself.
+pub const PAGE_FAULT_STACK_SIZE: usize = 4096 * 5;
+/// 20 KiB: same budget as the page fault handler, it prints and panics as well.
+pub const GENERAL_PROTECTION_FAULT_STACK_SIZE: usize = 4096 * 5;
+
+/// Reserves a static stack of `$size` bytes and evaluates to its top address.
+macro_rules! ist_stack {
+    ($size:expr) => {{
+        static mut STACK: [u8; $size] = [0; $size];
+
+        let stack_start = VirtAddr::from_ptr(&raw const STACK);
+        stack_start + $size
+    }};
+}
 
 lazy_static! {
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            ist_stack!(DOUBLE_FAULT_STACK_SIZE);
+        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] =
+            ist_stack!(PAGE_FAULT_STACK_SIZE);
+        tss.interrupt_stack_table[GENERAL_PROTECTION_FAULT_IST_INDEX as usize] =
+            ist_stack!(GENERAL_PROTECTION_FAULT_STACK_SIZE);
         tss
     };
 }
@@ -1,5 +1,6 @@
-use crate::{gdt, hlt_loop};
-use crate::{print, println};
+use crate::{apic, gdt, hlt_loop};
+use crate::{try_print, try_println};
+use core::sync::atomic::{AtomicU64, Ordering};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin;
@@ -15,6 +16,7 @@ lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         idt.breakpoint.set_handler_fn(breakpoint_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
         unsafe {
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
@@ -22,7 +24,16 @@ lazy_static! {
         }
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt[InterruptIndex::Serial.as_usize()].set_handler_fn(serial_interrupt_handler);
+        idt[usize::from(apic::SPURIOUS_VECTOR)].set_handler_fn(spurious_interrupt_handler);
+        unsafe {
+            idt.page_fault
+                .set_handler_fn(page_fault_handler)
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+            idt.general_protection_fault
+                .set_handler_fn(general_protection_fault_handler)
+                .set_stack_index(gdt::GENERAL_PROTECTION_FAULT_IST_INDEX);
+        }
         idt
     };
 }
@@ -31,40 +42,124 @@ pub fn init_idt() {
     IDT.load();
 }
 
+const BREAKPOINT_VECTOR: u8 = 3;
+const INVALID_OPCODE_VECTOR: u8 = 6;
+const DOUBLE_FAULT_VECTOR: u8 = 8;
+const GENERAL_PROTECTION_FAULT_VECTOR: u8 = 13;
+const PAGE_FAULT_VECTOR: u8 = 14;
+
+/// Interrupts taken per vector since boot; updated from the handlers themselves.
+static COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+fn record(vector: u8) {
+    COUNTS[usize::from(vector)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of times the interrupt or exception `vector` has been handled.
+pub fn count(vector: u8) -> u64 {
+    COUNTS[usize::from(vector)].load(Ordering::Relaxed)
+}
+
+/// Snapshot of the per-vector counters for the handlers installed in the IDT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptStats {
+    pub breakpoint: u64,
+    pub invalid_opcode: u64,
+    pub double_fault: u64,
+    pub general_protection_fault: u64,
+    pub page_fault: u64,
+    pub timer: u64,
+    pub keyboard: u64,
+    pub serial: u64,
+    pub spurious: u64,
+}
+
+pub fn stats() -> InterruptStats {
+    InterruptStats {
+        breakpoint: count(BREAKPOINT_VECTOR),
+        invalid_opcode: count(INVALID_OPCODE_VECTOR),
+        double_fault: count(DOUBLE_FAULT_VECTOR),
+        general_protection_fault: count(GENERAL_PROTECTION_FAULT_VECTOR),
+        page_fault: count(PAGE_FAULT_VECTOR),
+        timer: count(InterruptIndex::Timer.as_u8()),
+        keyboard: count(InterruptIndex::Keyboard.as_u8()),
+        serial: count(InterruptIndex::Serial.as_u8()),
+        spurious: count(apic::SPURIOUS_VECTOR),
+    }
+}
+
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+    record(BREAKPOINT_VECTOR);
+    try_println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    record(INVALID_OPCODE_VECTOR);
+    panic!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
 }
 
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    record(DOUBLE_FAULT_VECTOR);
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    print!(".");
+    record(InterruptIndex::Timer.as_u8());
+    crate::time::tick();
+    try_print!(".");
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
+    notify_end_of_interrupt(InterruptIndex::Timer);
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    record(apic::SPURIOUS_VECTOR);
+    // spurious interrupts must not be acknowledged
 }
 
 extern "x86-interrupt" fn page_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
+    record(PAGE_FAULT_VECTOR);
     use x86_64::registers::control::Cr2;
 
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
+    let write_protection_fault =
+        PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    if error_code.contains(write_protection_fault) {
+        if let Some(Some(memory)) = crate::memory::KERNEL_MEMORY.try_lock().as_deref_mut() {
+            let crate::memory::KernelMemory {
+                mapper,
+                frame_allocator,
+            } = memory;
+            if crate::memory::handle_copy_on_write(Cr2::read(), mapper, frame_allocator) {
+                return;
+            }
+        }
+    }
+
+    try_println!("EXCEPTION: PAGE FAULT");
+    try_println!("Accessed Address: {:?}", Cr2::read());
+    try_println!("Error Code: {:?}", error_code);
+    try_println!("{:#?}", stack_frame);
     hlt_loop();
 }
 
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    record(GENERAL_PROTECTION_FAULT_VECTOR);
+    panic!(
+        "EXCEPTION: GENERAL PROTECTION FAULT (error code {:#x})\n{:#?}",
+        error_code, stack_frame
+    );
+}
+
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    record(InterruptIndex::Keyboard.as_u8());
     // use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
     // use spin::Mutex;
     use x86_64::instructions::port::Port;
@@ -95,9 +190,26 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     let scancode: u8 = unsafe { port.read() };
     crate::task::keyboard::add_scancode(scancode);
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+    notify_end_of_interrupt(InterruptIndex::Keyboard);
+}
+
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    record(InterruptIndex::Serial.as_u8());
+    // the UART keeps the interrupt raised until every waiting byte has been read
+    while let Some(byte) = crate::serial::try_receive() {
+        crate::task::serial::add_byte(byte);
+    }
+
+    notify_end_of_interrupt(InterruptIndex::Serial);
+}
+
+fn notify_end_of_interrupt(index: InterruptIndex) {
+    if apic::is_enabled() {
+        apic::end_of_interrupt();
+    } else {
+        unsafe {
+            PICS.lock().notify_end_of_interrupt(index.as_u8());
+        }
     }
 }
 
@@ -106,10 +218,12 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    /// COM1, on IRQ 4.
+    Serial = PIC_1_OFFSET + 4,
 }
 
 impl InterruptIndex {
-    fn as_u8(self) -> u8 {
+    pub fn as_u8(self) -> u8 {
         self as u8
     }
 
@@ -122,3 +236,21 @@ impl InterruptIndex {
 fn test_breakpoint_exception() {
     x86_64::instructions::interrupts::int3();
 }
+
+#[test_case]
+fn test_interrupt_counters() {
+    use x86_64::instructions::{hlt, interrupts};
+
+    let before = stats();
+    for _ in 0..3 {
+        interrupts::int3();
+    }
+    assert_eq!(stats().breakpoint, before.breakpoint + 3);
+
+    let ticks = crate::time::uptime_ticks();
+    while crate::time::uptime_ticks() < ticks + 2 {
+        hlt();
+    }
+    assert!(stats().timer >= before.timer + 2);
+    assert_eq!(stats().page_fault, before.page_fault);
+}
@@ -3,20 +3,32 @@
 #![feature(custom_test_frameworks)]
 #![test_runner(crate::test_runner)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![reexport_test_harness_main = "test_main"]
 
+pub mod acpi;
 pub mod allocator;
+pub mod apic;
+pub mod backtrace;
+pub mod config;
+pub mod cpu;
+pub mod framebuffer;
 pub mod gdt;
 pub mod interrupts;
 pub mod memory;
+pub mod power;
+pub mod rng;
 pub mod serial;
+pub mod smp;
 pub mod task;
+pub mod time;
 pub mod vga_buffer;
 
 extern crate alloc;
 use core::panic::PanicInfo;
 
 pub fn init() {
+    config::init();
     gdt::init();
     interrupts::init_idt();
     unsafe {
@@ -58,10 +70,50 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
+    backtrace::dump();
     exit_qemu(QemuExitCode::Failed);
     hlt_loop();
 }
 
+/// Captures up to `N` bytes of formatted output without allocating, so panic
+/// handlers of test kernels can inspect messages. Longer output is truncated.
+pub struct MessageBuffer<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> MessageBuffer<N> {
+    pub fn new() -> Self {
+        MessageBuffer {
+            bytes: [0; N],
+            len: 0,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // `write_str` only ever stores whole characters
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl<const N: usize> Default for MessageBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> core::fmt::Write for MessageBuffer<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let mut count = s.len().min(N - self.len);
+        while !s.is_char_boundary(count) {
+            count -= 1;
+        }
+        self.bytes[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum QemuExitCode {
@@ -85,8 +137,16 @@ use bootloader::{entry_point, BootInfo};
 entry_point!(test_kernel_main);
 
 #[cfg(test)]
-fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    use memory::BootInfoFrameAllocator;
+    use x86_64::VirtAddr;
+
     init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
     test_main();
     hlt_loop();
 }
@@ -8,17 +8,21 @@ use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 use rsos::memory::BootInfoFrameAllocator;
-use rsos::task::{executor::Executor, keyboard, simple_executor::SimpleExecutor, Task};
+use rsos::task::{
+    executor::Executor, keyboard, serial, shell, simple_executor::SimpleExecutor, Task,
+};
+use rsos::{println, warn};
 
 extern crate alloc;
 
-mod serial;
-mod vga_buffer;
-
 entry_point!(kernel_main);
 
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
+    use rsos::acpi;
     use rsos::allocator;
+    use rsos::apic;
+    use rsos::config::InterruptController;
+    use rsos::cpu::{self, Feature};
     use rsos::memory;
     use x86_64::{
         structures::paging::{Page, Translate},
@@ -33,6 +37,20 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
 
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    keyboard::init(keyboard::DEFAULT_QUEUE_CAPACITY);
+    serial::init(serial::DEFAULT_QUEUE_CAPACITY);
+
+    if let Err(err) = acpi::init(phys_mem_offset) {
+        warn!("ACPI tables unavailable: {:?}", err);
+    }
+
+    if rsos::config::get().interrupt_controller == InterruptController::Apic
+        && cpu::has_feature(Feature::Apic)
+    {
+        apic::init(&mut mapper, &mut frame_allocator).expect("APIC initialization failed");
+    }
+
+    memory::install(mapper, frame_allocator);
 
     let heap_value = Box::new(41);
     println!("heap_value at {:p}", heap_value);
@@ -58,10 +76,11 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let mut executor = Executor::new();
     executor.spawn(Task::new(example_task()));
     executor.spawn(Task::new(keyboard::print_keypresses()));
+    executor.spawn(Task::new(shell::run()));
     executor.run();
     /*
     let page = Page::containing_address(VirtAddr::new(0));
-    memory::creat_example_mapping(page, &mut mapper, &mut frame_allocator);
+    memory::creat_example_mapping(page, &mut mapper, &mut frame_allocator).flush();
     let page_ptr: *mut u64 = page.start_address().as_mut_ptr();
     unsafe { page_ptr.offset(400).write_volatile(0x_f021_f077_f065_f04e) };
     let addresses = [
@@ -102,6 +121,8 @@ async fn example_task() {
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!("{}", info);
+    rsos::serial_println!("{}", info);
+    rsos::backtrace::dump();
     rsos::hlt_loop();
 }
 
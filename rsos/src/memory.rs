@@ -1,11 +1,44 @@
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::sync::atomic::{AtomicU64, Ordering};
 use x86_64::{
     structures::paging::{
-        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+        mapper::{FlagUpdateError, MapToError, MappedFrame, MapperFlush, TranslateResult},
+        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame,
+        Size4KiB, Translate,
     },
     PhysAddr, VirtAddr,
 };
 
+/// Start of the virtual region used by `map_physical_range`.
+pub const PHYS_RANGE_START: u64 = 0x_5555_5555_0000;
+
+static NEXT_PHYS_RANGE_PAGE: AtomicU64 = AtomicU64::new(PHYS_RANGE_START);
+
+/// The offset passed to `init`, so `is_mapped` can walk the page tables later.
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Marks a read-only page whose frame is copied on the next write (an OS-available bit).
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+/// The kernel's page tables and frame allocator, once `install` handed them over.
+///
+/// Interrupt handlers only `try_lock` this, so holding the lock while a fault
+/// can occur makes the fault unresolvable rather than deadlocking.
+pub static KERNEL_MEMORY: spin::Mutex<Option<KernelMemory>> = spin::Mutex::new(None);
+
+pub struct KernelMemory {
+    pub mapper: OffsetPageTable<'static>,
+    pub frame_allocator: BootInfoFrameAllocator,
+}
+
+/// Makes the mapper and frame allocator available to the page fault handler.
+pub fn install(mapper: OffsetPageTable<'static>, frame_allocator: BootInfoFrameAllocator) {
+    *KERNEL_MEMORY.lock() = Some(KernelMemory {
+        mapper,
+        frame_allocator,
+    });
+}
+
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
     next: usize,
@@ -36,21 +69,180 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     }
 }
 
+/// Maps `page` to the VGA text buffer frame. The returned `MapperFlush` must be
+/// flushed before the page is used.
 pub fn creat_example_mapping(
     page: Page,
     mapper: &mut OffsetPageTable,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) {
+) -> MapperFlush<Size4KiB> {
     use x86_64::structures::paging::PageTableFlags as Flags;
 
     let frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
     let flags = Flags::PRESENT | Flags::WRITABLE;
 
     let map_to_result = unsafe { mapper.map_to(page, frame, flags, frame_allocator) };
-    map_to_result.expect("map_to failed").flush();
+    map_to_result.expect("map_to failed")
+}
+
+/// Ranges of more pages than this are flushed by reloading CR3 instead of page by page.
+const FLUSH_ALL_THRESHOLD: u64 = 32;
+
+/// Pending TLB invalidation for a range of pages, the counterpart of `MapperFlush`
+/// for functions that change several mappings at once.
+#[must_use = "Page table changes must be flushed or ignored."]
+pub struct RangeFlush {
+    start: Page,
+    count: u64,
+}
+
+impl RangeFlush {
+    /// Invalidates every page of the range, or the whole TLB for large ranges.
+    pub fn flush(self) {
+        if self.count > FLUSH_ALL_THRESHOLD {
+            flush_all();
+        } else {
+            for i in 0..self.count {
+                flush((self.start + i).start_address());
+            }
+        }
+    }
+
+    /// Leaves the TLB alone, for pages that were not mapped before.
+    pub fn ignore(self) {}
+}
+
+/// Maps the physical range `phys_start..phys_start + size` to fresh virtual pages
+/// and returns the virtual address corresponding to `phys_start`, along with the
+/// `RangeFlush` that must be flushed before the range is used.
+///
+/// The range is widened to page boundaries, so `phys_start` and `size` don't need
+/// to be page aligned. `PRESENT` is always added to `flags`.
+pub fn map_physical_range(
+    phys_start: PhysAddr,
+    size: u64,
+    flags: PageTableFlags,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(VirtAddr, RangeFlush), MapToError<Size4KiB>> {
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(phys_start);
+    let last_frame = PhysFrame::containing_address(phys_start + (size.max(1) - 1));
+    let page_count = (last_frame.start_address() - first_frame.start_address()) / 4096 + 1;
+
+    let virt_start = NEXT_PHYS_RANGE_PAGE.fetch_add(page_count * 4096, Ordering::Relaxed);
+    let first_page = Page::containing_address(VirtAddr::new(virt_start));
+
+    for (i, frame) in PhysFrame::range_inclusive(first_frame, last_frame).enumerate() {
+        let page = first_page + i as u64;
+        unsafe {
+            mapper
+                .map_to(
+                    page,
+                    frame,
+                    flags | PageTableFlags::PRESENT,
+                    frame_allocator,
+                )?
+                // covered by the returned `RangeFlush`
+                .ignore()
+        };
+    }
+
+    let virt = first_page.start_address() + (phys_start - first_frame.start_address());
+    let flush = RangeFlush {
+        start: first_page,
+        count: page_count,
+    };
+    Ok((virt, flush))
+}
+
+/// Makes `page` read-only and flags it so that the next write gives it a private copy.
+///
+/// The returned `MapperFlush` must be flushed before the page is written again, or
+/// the write can go through a stale writable translation.
+pub fn mark_copy_on_write(
+    page: Page,
+    mapper: &mut OffsetPageTable,
+) -> Result<MapperFlush<Size4KiB>, FlagUpdateError> {
+    let flags = match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped { flags, .. } => flags,
+        _ => return Err(FlagUpdateError::PageNotMapped),
+    };
+    let flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+    unsafe { mapper.update_flags(page, flags) }
+}
+
+/// Resolves a write fault on a copy-on-write page by copying its frame.
+///
+/// Returns `false` if `addr` is not on a copy-on-write page or no frame is left.
+pub fn handle_copy_on_write(
+    addr: VirtAddr,
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> bool {
+    let page = Page::<Size4KiB>::containing_address(addr);
+    let (old_frame, flags) = match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            flags,
+            ..
+        } if flags.contains(COPY_ON_WRITE) => (frame, flags),
+        _ => return false,
+    };
+    let new_frame = match frame_allocator.allocate_frame() {
+        Some(frame) => frame,
+        None => return false,
+    };
+
+    let phys_offset = mapper.phys_offset();
+    unsafe {
+        let src: *const u8 = (phys_offset + old_frame.start_address().as_u64()).as_ptr();
+        let dst: *mut u8 = (phys_offset + new_frame.start_address().as_u64()).as_mut_ptr();
+        core::ptr::copy_nonoverlapping(src, dst, 4096);
+    }
+
+    let flags = (flags - COPY_ON_WRITE) | PageTableFlags::WRITABLE;
+    // The faulting write is retried as soon as the handler returns, so unlike the
+    // mapping functions this cannot leave the flush to its caller
+    match unsafe { remap(page, new_frame, flags, mapper, frame_allocator) } {
+        Ok(flush) => flush.flush(),
+        Err(_) => return false,
+    }
+    true
+}
+
+/// Points an already mapped `page` at `frame`, replacing the old mapping.
+///
+/// The returned `MapperFlush` must be flushed before the new mapping is used.
+///
+/// ## Safety
+///
+/// Same as `Mapper::map_to`: the caller must ensure the new mapping doesn't break memory safety.
+pub unsafe fn remap(
+    page: Page,
+    frame: PhysFrame,
+    flags: PageTableFlags,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<MapperFlush<Size4KiB>, MapToError<Size4KiB>> {
+    if let Ok((_, flush)) = mapper.unmap(page) {
+        // the stale entry is invalidated by the flush of the new mapping
+        flush.ignore();
+    }
+    mapper.map_to(page, frame, flags, frame_allocator)
+}
+
+/// Invalidates the TLB entry of the page containing `addr`.
+pub fn flush(addr: VirtAddr) {
+    x86_64::instructions::tlb::flush(addr);
+}
+
+/// Invalidates all non-global TLB entries by reloading CR3.
+pub fn flush_all() {
+    x86_64::instructions::tlb::flush_all();
 }
 
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
@@ -102,3 +294,38 @@ fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Opt
 
     Some(frame.start_address() + u64::from(addr.page_offset()))
 }
+
+/// Returns whether `addr` is backed by a present page, walking the active page
+/// tables without locking or panicking, so it is usable from the panic handler.
+/// Always false before `init` recorded the physical memory offset.
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    use x86_64::registers::control::Cr3;
+    use x86_64::structures::paging::page_table::FrameError;
+
+    let offset = PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed);
+    if offset == 0 {
+        return false;
+    }
+
+    let table_indexes = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let mut frame = Cr3::read().0;
+
+    for &index in &table_indexes {
+        let table_ptr: *const PageTable =
+            VirtAddr::new(offset + frame.start_address().as_u64()).as_ptr();
+        let table = unsafe { &*table_ptr };
+
+        frame = match table[index].frame() {
+            Ok(frame) => frame,
+            Err(FrameError::FrameNotPresent) => return false,
+            Err(FrameError::HugeFrame) => return true,
+        };
+    }
+
+    true
+}
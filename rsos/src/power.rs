@@ -0,0 +1,55 @@
+use crate::{acpi, hlt_loop, warn};
+use x86_64::instructions::{interrupts, port::Port};
+
+/// Powers the machine off, falling back to emulator-specific ports when ACPI is unavailable.
+pub fn shutdown() -> ! {
+    interrupts::disable();
+    if let Err(err) = acpi::soft_off() {
+        warn!("ACPI shutdown failed: {:?}", err);
+    }
+
+    // QEMU (0x604) and older Bochs/QEMU (0xb004) power off on these writes
+    unsafe {
+        Port::<u16>::new(0x604).write(0x2000);
+        Port::<u16>::new(0xb004).write(0x2000);
+    }
+
+    warn!("shutdown failed; halting");
+    hlt_loop();
+}
+
+/// Resets the machine via the ACPI reset register, the keyboard controller,
+/// or, as a last resort, a triple fault.
+pub fn reboot() -> ! {
+    interrupts::disable();
+    if let Err(err) = acpi::reset() {
+        warn!("ACPI reset failed: {:?}", err);
+    }
+
+    // pulse the CPU reset line through the 8042 keyboard controller
+    let mut status: Port<u8> = Port::new(0x64);
+    unsafe {
+        for _ in 0..100_000 {
+            if status.read() & 0x2 == 0 {
+                break;
+            }
+        }
+        status.write(0xfe);
+    }
+    for _ in 0..10_000_000 {
+        core::hint::spin_loop();
+    }
+
+    // an empty IDT turns the next exception into a triple fault
+    unsafe {
+        use x86_64::structures::DescriptorTablePointer;
+        use x86_64::VirtAddr;
+
+        x86_64::instructions::tables::lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::new(0),
+        });
+        core::arch::asm!("int3");
+    }
+    hlt_loop();
+}
@@ -0,0 +1,164 @@
+use crate::config::{self, Config};
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use x86_64::instructions::port::Port;
+use x86_64::instructions::random::RdRand;
+
+/// How often a failing `rdrand` is retried before using the fallback.
+const RDRAND_RETRIES: usize = 10;
+
+lazy_static! {
+    /// `Some` if CPUID reports RDRAND support.
+    static ref RDRAND: Option<RdRand> = RdRand::new();
+}
+
+/// State of the xorshift64* fallback generator; zero means "not yet seeded".
+static FALLBACK_STATE: AtomicU64 = AtomicU64::new(0);
+
+/// Seed of the fallback generator once computed; zero means "not yet computed".
+static SEED: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the seed of the software generator: `rng_seed=` from the command line
+/// if given, otherwise a mix of the TSC, the RTC time and the timer tick count.
+///
+/// It is computed on first use, and every caller (including interrupt handlers
+/// racing the first call) sees the same value.
+pub fn seed() -> u64 {
+    let seed = SEED.load(Ordering::Relaxed);
+    if seed != 0 {
+        return seed;
+    }
+    let fresh = initial_seed(&config::get());
+    match SEED.compare_exchange(0, fresh, Ordering::Relaxed, Ordering::Relaxed) {
+        Ok(_) => fresh,
+        Err(existing) => existing,
+    }
+}
+
+/// Returns a random `u64`, using RDRAND when available unless a fixed seed was
+/// configured, in which case the sequence is reproducible.
+pub fn next_u64() -> u64 {
+    if config::get().rng_seed.is_some() {
+        return fallback_next_u64();
+    }
+    if let Some(rdrand) = *RDRAND {
+        for _ in 0..RDRAND_RETRIES {
+            if let Some(value) = rdrand.get_u64() {
+                return value;
+            }
+        }
+    }
+    fallback_next_u64()
+}
+
+/// Fills `dest` completely with random bytes.
+pub fn fill_bytes(dest: &mut [u8]) {
+    for chunk in dest.chunks_mut(8) {
+        let bytes = next_u64().to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
+
+fn fallback_next_u64() -> u64 {
+    let mut state = FALLBACK_STATE.load(Ordering::Relaxed);
+    loop {
+        let current = if state == 0 { seed() } else { state };
+        let next = xorshift64(current);
+        match FALLBACK_STATE.compare_exchange_weak(
+            state,
+            next,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => return output(next),
+            Err(actual) => state = actual,
+        }
+    }
+}
+
+fn xorshift64(mut x: u64) -> u64 {
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    x
+}
+
+/// Turns a generator state into the value handed out.
+fn output(state: u64) -> u64 {
+    state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+/// Builds a non-zero seed from `config`, or from the clocks if it has none.
+fn initial_seed(config: &Config) -> u64 {
+    let seed = match config.rng_seed {
+        Some(seed) => seed,
+        None => {
+            let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+            mix(tsc ^ rtc_time().rotate_left(21) ^ crate::time::uptime_ticks().rotate_left(42))
+        }
+    };
+    seed.max(1)
+}
+
+/// Scrambles the bits so that nearby inputs give unrelated seeds.
+fn mix(mut seed: u64) -> u64 {
+    seed = (seed ^ (seed >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    seed = (seed ^ (seed >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    seed ^ (seed >> 31)
+}
+
+/// The CMOS real time clock registers packed into one value.
+fn rtc_time() -> u64 {
+    // seconds, minutes, hours, day of month, month, year
+    const REGISTERS: [u8; 6] = [0x00, 0x02, 0x04, 0x07, 0x08, 0x09];
+
+    let mut time: u64 = 0;
+    for &register in &REGISTERS {
+        time = (time << 8) | u64::from(read_cmos(register));
+    }
+    time
+}
+
+fn read_cmos(register: u8) -> u8 {
+    let mut address: Port<u8> = Port::new(0x70);
+    let mut data: Port<u8> = Port::new(0x71);
+    unsafe {
+        address.write(register);
+        data.read()
+    }
+}
+
+#[test_case]
+fn test_consecutive_draws_differ() {
+    assert_ne!(next_u64(), next_u64());
+}
+
+#[test_case]
+fn test_fill_bytes_fills_slice() {
+    let mut buffer = [0u8; 37];
+    fill_bytes(&mut buffer);
+    assert!(buffer[..8].iter().any(|&b| b != 0));
+    assert!(buffer[29..].iter().any(|&b| b != 0));
+}
+
+#[test_case]
+fn test_fixed_seed_is_reproducible() {
+    let config = config::parse("rng_seed=42");
+    let mut state = initial_seed(&config);
+    let draws = [(); 3].map(|_| {
+        state = xorshift64(state);
+        output(state)
+    });
+    assert_eq!(
+        draws,
+        [
+            0x56ce_4ab7_719b_a3a0,
+            0xc841_eb53_ebbb_2dda,
+            0xca46_6be0_c998_0276
+        ]
+    );
+
+    // zero would keep xorshift at zero forever
+    assert_eq!(initial_seed(&config::parse("rng_seed=0")), 1);
+    assert_ne!(seed(), 0);
+}
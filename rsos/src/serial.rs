@@ -38,3 +38,110 @@ macro_rules! serial_println {
         concat!($fmt, "\n"), $($arg)*
     ));
 }
+
+/// Returns the next received byte of the first serial port, if one is waiting.
+///
+/// Reads the UART registers directly rather than through `SERIAL1`, so it is
+/// safe to call from the receive interrupt while the port is locked for output.
+pub fn try_receive() -> Option<u8> {
+    use x86_64::instructions::port::Port;
+
+    const DATA: u16 = 0x3F8;
+    const LINE_STATUS: u16 = DATA + 5;
+    const DATA_READY: u8 = 1;
+
+    unsafe {
+        if Port::<u8>::new(LINE_STATUS).read() & DATA_READY == 0 {
+            return None;
+        }
+        Some(Port::<u8>::new(DATA).read())
+    }
+}
+
+/// Prints `len` bytes starting at `addr` over serial, 16 per line, as the address,
+/// the bytes in hex and their printable ASCII characters. Does not allocate, so it
+/// works before the heap is set up.
+///
+/// # Safety
+///
+/// `addr..addr + len` must be mapped and readable.
+pub unsafe fn hexdump(addr: *const u8, len: usize) {
+    use x86_64::instructions::interrupts;
+
+    let bytes = unsafe { core::slice::from_raw_parts(addr, len) };
+    interrupts::without_interrupts(|| {
+        write_hexdump(&mut *SERIAL1.lock(), bytes, addr as usize)
+            .expect("Printing to serial failed");
+    });
+}
+
+/// Formats `bytes` as `hexdump` does, labelling the first byte with address `base`.
+pub fn write_hexdump(
+    out: &mut impl core::fmt::Write,
+    bytes: &[u8],
+    base: usize,
+) -> core::fmt::Result {
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        write!(out, "{:016x} ", base + line * 16)?;
+        for i in 0..16 {
+            if i == 8 {
+                out.write_char(' ')?;
+            }
+            match chunk.get(i) {
+                Some(byte) => write!(out, " {:02x}", byte)?,
+                None => out.write_str("   ")?,
+            }
+        }
+        out.write_str("  |")?;
+        for &byte in chunk {
+            let shown = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            };
+            out.write_char(shown)?;
+        }
+        out.write_str("|\n")?;
+    }
+    Ok(())
+}
+
+#[test_case]
+fn test_hexdump_format() {
+    use core::fmt::{self, Write};
+
+    struct Buffer {
+        bytes: [u8; 256],
+        len: usize,
+    }
+
+    impl Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.bytes
+                .get_mut(self.len..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    static DATA: [u8; 20] = *b"Hello, hexdump!\n\x00\x01\x7f\xff";
+    let mut out = Buffer {
+        bytes: [0; 256],
+        len: 0,
+    };
+    write_hexdump(&mut out, &DATA, 0x1000).unwrap();
+
+    let mut lines = core::str::from_utf8(&out.bytes[..out.len]).unwrap().lines();
+    assert_eq!(
+        lines.next(),
+        Some("0000000000001000  48 65 6c 6c 6f 2c 20 68  65 78 64 75 6d 70 21 0a  |Hello, hexdump!.|")
+    );
+    assert_eq!(
+        lines.next(),
+        Some("0000000000001010  00 01 7f ff                                       |....|")
+    );
+    assert_eq!(lines.next(), None);
+}
@@ -0,0 +1,98 @@
+//! Application processor bring-up.
+//!
+//! This is the first milestone of SMP support: every AP runs a tiny real mode
+//! trampoline that reports it came online and then parks in a `cli; hlt` loop.
+//! APs don't enter long mode or load their own GDT/IDT yet.
+
+use crate::{apic, memory::KERNEL_MEMORY, time, warn};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::structures::paging::FrameAllocator;
+
+/// Offset of the 16 bit "online" counter inside the trampoline page.
+const COUNTER_OFFSET: u64 = 0x100;
+
+/// Real mode code started by the SIPI at `vector * 0x1000`.
+const TRAMPOLINE: [u8; 14] = [
+    0xfa, // cli
+    0x8c, 0xc8, // mov ax, cs
+    0x8e, 0xd8, // mov ds, ax
+    0xf0, 0xff, 0x06, 0x00, 0x01, // lock inc word [0x100]
+    0xfa, // park: cli
+    0xf4, // hlt
+    0xeb, 0xfc, // jmp park
+];
+
+// INIT and STARTUP IPIs, level assert, sent to all processors excluding self
+const ICR_INIT: u32 = 0x000c_4500;
+const ICR_STARTUP: u32 = 0x000c_4600;
+
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of application processors that reported online.
+pub fn online_aps() -> usize {
+    ONLINE.load(Ordering::Relaxed)
+}
+
+/// Starts all application processors and returns how many came online.
+///
+/// Requires the APIC (`apic::init`) and `memory::install`. The trampoline needs a
+/// frame below 1 MiB, so this should run before most frames are handed out.
+pub fn boot_aps() -> usize {
+    if !apic::is_enabled() {
+        warn!("SMP needs the APIC; not starting application processors");
+        return 0;
+    }
+
+    let counter = {
+        let mut memory = KERNEL_MEMORY.lock();
+        let memory = match memory.as_mut() {
+            Some(memory) => memory,
+            None => {
+                warn!("SMP needs memory::install; not starting application processors");
+                return 0;
+            }
+        };
+        let frame = match memory.frame_allocator.allocate_frame() {
+            Some(frame) if frame.start_address().as_u64() < 0x10_0000 => frame,
+            _ => {
+                warn!("no frame below 1 MiB for the SMP trampoline");
+                return 0;
+            }
+        };
+
+        let page = memory.mapper.phys_offset() + frame.start_address().as_u64();
+        let code: *mut u8 = page.as_mut_ptr();
+        let counter: *mut u16 = (page + COUNTER_OFFSET).as_mut_ptr();
+        unsafe {
+            code.copy_from_nonoverlapping(TRAMPOLINE.as_ptr(), TRAMPOLINE.len());
+            counter.write_volatile(0);
+        }
+
+        let vector = (frame.start_address().as_u64() >> 12) as u32;
+        apic::send_ipi(ICR_INIT);
+        wait_ms(10);
+        apic::send_ipi(ICR_STARTUP | vector);
+        wait_ms(1);
+        apic::send_ipi(ICR_STARTUP | vector);
+        counter
+    };
+
+    wait_ms(100);
+    let started = usize::from(unsafe { counter.read_volatile() });
+    ONLINE.store(started, Ordering::Relaxed);
+    started
+}
+
+/// Waits at least `ms` milliseconds.
+///
+/// The uptime only advances in whole ticks, so the wait starts at the next tick.
+fn wait_ms(ms: u64) {
+    let start_tick = time::uptime_ticks();
+    while time::uptime_ticks() == start_tick {
+        x86_64::instructions::hlt();
+    }
+    let start = time::uptime_ms();
+    while time::uptime_ms() < start + ms {
+        x86_64::instructions::hlt();
+    }
+}
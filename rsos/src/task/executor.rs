@@ -1,5 +1,8 @@
 use super::{Task, TaskId};
+use crate::config::{self, LogLevel};
+use crate::{print, println, time};
 use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use core::sync::atomic::{AtomicU64, Ordering};
 use core::task::{self, Context, Poll, Waker};
 use crossbeam_queue::ArrayQueue;
 
@@ -7,6 +10,30 @@ pub struct Executor {
     tasks: BTreeMap<TaskId, Task>,
     task_queue: Arc<ArrayQueue<TaskId>>,
     waker_cache: BTreeMap<TaskId, Waker>,
+    spawned: u64,
+    polls: u64,
+    wakes: Arc<AtomicU64>,
+    watchdog: Option<Watchdog>,
+}
+
+/// Warns when tasks stay pending without a wakeup for `threshold` timer ticks.
+struct Watchdog {
+    threshold: u64,
+    last_progress: u64,
+    warned: bool,
+}
+
+/// Snapshot of the executor's activity counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutorMetrics {
+    /// Tasks spawned since the executor was created.
+    pub spawned: u64,
+    /// Tasks that were spawned but have not completed yet.
+    pub pending: usize,
+    /// Calls to `Future::poll` on any task.
+    pub polls: u64,
+    /// Wakeups delivered through the executor's wakers.
+    pub wakes: u64,
 }
 
 impl Executor {
@@ -15,48 +42,151 @@ impl Executor {
             tasks: BTreeMap::new(),
             task_queue: Arc::new(ArrayQueue::new(100)),
             waker_cache: BTreeMap::new(),
+            spawned: 0,
+            polls: 0,
+            wakes: Arc::new(AtomicU64::new(0)),
+            watchdog: None,
         }
     }
 
+    /// Enables a watchdog that logs the pending task ids once the executor has
+    /// been idle with pending tasks for `threshold_ticks` timer ticks, which
+    /// usually means a wakeup was lost. `None` disables it.
+    pub fn set_watchdog(&mut self, threshold_ticks: Option<u64>) {
+        self.watchdog = threshold_ticks.map(|threshold| Watchdog {
+            threshold,
+            last_progress: time::uptime_ticks(),
+            warned: false,
+        });
+    }
+
+    /// Logs a warning if the watchdog threshold has passed without progress.
+    ///
+    /// Returns `true` if this call logged the warning; it fires once per stall.
+    pub fn check_watchdog(&mut self) -> bool {
+        let Some(watchdog) = &mut self.watchdog else {
+            return false;
+        };
+        if watchdog.warned || self.tasks.is_empty() || !self.task_queue.is_empty() {
+            return false;
+        }
+        let idle = time::uptime_ticks() - watchdog.last_progress;
+        if idle < watchdog.threshold {
+            return false;
+        }
+
+        watchdog.warned = true;
+        if config::enabled(LogLevel::Warn) {
+            print!(
+                "WARNING: executor idle for {} ticks with {} pending tasks:",
+                idle,
+                self.tasks.len()
+            );
+            for task_id in self.tasks.keys() {
+                print!(" {}", task_id.0);
+            }
+            println!();
+        }
+        true
+    }
+
     pub fn spawn(&mut self, task: Task) {
         let task_id = task.id;
         if self.tasks.insert(task.id, task).is_some() {
             panic!("task with same ID already in tasks");
         }
+        self.spawned += 1;
         self.task_queue.push(task_id).expect("queue full");
     }
 
-    pub fn run_ready_tasks(&mut self) {
+    pub fn metrics(&self) -> ExecutorMetrics {
+        ExecutorMetrics {
+            spawned: self.spawned,
+            pending: self.tasks.len(),
+            polls: self.polls,
+            wakes: self.wakes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Polls the tasks that are ready right now and returns how many were polled.
+    ///
+    /// Tasks woken during the pass are left for the next pass.
+    pub fn poll_ready(&mut self) -> usize {
+        let mut polled = 0;
+        for _ in 0..self.task_queue.len() {
+            match self.task_queue.pop() {
+                Some(task_id) => {
+                    if self.poll_task(task_id) {
+                        polled += 1;
+                    }
+                }
+                None => break,
+            }
+        }
+        polled
+    }
+
+    /// Polls exactly one ready task and returns its id, or `None` if no task is ready.
+    ///
+    /// Tasks are taken in the order they were spawned or woken, which lets tests
+    /// drive the schedule one poll at a time.
+    pub fn step(&mut self) -> Option<TaskId> {
+        while let Some(task_id) = self.task_queue.pop() {
+            if self.poll_task(task_id) {
+                return Some(task_id);
+            }
+        }
+        None
+    }
+
+    /// Polls ready tasks until the ready queue is empty, then returns instead of halting.
+    pub fn run_until_idle(&mut self) {
+        while !self.task_queue.is_empty() {
+            self.poll_ready();
+        }
+    }
+
+    /// Polls a single task, returns `false` if it no longer exists.
+    fn poll_task(&mut self, task_id: TaskId) -> bool {
         // destructure 'self' to avoid borrow checker errors
         let Self {
             tasks,
             task_queue,
             waker_cache,
+            polls,
+            wakes,
+            watchdog,
+            ..
         } = self;
 
-        while let Some(task_id) = task_queue.pop() {
-            let task = match tasks.get_mut(&task_id) {
-                Some(task) => task,
-                None => continue,
-            };
-            let waker = waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
-            let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
-                Poll::Ready(()) => {
-                    tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
-                }
-                Poll::Pending => {}
+        let task = match tasks.get_mut(&task_id) {
+            Some(task) => task,
+            None => return false,
+        };
+        let waker = waker_cache
+            .entry(task_id)
+            .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone(), wakes.clone()));
+        let mut context = Context::from_waker(waker);
+        *polls += 1;
+        if let Some(watchdog) = watchdog {
+            watchdog.last_progress = time::uptime_ticks();
+            watchdog.warned = false;
+        }
+        match task.poll(&mut context) {
+            Poll::Ready(()) => {
+                tasks.remove(&task_id);
+                waker_cache.remove(&task_id);
             }
+            Poll::Pending => {}
         }
+        true
     }
 
     pub fn run(&mut self) -> ! {
         loop {
-            self.run_ready_tasks();
+            self.run_until_idle();
             self.sleep_if_idle();
+            self.check_watchdog();
         }
     }
 
@@ -75,17 +205,20 @@ impl Executor {
 struct TaskWaker {
     task_id: TaskId,
     task_queue: Arc<ArrayQueue<TaskId>>,
+    wakes: Arc<AtomicU64>,
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>, wakes: Arc<AtomicU64>) -> Waker {
         Waker::from(Arc::new(TaskWaker {
             task_id,
             task_queue,
+            wakes,
         }))
     }
 
     fn wake_task(&self) {
+        self.wakes.fetch_add(1, Ordering::Relaxed);
         self.task_queue.push(self.task_id).expect("task_queue full");
     }
 }
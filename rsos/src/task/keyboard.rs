@@ -1,45 +1,275 @@
-use crate::{print, println};
+use crate::{print, println, try_warn};
 use conquer_once::spin::OnceCell;
 use core::{
     pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
     task::{Context, Poll},
 };
 use crossbeam_queue::ArrayQueue;
 use futures_util::stream::{Stream, StreamExt};
 use futures_util::task::AtomicWaker;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use lazy_static::lazy_static;
+use pc_keyboard::{
+    layouts::AnyLayout, DecodedKey, HandleControl, KeyCode, KeyEvent, KeyState, Keyboard,
+    ScancodeSet1,
+};
+use spin::Mutex;
+
+/// Queue capacity used when `init` was not called before the queues are needed.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 100;
 
 static WAKER: AtomicWaker = AtomicWaker::new();
 
+static DROPPED_SCANCODES: AtomicU64 = AtomicU64::new(0);
+
+static STREAM_CREATED: AtomicBool = AtomicBool::new(false);
+
+static ECHO: AtomicBool = AtomicBool::new(true);
+
+static RAW_MODE: AtomicBool = AtomicBool::new(false);
+
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 
+static CHAR_QUEUE: OnceCell<ArrayQueue<char>> = OnceCell::uninit();
+
+lazy_static! {
+    /// Decoder used by the interrupt path to fill `CHAR_QUEUE`.
+    static ref DECODER: Mutex<Keyboard<AnyLayout, ScancodeSet1>> = Mutex::new(Keyboard::new(
+        ScancodeSet1::new(),
+        crate::config::get().keyboard_layout.layout(),
+        HandleControl::Ignore,
+    ));
+}
+
+/// Allocates the scancode and character queues with room for `capacity` entries each.
+///
+/// Needs the heap, and must be called at most once, before the first `ScancodeStream`.
+pub fn init(capacity: usize) {
+    SCANCODE_QUEUE
+        .try_init_once(|| ArrayQueue::new(capacity))
+        .expect("keyboard::init should only be called once");
+    let _ = CHAR_QUEUE.try_init_once(|| ArrayQueue::new(capacity));
+}
+
+/// Number of scancodes dropped because the scancode queue was full.
+pub fn dropped_scancodes() -> u64 {
+    DROPPED_SCANCODES.load(Ordering::Relaxed)
+}
+
 pub(crate) fn add_scancode(scancode: u8) {
     if let Ok(queue) = SCANCODE_QUEUE.try_get() {
         if let Err(_) = queue.push(scancode) {
-            println!("WARNING: scancode queue full; dropping keyboard input");
+            DROPPED_SCANCODES.fetch_add(1, Ordering::Relaxed);
+            try_warn!("scancode queue full; dropping keyboard input");
         } else {
             WAKER.wake();
         }
     } else {
-        println!("WARNING: scancode queue uninitialised");
+        try_warn!("scancode queue uninitialised");
+    }
+
+    if let Ok(queue) = CHAR_QUEUE.try_get() {
+        let mut keyboard = DECODER.lock();
+        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+            if let Some(DecodedKey::Unicode(character)) = keyboard.process_keyevent(key_event) {
+                // nobody is reading characters right now, drop the input silently
+                let _ = queue.push(character);
+            }
+        }
     }
 }
 
-pub async fn print_keypresses() {
-    let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(
-        ScancodeSet1::new(),
-        layouts::Us104Key,
-        HandleControl::Ignore,
-    );
+/// Sets whether `print_keypresses` echoes keys to the screen. Characters are
+/// delivered to `try_read_char` either way.
+pub fn set_echo(echo: bool) {
+    ECHO.store(echo, Ordering::Relaxed);
+}
 
-    while let Some(scancode) = scancodes.next().await {
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(character) => print!("{}", character),
-                    DecodedKey::RawKey(key) => print!("{:?}", key),
+pub fn echo() -> bool {
+    ECHO.load(Ordering::Relaxed)
+}
+
+/// In raw mode keys are only delivered, never acted on: echo is off, `print_keypresses`
+/// ignores its shortcuts, and `Ctrl+<letter>` reaches `try_read_char` as the control
+/// character (`Ctrl+C` as `'\u{3}'`), so a shell can do its own line editing.
+/// Leaving raw mode turns echo back on.
+pub fn set_raw_mode(raw: bool) {
+    use x86_64::instructions::interrupts;
+
+    RAW_MODE.store(raw, Ordering::Relaxed);
+    set_echo(!raw);
+    let handling = if raw {
+        HandleControl::MapLettersToUnicode
+    } else {
+        HandleControl::Ignore
+    };
+    // The keyboard interrupt takes the decoder lock too
+    interrupts::without_interrupts(|| DECODER.lock().set_ctrl_handling(handling));
+}
+
+pub fn raw_mode() -> bool {
+    RAW_MODE.load(Ordering::Relaxed)
+}
+
+/// Pops a decoded character without waiting, for code running outside the executor.
+///
+/// The character buffer is allocated on the first call, so keys pressed before
+/// that are not buffered.
+pub fn try_read_char() -> Option<char> {
+    CHAR_QUEUE
+        .get_or_init(|| ArrayQueue::new(DEFAULT_QUEUE_CAPACITY))
+        .pop()
+}
+
+/// Which modifier keys were held when a key was pressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+}
+
+/// A decoded key press together with the modifiers held at the time.
+///
+/// Control is not folded into the character, so `Ctrl+C` arrives as `'c'` with `ctrl` set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPress {
+    pub key: DecodedKey,
+    pub modifiers: Modifiers,
+}
+
+impl KeyPress {
+    /// Returns true for `Ctrl+<letter>` without Alt, in either case.
+    pub fn is_ctrl(&self, letter: char) -> bool {
+        self.modifiers.ctrl
+            && !self.modifiers.alt
+            && matches!(self.key, DecodedKey::Unicode(ch) if ch.eq_ignore_ascii_case(&letter))
+    }
+}
+
+/// Turns scancodes into key presses, tracking Ctrl, Alt and Shift on both sides.
+pub struct KeyDecoder {
+    keyboard: Keyboard<AnyLayout, ScancodeSet1>,
+    /// Held state of LCtrl, RCtrl, LAlt, RAlt, LShift and RShift.
+    held: [bool; 6],
+}
+
+impl KeyDecoder {
+    pub fn new() -> Self {
+        KeyDecoder {
+            keyboard: Keyboard::new(
+                ScancodeSet1::new(),
+                crate::config::get().keyboard_layout.layout(),
+                HandleControl::Ignore,
+            ),
+            held: [false; 6],
+        }
+    }
+
+    /// Modifiers currently held down.
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            ctrl: self.held[0] || self.held[1],
+            alt: self.held[2] || self.held[3],
+            shift: self.held[4] || self.held[5],
+        }
+    }
+
+    /// Feeds one scancode, returning a key press once a complete one has been decoded.
+    pub fn add_scancode(&mut self, scancode: u8) -> Option<KeyPress> {
+        let event = self.keyboard.add_byte(scancode).ok()??;
+        self.track_modifier(&event);
+        let key = self.keyboard.process_keyevent(event)?;
+        Some(KeyPress {
+            key,
+            modifiers: self.modifiers(),
+        })
+    }
+
+    fn track_modifier(&mut self, event: &KeyEvent) {
+        let index = match event.code {
+            KeyCode::LControl => 0,
+            KeyCode::RControl => 1,
+            KeyCode::LAlt => 2,
+            KeyCode::RAltGr => 3,
+            KeyCode::LShift => 4,
+            KeyCode::RShift => 5,
+            _ => return,
+        };
+        match event.state {
+            KeyState::Down => self.held[index] = true,
+            KeyState::Up => self.held[index] = false,
+            KeyState::SingleShot => {}
+        }
+    }
+}
+
+impl Default for KeyDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Key presses decoded from the keyboard interrupt's scancodes.
+///
+/// Built on `ScancodeStream`, so at most one of the two may be created.
+pub struct KeyStream {
+    scancodes: ScancodeStream,
+    decoder: KeyDecoder,
+}
+
+impl KeyStream {
+    pub fn new() -> Self {
+        KeyStream {
+            scancodes: ScancodeStream::new(),
+            decoder: KeyDecoder::new(),
+        }
+    }
+}
+
+impl Default for KeyStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for KeyStream {
+    type Item = KeyPress;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let Self { scancodes, decoder } = &mut *self;
+        loop {
+            match Pin::new(&mut *scancodes).poll_next(cx) {
+                Poll::Ready(Some(scancode)) => {
+                    if let Some(key) = decoder.add_scancode(scancode) {
+                        return Poll::Ready(Some(key));
+                    }
                 }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Echoes key presses to the screen; `Ctrl+L` clears it and `Ctrl+C` prints `^C`.
+///
+/// Prints nothing while echo is off, and ignores the shortcuts in raw mode.
+pub async fn print_keypresses() {
+    let mut keys = KeyStream::new();
+
+    while let Some(key) = keys.next().await {
+        let raw = raw_mode();
+        if !raw && key.is_ctrl('l') {
+            let mut writer = crate::vga_buffer::WRITER.lock();
+            writer.clear_screen();
+            writer.goto(0, 0);
+        } else if !raw && key.is_ctrl('c') {
+            println!("^C");
+        } else if echo() {
+            match key.key {
+                DecodedKey::Unicode(character) => print!("{}", character),
+                DecodedKey::RawKey(key) => print!("{:?}", key),
             }
         }
     }
@@ -51,9 +281,10 @@ pub struct ScancodeStream {
 
 impl ScancodeStream {
     pub fn new() -> Self {
-        SCANCODE_QUEUE
-            .try_init_once(|| ArrayQueue::new(100))
-            .expect("ScancodeStream::new should only be called once");
+        if STREAM_CREATED.swap(true, Ordering::Relaxed) {
+            panic!("ScancodeStream::new should only be called once");
+        }
+        SCANCODE_QUEUE.get_or_init(|| ArrayQueue::new(DEFAULT_QUEUE_CAPACITY));
         ScancodeStream { _private: () }
     }
 }
@@ -77,3 +308,112 @@ impl Stream for ScancodeStream {
         }
     }
 }
+
+#[test_case]
+fn test_try_read_char() {
+    use x86_64::instructions::interrupts;
+
+    while try_read_char().is_some() {}
+    assert_eq!(try_read_char(), None);
+
+    interrupts::without_interrupts(|| {
+        add_scancode(0x1e); // 'A' pressed
+        add_scancode(0x9e); // 'A' released
+    });
+    assert_eq!(try_read_char(), Some('a'));
+    assert_eq!(try_read_char(), None);
+}
+
+#[test_case]
+fn test_scancode_queue_overflow() {
+    use x86_64::instructions::interrupts;
+
+    const CAPACITY: usize = 4;
+    if !SCANCODE_QUEUE.is_initialized() {
+        init(CAPACITY);
+    }
+    let queue = SCANCODE_QUEUE.try_get().unwrap();
+    while queue.pop().is_some() {}
+    let capacity = queue.capacity();
+    let dropped = dropped_scancodes();
+
+    interrupts::without_interrupts(|| {
+        for i in 0..capacity + 2 {
+            // key releases, so the character queue stays untouched
+            add_scancode(0x80 | i as u8);
+        }
+    });
+
+    assert_eq!(dropped_scancodes(), dropped + 2);
+    for i in 0..capacity {
+        assert_eq!(queue.pop(), Some(0x80 | i as u8));
+    }
+    assert_eq!(queue.pop(), None);
+}
+
+#[test_case]
+fn test_ctrl_shortcut() {
+    let mut decoder = KeyDecoder::new();
+    decoder.add_scancode(0x1d); // left Ctrl pressed
+    let key = decoder.add_scancode(0x2e).unwrap(); // 'C' pressed
+    assert_eq!(key.key, DecodedKey::Unicode('c'));
+    assert!(key.modifiers.ctrl && !key.modifiers.shift);
+    assert!(key.is_ctrl('c') && key.is_ctrl('C'));
+
+    assert_eq!(decoder.add_scancode(0xae), None); // 'C' released
+    decoder.add_scancode(0x9d); // left Ctrl released
+    let key = decoder.add_scancode(0x2e).unwrap();
+    assert_eq!(key.modifiers, Modifiers::default());
+    assert!(!key.is_ctrl('c'));
+}
+
+#[test_case]
+fn test_echo_off_still_delivers_characters() {
+    use crate::vga_buffer::WRITER;
+    use alloc::boxed::Box;
+    use core::future::Future;
+    use futures_util::task::noop_waker_ref;
+    use x86_64::instructions::interrupts;
+
+    let cursor = || interrupts::without_interrupts(|| WRITER.lock().cursor());
+    let press = |scancode: u8| {
+        interrupts::without_interrupts(|| {
+            add_scancode(scancode);
+            add_scancode(scancode | 0x80);
+        })
+    };
+    let mut task = Box::pin(print_keypresses());
+    let mut cx = Context::from_waker(noop_waker_ref());
+    println!();
+    assert!(task.as_mut().poll(&mut cx).is_pending());
+    while try_read_char().is_some() {}
+
+    set_echo(false);
+    let before = cursor();
+    press(0x1e); // 'A'
+    assert!(task.as_mut().poll(&mut cx).is_pending());
+    assert_eq!(cursor(), before);
+    assert_eq!(try_read_char(), Some('a'));
+
+    set_echo(true);
+    press(0x30); // 'B'
+    assert!(task.as_mut().poll(&mut cx).is_pending());
+    assert_eq!(cursor(), (before.0, before.1 + 1));
+    assert_eq!(try_read_char(), Some('b'));
+
+    set_raw_mode(true);
+    let before = cursor();
+    interrupts::without_interrupts(|| {
+        add_scancode(0x1d); // left Ctrl pressed
+        add_scancode(0x2e); // 'C' pressed
+        add_scancode(0xae);
+        add_scancode(0x9d);
+    });
+    assert!(task.as_mut().poll(&mut cx).is_pending());
+    assert_eq!(cursor(), before);
+    assert_eq!(try_read_char(), Some('\u{3}'));
+
+    set_raw_mode(false);
+    assert!(echo());
+    println!();
+}
@@ -0,0 +1,87 @@
+use alloc::{boxed::Box, collections::BTreeMap};
+use core::any::Any;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// Per-task storage, created with `TaskLocal::new` in a `static`.
+///
+/// Every task sees its own value, created lazily by the init function on first
+/// access and dropped together with the task.
+pub struct TaskLocal<T: 'static> {
+    init: fn() -> T,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessError {
+    /// No task is being polled right now.
+    NoTask,
+    /// `with` was called again for this local from inside its own closure.
+    AlreadyBorrowed,
+}
+
+/// Values of all task locals of one task, keyed by the address of the `TaskLocal`.
+pub(crate) struct LocalMap {
+    values: BTreeMap<usize, Option<Box<dyn Any>>>,
+}
+
+/// Locals of the task currently being polled, null outside of `enter`.
+static CURRENT: AtomicPtr<LocalMap> = AtomicPtr::new(ptr::null_mut());
+
+impl LocalMap {
+    pub(crate) fn new() -> Self {
+        LocalMap {
+            values: BTreeMap::new(),
+        }
+    }
+}
+
+/// Makes `locals` the current task's locals while `f` runs.
+pub(crate) fn enter<R>(locals: &mut LocalMap, f: impl FnOnce() -> R) -> R {
+    let previous = CURRENT.swap(locals, Ordering::Acquire);
+    let result = f();
+    CURRENT.store(previous, Ordering::Release);
+    result
+}
+
+impl<T: 'static> TaskLocal<T> {
+    pub const fn new(init: fn() -> T) -> Self {
+        TaskLocal { init }
+    }
+
+    /// Runs `f` with the current task's value.
+    ///
+    /// Panics when called outside of a task or recursively for the same local.
+    pub fn with<R>(&'static self, f: impl FnOnce(&mut T) -> R) -> R {
+        match self.try_with(f) {
+            Ok(result) => result,
+            Err(AccessError::NoTask) => panic!("TaskLocal accessed outside of a task"),
+            Err(AccessError::AlreadyBorrowed) => panic!("TaskLocal already borrowed"),
+        }
+    }
+
+    pub fn try_with<R>(&'static self, f: impl FnOnce(&mut T) -> R) -> Result<R, AccessError> {
+        let key = self as *const Self as usize;
+        let locals = CURRENT.load(Ordering::Acquire);
+        if locals.is_null() {
+            return Err(AccessError::NoTask);
+        }
+
+        // the value is moved out while `f` runs, so nested calls can't alias it
+        let mut value = {
+            let locals = unsafe { &mut *locals };
+            match locals.values.get_mut(&key) {
+                Some(slot) => slot.take().ok_or(AccessError::AlreadyBorrowed)?,
+                None => {
+                    locals.values.insert(key, None);
+                    Box::new((self.init)())
+                }
+            }
+        };
+
+        let result = f(value.downcast_mut::<T>().expect("task local type mismatch"));
+
+        let locals = unsafe { &mut *locals };
+        locals.values.insert(key, Some(value));
+        Ok(result)
+    }
+}
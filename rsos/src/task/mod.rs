@@ -5,14 +5,21 @@ use core::{future::Future, pin::Pin};
 
 pub mod executor;
 pub mod keyboard;
+pub mod local;
+pub mod serial;
+pub mod shell;
 pub mod simple_executor;
 
+pub use local::TaskLocal;
+
+/// Identifies a task; ids are assigned in spawn order and never reused.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct TaskId(u64);
+pub struct TaskId(u64);
 
 pub struct Task {
     id: TaskId,
     future: Pin<Box<dyn Future<Output = ()>>>,
+    locals: local::LocalMap,
 }
 
 impl Task {
@@ -20,11 +27,17 @@ impl Task {
         Task {
             id: TaskId::new(),
             future: Box::pin(future),
+            locals: local::LocalMap::new(),
         }
     }
 
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
-        self.future.as_mut().poll(context)
+        let Self { future, locals, .. } = self;
+        local::enter(locals, || future.as_mut().poll(context))
     }
 }
 
@@ -0,0 +1,105 @@
+use crate::interrupts::{InterruptIndex, PICS, PIC_1_OFFSET};
+use crate::try_warn;
+use conquer_once::spin::OnceCell;
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+
+/// Queue capacity used when `init` was not called before the queue is needed.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 256;
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+static DROPPED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+static STREAM_CREATED: AtomicBool = AtomicBool::new(false);
+
+static BYTE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+
+/// Allocates the receive queue with room for `capacity` bytes and enables the
+/// receive interrupt of the first serial port.
+///
+/// Needs the heap, and must be called at most once, before the first `SerialStream`.
+pub fn init(capacity: usize) {
+    use x86_64::instructions::interrupts;
+
+    BYTE_QUEUE
+        .try_init_once(|| ArrayQueue::new(capacity))
+        .expect("serial::init should only be called once");
+    // programming the UART enables its receive interrupt
+    lazy_static::initialize(&crate::serial::SERIAL1);
+    interrupts::without_interrupts(|| unsafe {
+        let mut pics = PICS.lock();
+        let [primary, secondary] = pics.read_masks();
+        let irq = InterruptIndex::Serial.as_u8() - PIC_1_OFFSET;
+        pics.write_masks(primary & !(1 << irq), secondary);
+    });
+}
+
+/// Number of received bytes dropped because the queue was full or not yet allocated.
+pub fn dropped_bytes() -> u64 {
+    DROPPED_BYTES.load(Ordering::Relaxed)
+}
+
+pub(crate) fn add_byte(byte: u8) {
+    match BYTE_QUEUE.try_get() {
+        Ok(queue) => {
+            if queue.push(byte).is_err() {
+                DROPPED_BYTES.fetch_add(1, Ordering::Relaxed);
+                try_warn!("serial input queue full; dropping input");
+            } else {
+                WAKER.wake();
+            }
+        }
+        // nobody asked for serial input
+        Err(_) => {
+            DROPPED_BYTES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Bytes received on the first serial port.
+pub struct SerialStream {
+    _private: (),
+}
+
+impl SerialStream {
+    pub fn new() -> Self {
+        if STREAM_CREATED.swap(true, Ordering::Relaxed) {
+            panic!("SerialStream::new should only be called once");
+        }
+        BYTE_QUEUE.get_or_init(|| ArrayQueue::new(DEFAULT_QUEUE_CAPACITY));
+        SerialStream { _private: () }
+    }
+}
+
+impl Default for SerialStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let queue = BYTE_QUEUE.try_get().expect("not initialised");
+        if let Some(byte) = queue.pop() {
+            return Poll::Ready(Some(byte));
+        }
+
+        WAKER.register(cx.waker());
+        match queue.pop() {
+            Some(byte) => {
+                WAKER.take();
+                Poll::Ready(Some(byte))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
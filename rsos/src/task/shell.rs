@@ -0,0 +1,201 @@
+use super::serial::SerialStream;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use futures_util::stream::StreamExt;
+
+/// Bytes `cat` keeps before truncating its input.
+pub const CAT_BUFFER_SIZE: usize = 4096;
+
+/// A line holding only this ends the input to `cat`.
+pub const CAT_SENTINEL: &[u8] = b".";
+
+/// Longest command line kept; the rest of a longer line is ignored.
+const MAX_COMMAND_LINE: usize = 256;
+
+const PROMPT: &str = "> ";
+
+enum Mode {
+    Command,
+    /// Collecting input for `cat` until the sentinel line.
+    Cat {
+        buffer: Vec<u8>,
+        truncated: bool,
+    },
+}
+
+/// A line-based shell. Input arrives one byte at a time through `feed`, and
+/// everything the shell prints goes to `out`.
+///
+/// Commands:
+/// - `cat` (or `dump`): reads lines up to one holding only `.` and prints them back.
+/// - `help`: lists the commands.
+pub struct Shell<W> {
+    out: W,
+    line: Vec<u8>,
+    mode: Mode,
+    cat_capacity: usize,
+    /// Whether the previous byte was `\r`, so a following `\n` does not end another line.
+    after_cr: bool,
+}
+
+impl<W: Write> Shell<W> {
+    pub fn new(out: W) -> Self {
+        Self::with_cat_capacity(out, CAT_BUFFER_SIZE)
+    }
+
+    pub fn with_cat_capacity(out: W, cat_capacity: usize) -> Self {
+        Shell {
+            out,
+            line: Vec::new(),
+            mode: Mode::Command,
+            cat_capacity,
+            after_cr: false,
+        }
+    }
+
+    pub fn output(&self) -> &W {
+        &self.out
+    }
+
+    pub fn prompt(&mut self) {
+        let _ = self.out.write_str(PROMPT);
+    }
+
+    /// Handles one input byte; `\r`, `\n` and `\r\n` all end a line.
+    pub fn feed(&mut self, byte: u8) {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            b'\n' if after_cr => {}
+            b'\r' | b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                self.end_line(&line);
+            }
+            byte => {
+                let limit = match self.mode {
+                    Mode::Command => MAX_COMMAND_LINE,
+                    // one more than fits, so an overlong line is noticed
+                    Mode::Cat { .. } => self.cat_capacity + 1,
+                };
+                if self.line.len() < limit {
+                    self.line.push(byte);
+                }
+            }
+        }
+    }
+
+    fn end_line(&mut self, line: &[u8]) {
+        match &mut self.mode {
+            Mode::Command => self.run_command(line),
+            Mode::Cat { buffer, truncated } if line != CAT_SENTINEL => {
+                let room = self.cat_capacity - buffer.len();
+                buffer.extend_from_slice(&line[..line.len().min(room)]);
+                if buffer.len() < self.cat_capacity {
+                    buffer.push(b'\n');
+                } else {
+                    *truncated = true;
+                }
+            }
+            Mode::Cat { buffer, truncated } => {
+                let _ = self.out.write_str(&String::from_utf8_lossy(buffer));
+                if *truncated {
+                    let _ = writeln!(
+                        self.out,
+                        "WARNING: input truncated to {} bytes",
+                        self.cat_capacity
+                    );
+                }
+                self.mode = Mode::Command;
+                self.prompt();
+            }
+        }
+    }
+
+    fn run_command(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        match line.trim() {
+            "" => {}
+            "cat" | "dump" => {
+                let _ = writeln!(self.out, "reading input up to a line with a single '.'");
+                self.mode = Mode::Cat {
+                    buffer: Vec::new(),
+                    truncated: false,
+                };
+                return;
+            }
+            "help" => {
+                let _ = writeln!(self.out, "commands: cat, dump, help");
+            }
+            command => {
+                let _ = writeln!(self.out, "unknown command: {}", command);
+            }
+        }
+        self.prompt();
+    }
+}
+
+/// Writes to the first serial port.
+struct SerialOut;
+
+impl Write for SerialOut {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        crate::serial_print!("{}", s);
+        Ok(())
+    }
+}
+
+/// Runs the shell on the first serial port.
+pub async fn run() {
+    let mut input = SerialStream::new();
+    let mut shell = Shell::new(SerialOut);
+    shell.prompt();
+    while let Some(byte) = input.next().await {
+        shell.feed(byte);
+    }
+}
+
+#[cfg(test)]
+fn feed_str<W: Write>(shell: &mut Shell<W>, input: &str) {
+    for byte in input.bytes() {
+        shell.feed(byte);
+    }
+}
+
+#[test_case]
+fn test_cat_echoes_block() {
+    let mut shell = Shell::new(String::new());
+    feed_str(&mut shell, "cat\r\nhello\r\n\r\nworld\r\n.\r\n");
+    assert_eq!(
+        shell.output(),
+        "reading input up to a line with a single '.'\nhello\n\nworld\n> "
+    );
+}
+
+#[test_case]
+fn test_cat_truncates_full_buffer() {
+    let mut shell = Shell::with_cat_capacity(String::new(), 8);
+    feed_str(&mut shell, "dump\nabcdef\nghijkl\n.\nhelp\n");
+    let output = shell.output();
+    assert!(output.contains("abcdef\ng"));
+    assert!(!output.contains("gh"));
+    assert!(output.contains("WARNING: input truncated to 8 bytes\n> commands:"));
+}
+
+#[test_case]
+fn test_shell_reads_serial_input() {
+    use futures_util::FutureExt;
+    use x86_64::instructions::interrupts;
+
+    let mut input = SerialStream::new();
+    interrupts::without_interrupts(|| {
+        for byte in b"cat\nfrom serial\n.\n" {
+            super::serial::add_byte(*byte);
+        }
+    });
+
+    let mut shell = Shell::new(String::new());
+    while let Some(Some(byte)) = input.next().now_or_never() {
+        shell.feed(byte);
+    }
+    assert!(shell.output().ends_with("from serial\n> "));
+}
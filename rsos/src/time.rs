@@ -0,0 +1,38 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Input clock of the programmable interval timer.
+pub const PIT_FREQUENCY_HZ: u64 = 1_193_182;
+/// Divisor the PIT runs with after boot (the BIOS default of 65536).
+pub const PIT_DIVISOR: u64 = 65536;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Called from the timer interrupt handler.
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of timer interrupts received since boot.
+pub fn uptime_ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Milliseconds since boot, derived from the timer tick frequency.
+pub fn uptime_ms() -> u64 {
+    uptime_ticks() * PIT_DIVISOR * 1000 / PIT_FREQUENCY_HZ
+}
+
+#[test_case]
+fn test_uptime_advances() {
+    let start = uptime_ticks();
+    for _ in 0..100 {
+        if uptime_ticks() > start {
+            break;
+        }
+        x86_64::instructions::hlt();
+    }
+    let ticks = uptime_ticks();
+    assert!(ticks > start);
+    // The clock may tick again before `uptime_ms` reads it, but never goes back
+    assert!(uptime_ms() >= ticks * PIT_DIVISOR * 1000 / PIT_FREQUENCY_HZ);
+}
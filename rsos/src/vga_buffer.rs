@@ -1,14 +1,11 @@
 use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use volatile::Volatile;
 
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColoerCode::new(Color::Yellow, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    });
+    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer::new());
 }
 
 #[allow(dead_code)]
@@ -38,11 +35,34 @@ pub enum Color {
 struct ColoerCode(u8);
 
 impl ColoerCode {
-    fn new(foreground: Color, background: Color) -> ColoerCode {
+    const fn new(foreground: Color, background: Color) -> ColoerCode {
         ColoerCode((background as u8) << 4 | (foreground as u8))
     }
+
+    fn with_foreground(self, foreground: u8) -> ColoerCode {
+        ColoerCode((self.0 & 0xf0) | (foreground & 0x0f))
+    }
+
+    fn with_background(self, background: u8) -> ColoerCode {
+        ColoerCode((background & 0x0f) << 4 | (self.0 & 0x0f))
+    }
 }
 
+const DEFAULT_COLOR_CODE: ColoerCode = ColoerCode::new(Color::Yellow, Color::Black);
+const STATUS_COLOR_CODE: ColoerCode = ColoerCode::new(Color::Black, Color::LightGray);
+
+/// VGA color indices in ANSI order (black, red, green, yellow, blue, magenta, cyan, white).
+const ANSI_COLORS: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightGray,
+];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 struct ScreenChar {
@@ -50,6 +70,11 @@ struct ScreenChar {
     color_code: ColoerCode,
 }
 
+const BLANK: ScreenChar = ScreenChar {
+    ascii_character: b' ',
+    color_code: DEFAULT_COLOR_CODE,
+};
+
 const BUFFER_HEIGHT: usize = 25;
 const BUFFER_WIDTH: usize = 80;
 
@@ -58,14 +83,201 @@ struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+/// A screen row kept out of scrolling for `Writer::set_status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusLine {
+    Top,
+    Bottom,
+}
+
+const ANSI_MAX_PARAMS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AnsiState {
+    Ground,
+    Escape,
+    Csi,
+}
+
+/// Parser state for ANSI escape sequences, kept across `write_str` calls
+/// so that a sequence split over several writes is still recognized.
+struct AnsiParser {
+    state: AnsiState,
+    params: [u16; ANSI_MAX_PARAMS],
+    param_count: usize,
+}
+
+impl AnsiParser {
+    const fn new() -> AnsiParser {
+        AnsiParser {
+            state: AnsiState::Ground,
+            params: [0; ANSI_MAX_PARAMS],
+            param_count: 0,
+        }
+    }
+
+    fn start_csi(&mut self) {
+        self.state = AnsiState::Csi;
+        self.params = [0; ANSI_MAX_PARAMS];
+        self.param_count = 0;
+    }
+
+    fn push_digit(&mut self, digit: u8) {
+        if self.param_count == 0 {
+            self.param_count = 1;
+        }
+        if let Some(param) = self.params.get_mut(self.param_count - 1) {
+            *param = param.saturating_mul(10).saturating_add(u16::from(digit));
+        }
+    }
+
+    fn next_param(&mut self) {
+        if self.param_count == 0 {
+            self.param_count = 1;
+        }
+        self.param_count += 1;
+    }
+
+    fn params(&self) -> &[u16] {
+        &self.params[..self.param_count.min(ANSI_MAX_PARAMS)]
+    }
+}
+
+/// Writes go to an off-screen shadow copy of the screen and only rows that changed
+/// are copied to VGA memory on `flush`, so scrolling and redraws don't tear.
 pub struct Writer {
+    row_position: usize,
     column_position: usize,
     color_code: ColoerCode,
     buffer: &'static mut Buffer,
+    shadow: [[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    dirty: [bool; BUFFER_HEIGHT],
+    auto_flush: bool,
+    ansi: AnsiParser,
+    status: Option<StatusLine>,
 }
 
 impl Writer {
+    /// Takes over the VGA text buffer, keeping what is already on screen.
+    fn new() -> Writer {
+        let buffer = unsafe { &mut *(0xb8000 as *mut Buffer) };
+        let mut shadow = [[BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        for (row, cells) in shadow.iter_mut().enumerate() {
+            for (col, cell) in cells.iter_mut().enumerate() {
+                *cell = buffer.chars[row][col].read();
+            }
+        }
+        Writer {
+            row_position: BUFFER_HEIGHT - 1,
+            column_position: 0,
+            color_code: DEFAULT_COLOR_CODE,
+            buffer,
+            shadow,
+            dirty: [false; BUFFER_HEIGHT],
+            auto_flush: true,
+            ansi: AnsiParser::new(),
+            status: None,
+        }
+    }
+
+    /// Writes one byte; a newline also flushes when auto-flush is on.
     pub fn write_byte(&mut self, byte: u8) {
+        self.put_byte(byte);
+        if byte == b'\n' && self.auto_flush {
+            self.flush();
+        }
+    }
+
+    /// Writes `s`, then flushes once when auto-flush is on, so a multi-line
+    /// string reaches the screen in a single pass however far it scrolls.
+    pub fn write_string(&mut self, s: &str) {
+        for byte in s.bytes() {
+            self.process_byte(byte);
+        }
+        if self.auto_flush {
+            self.flush();
+        }
+        self.update_cursor();
+    }
+
+    /// Copies the rows changed since the last flush to VGA memory.
+    pub fn flush(&mut self) {
+        for row in 0..BUFFER_HEIGHT {
+            if !core::mem::replace(&mut self.dirty[row], false) {
+                continue;
+            }
+            for col in 0..BUFFER_WIDTH {
+                self.buffer.chars[row][col].write(self.shadow[row][col]);
+            }
+        }
+    }
+
+    /// With auto-flush on (the default) every `write_string`, newline and clear
+    /// reaches the screen right away; with it off, nothing does until `flush`.
+    pub fn set_auto_flush(&mut self, auto_flush: bool) {
+        self.auto_flush = auto_flush;
+    }
+
+    /// Reserves a row as a status line that printing and scrolling never touch,
+    /// or gives it back to the scrolling region with `None`. The row starts blank.
+    pub fn reserve_status_line(&mut self, status: Option<StatusLine>) {
+        self.status = status;
+        if let Some(row) = self.status_row() {
+            self.shadow[row] = [ScreenChar {
+                ascii_character: b' ',
+                color_code: STATUS_COLOR_CODE,
+            }; BUFFER_WIDTH];
+            self.dirty[row] = true;
+        }
+        let (first, last) = self.scroll_region();
+        self.row_position = self.row_position.clamp(first, last);
+        if self.auto_flush {
+            self.flush();
+        }
+        self.update_cursor();
+    }
+
+    /// Replaces the text of the reserved status line, truncated to the screen width.
+    /// Does nothing unless a row was reserved with `reserve_status_line`.
+    pub fn set_status(&mut self, status: &str) {
+        let Some(row) = self.status_row() else {
+            return;
+        };
+        let mut bytes = status.bytes();
+        for cell in self.shadow[row].iter_mut() {
+            let ascii_character = match bytes.next() {
+                Some(byte @ 0x20..0x7e) => byte,
+                Some(_) => 0xfe,
+                None => b' ',
+            };
+            *cell = ScreenChar {
+                ascii_character,
+                color_code: STATUS_COLOR_CODE,
+            };
+        }
+        self.dirty[row] = true;
+        if self.auto_flush {
+            self.flush();
+        }
+    }
+
+    fn status_row(&self) -> Option<usize> {
+        match self.status? {
+            StatusLine::Top => Some(0),
+            StatusLine::Bottom => Some(BUFFER_HEIGHT - 1),
+        }
+    }
+
+    /// The first and last rows that printing uses and scrolling moves.
+    fn scroll_region(&self) -> (usize, usize) {
+        match self.status {
+            None => (0, BUFFER_HEIGHT - 1),
+            Some(StatusLine::Top) => (1, BUFFER_HEIGHT - 1),
+            Some(StatusLine::Bottom) => (0, BUFFER_HEIGHT - 2),
+        }
+    }
+
+    fn put_byte(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
             byte => {
@@ -73,36 +285,146 @@ impl Writer {
                     self.new_line();
                 }
 
-                let row = BUFFER_HEIGHT - 1;
+                let row = self.row_position;
                 let col = self.column_position;
 
-                let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
+                self.shadow[row][col] = ScreenChar {
                     ascii_character: byte,
-                    color_code,
-                });
+                    color_code: self.color_code,
+                };
+                self.dirty[row] = true;
                 self.column_position += 1;
             }
         }
     }
 
-    pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                0x20..0x7e | b'\n' => self.write_byte(byte),
-                _ => self.write_byte(0xfe),
+    /// Moves the write position, clamping it to the buffer dimensions and keeping
+    /// it off a reserved status line.
+    pub fn goto(&mut self, row: usize, col: usize) {
+        let (first, last) = self.scroll_region();
+        self.row_position = row.clamp(first, last);
+        self.column_position = col.min(BUFFER_WIDTH - 1);
+        self.update_cursor();
+    }
+
+    /// The current write position as (row, column).
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row_position, self.column_position)
+    }
+
+    /// Writes `s` starting at the given position and leaves the write position after it.
+    pub fn write_at(&mut self, row: usize, col: usize, s: &str) {
+        self.goto(row, col);
+        self.write_string(s);
+    }
+
+    /// Writes the characters shown on `row`, without trailing blanks, to `out`.
+    pub fn read_row(&self, row: usize, out: &mut impl fmt::Write) -> fmt::Result {
+        let chars = &self.shadow[row.min(BUFFER_HEIGHT - 1)];
+        let len = chars
+            .iter()
+            .rposition(|c| c.ascii_character != b' ')
+            .map_or(0, |last| last + 1);
+        for c in &chars[..len] {
+            out.write_char(char::from(c.ascii_character))?;
+        }
+        Ok(())
+    }
+
+    /// Moves the blinking hardware cursor to the current write position.
+    fn update_cursor(&self) {
+        use x86_64::instructions::port::Port;
+
+        let col = self.column_position.min(BUFFER_WIDTH - 1);
+        let position = (self.row_position * BUFFER_WIDTH + col) as u16;
+        let mut index: Port<u8> = Port::new(0x3d4);
+        let mut data: Port<u8> = Port::new(0x3d5);
+        unsafe {
+            index.write(0x0f);
+            data.write(position as u8);
+            index.write(0x0e);
+            data.write((position >> 8) as u8);
+        }
+    }
+
+    fn process_byte(&mut self, byte: u8) {
+        match self.ansi.state {
+            AnsiState::Ground => match byte {
+                0x1b => self.ansi.state = AnsiState::Escape,
+                0x20..0x7e | b'\n' => self.put_byte(byte),
+                _ => self.put_byte(0xfe),
+            },
+            AnsiState::Escape => match byte {
+                b'[' => self.ansi.start_csi(),
+                // other two-byte sequences are swallowed
+                _ => self.ansi.state = AnsiState::Ground,
+            },
+            AnsiState::Csi => match byte {
+                b'0'..=b'9' => self.ansi.push_digit(byte - b'0'),
+                b';' => self.ansi.next_param(),
+                // final byte terminates the sequence
+                0x40..=0x7e => {
+                    self.ansi.state = AnsiState::Ground;
+                    self.execute_csi(byte);
+                }
+                // intermediate and private bytes are ignored
+                _ => {}
+            },
+        }
+    }
+
+    fn execute_csi(&mut self, command: u8) {
+        match command {
+            b'm' => {
+                if self.ansi.params().is_empty() {
+                    self.select_graphic_rendition(0);
+                }
+                for i in 0..self.ansi.params().len() {
+                    self.select_graphic_rendition(self.ansi.params()[i]);
+                }
             }
+            b'J' if self.ansi.params() == [2] => self.clear_screen(),
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, param: u16) {
+        let code = self.color_code;
+        self.color_code = match param {
+            0 => DEFAULT_COLOR_CODE,
+            30..=37 => code.with_foreground(ANSI_COLORS[usize::from(param - 30)] as u8),
+            39 => code.with_foreground(DEFAULT_COLOR_CODE.0),
+            40..=47 => code.with_background(ANSI_COLORS[usize::from(param - 40)] as u8),
+            49 => code.with_background(DEFAULT_COLOR_CODE.0 >> 4),
+            90..=97 => code.with_foreground(ANSI_COLORS[usize::from(param - 90)] as u8 | 0x8),
+            100..=107 => code.with_background(ANSI_COLORS[usize::from(param - 100)] as u8 | 0x8),
+            _ => code,
+        };
+    }
+
+    /// Clears every row but a reserved status line.
+    pub fn clear_screen(&mut self) {
+        let (first, last) = self.scroll_region();
+        for row in first..=last {
+            self.clear_row(row);
+        }
+        self.column_position = 0;
+        if self.auto_flush {
+            self.flush();
         }
     }
 
     fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
-            }
+        let (first, last) = self.scroll_region();
+        if self.row_position < last {
+            self.row_position += 1;
+            self.column_position = 0;
+            return;
         }
-        self.clear_row(BUFFER_HEIGHT - 1);
+
+        self.shadow.copy_within(first + 1..=last, first);
+        self.dirty[first..=last].fill(true);
+        self.clear_row(last);
         self.column_position = 0;
     }
 
@@ -111,9 +433,8 @@ impl Writer {
             ascii_character: b' ',
             color_code: self.color_code,
         };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
-        }
+        self.shadow[row] = [blank; BUFFER_WIDTH];
+        self.dirty[row] = true;
     }
 }
 
@@ -144,13 +465,67 @@ pub fn _print(args: fmt::Arguments) {
     });
 }
 
+/// Like `print!`, but safe to use in interrupt handlers: if the interrupted code holds
+/// the writer lock, the message is dropped instead of spinning forever. Evaluates to
+/// whether the message was written.
+#[macro_export]
+macro_rules! try_print {
+    ($($arg:tt)*) => ($crate::vga_buffer::_try_print(format_args!($($arg)*)));
+}
+
+/// Interrupt-safe `println!`; see `try_print!`.
+#[macro_export]
+macro_rules! try_println {
+    () => ($crate::try_print!("\n"));
+    ($($arg:tt)*) => ($crate::try_print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Prints a `WARNING:` line unless the boot `log_level` is `error`.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        if $crate::config::enabled($crate::config::LogLevel::Warn) {
+            $crate::println!("WARNING: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+/// Interrupt-safe `warn!`; see `try_print!`.
+#[macro_export]
+macro_rules! try_warn {
+    ($($arg:tt)*) => {
+        if $crate::config::enabled($crate::config::LogLevel::Warn) {
+            $crate::try_println!("WARNING: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+static DROPPED_MESSAGES: AtomicU64 = AtomicU64::new(0);
+
+#[doc(hidden)]
+pub fn _try_print(args: fmt::Arguments) -> bool {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+    interrupts::without_interrupts(|| match WRITER.try_lock() {
+        Some(mut writer) => {
+            writer.write_fmt(args).unwrap();
+            true
+        }
+        None => {
+            DROPPED_MESSAGES.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    })
+}
+
+/// Number of `try_print!` messages dropped because the writer was busy.
+pub fn dropped_messages() -> u64 {
+    DROPPED_MESSAGES.load(Ordering::Relaxed)
+}
+
 pub fn print_something() {
     use core::fmt::Write;
-    let mut writer = Writer {
-        column_position: 0,
-        color_code: ColoerCode::new(Color::Yellow, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    };
+    let mut writer = Writer::new();
 
     writer.write_byte(b'H');
     writer.write_string("ello ");
@@ -185,3 +560,135 @@ fn test_println_output() {
         }
     });
 }
+
+#[test_case]
+fn test_ansi_color_sequence() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\n\x1b[31mab\x1b[0mc\n").expect("write failed");
+        let red = ColoerCode::new(Color::Red, Color::Black);
+        let row = &writer.buffer.chars[BUFFER_HEIGHT - 2];
+        for (i, c) in "abc".chars().enumerate() {
+            let screen_char = row[i].read();
+            assert_eq!(char::from(screen_char.ascii_character), c);
+        }
+        assert_eq!(row[0].read().color_code, red);
+        assert_eq!(row[1].read().color_code, red);
+        assert_eq!(row[2].read().color_code, DEFAULT_COLOR_CODE);
+    });
+}
+
+#[test_case]
+fn test_write_at() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.write_at(3, 5, "xyz");
+        for (i, c) in "xyz".chars().enumerate() {
+            let screen_char = writer.buffer.chars[3][5 + i].read();
+            assert_eq!(char::from(screen_char.ascii_character), c);
+        }
+
+        writer.write_at(100, 200, "!");
+        let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 1][BUFFER_WIDTH - 1].read();
+        assert_eq!(screen_char.ascii_character, b'!');
+
+        writer.write_byte(b'\n');
+    });
+}
+
+#[test_case]
+fn test_try_print_while_locked() {
+    use x86_64::instructions::interrupts;
+
+    let dropped = dropped_messages();
+    interrupts::without_interrupts(|| {
+        let _guard = WRITER.lock();
+        // The breakpoint handler logs with `try_println!`, as if it interrupted a `println!`
+        interrupts::int3();
+        assert!(!try_println!("dropped while locked"));
+    });
+    assert_eq!(dropped_messages(), dropped + 2);
+
+    assert!(try_println!("written once unlocked"));
+    println!("normal output still works");
+    assert_eq!(dropped_messages(), dropped + 2);
+}
+
+#[test_case]
+fn test_shadow_buffer_flush() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.set_auto_flush(false);
+        write!(writer, "\nshadow one\nshadow two\x1b[32m green").expect("write failed");
+        let row = BUFFER_HEIGHT - 1;
+        // nothing reaches the screen before the flush
+        assert_ne!(writer.buffer.chars[row][0].read(), writer.shadow[row][0]);
+
+        writer.flush();
+        writer.set_auto_flush(true);
+        for row in 0..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                assert_eq!(
+                    writer.buffer.chars[row][col].read(),
+                    writer.shadow[row][col]
+                );
+            }
+        }
+        assert_eq!(writer.shadow[row][0].ascii_character, b's');
+        assert_eq!(writer.shadow[BUFFER_HEIGHT - 2][7].ascii_character, b'o');
+        assert!(writer.dirty.iter().all(|&dirty| !dirty));
+        writer.write_string("\x1b[0m\n");
+    });
+}
+
+#[test_case]
+fn test_status_line_survives_scrolling() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        for status in [StatusLine::Bottom, StatusLine::Top] {
+            writer.reserve_status_line(Some(status));
+            writer.set_status("status: ok");
+            let status_row = writer.status_row().unwrap();
+            let (_, last) = writer.scroll_region();
+
+            for i in 0..BUFFER_HEIGHT * 2 {
+                writeln!(writer, "scrolled line {}", i).expect("writeln failed");
+            }
+            write!(writer, "last").expect("write failed");
+
+            for (i, c) in "status: ok".chars().enumerate() {
+                let screen_char = writer.buffer.chars[status_row][i].read();
+                assert_eq!(char::from(screen_char.ascii_character), c);
+                assert_eq!(screen_char.color_code, STATUS_COLOR_CODE);
+            }
+            assert_eq!(writer.buffer.chars[last][0].read().ascii_character, b'l');
+            assert_eq!(
+                writer.buffer.chars[last - 1][14].read().ascii_character,
+                b'4'
+            );
+
+            writer.set_status("updated");
+            assert_eq!(
+                writer.buffer.chars[status_row][0].read().ascii_character,
+                b'u'
+            );
+            assert_eq!(
+                writer.buffer.chars[status_row][7].read().ascii_character,
+                b' '
+            );
+        }
+        writer.reserve_status_line(None);
+        writer.write_byte(b'\n');
+    });
+}
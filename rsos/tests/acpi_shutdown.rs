@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rsos::{acpi, exit_qemu, serial_print, serial_println, QemuExitCode};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+/// A successful soft-off makes QEMU exit with status 0, which the test
+/// runner passes through as success; reaching any exit below means it failed.
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("acpi_shutdown::acpi_shutdown...\t");
+
+    rsos::init();
+    if let Err(err) = acpi::init(VirtAddr::new(boot_info.physical_memory_offset)) {
+        serial_println!("[skipped: {:?}]", err);
+        exit_qemu(QemuExitCode::Success);
+        rsos::hlt_loop();
+    }
+
+    // flush the expected output before the machine goes away
+    serial_println!("[ok]");
+    let result = acpi::soft_off();
+
+    serial_println!("[failed]\n");
+    serial_println!(
+        "Error: machine still running after soft-off: {:?}\n",
+        result
+    );
+    exit_qemu(QemuExitCode::Failed);
+    rsos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rsos::test_panic_handler(info)
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rsos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rsos::memory::{self, BootInfoFrameAllocator};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rsos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    rsos::apic::init(&mut mapper, &mut frame_allocator).expect("APIC initialization failed");
+
+    test_main();
+    rsos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rsos::test_panic_handler(info)
+}
+
+#[test_case]
+fn apic_is_enabled() {
+    assert!(rsos::apic::is_enabled());
+}
+
+#[test_case]
+fn timer_fires_under_apic() {
+    let start = rsos::time::uptime_ticks();
+    for _ in 0..100 {
+        if rsos::time::uptime_ticks() >= start + 3 {
+            break;
+        }
+        x86_64::instructions::hlt();
+    }
+    assert!(rsos::time::uptime_ticks() >= start + 3);
+}
@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rsos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    rsos::init();
+    test_main();
+
+    rsos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rsos::test_panic_handler(info)
+}
+
+#[test_case]
+fn breakpoint_returns() {
+    static REACHED: AtomicUsize = AtomicUsize::new(0);
+
+    x86_64::instructions::interrupts::int3();
+    REACHED.fetch_add(1, Ordering::SeqCst);
+    assert_eq!(REACHED.load(Ordering::SeqCst), 1);
+}
+
+#[test_case]
+fn repeated_breakpoints() {
+    let mut count = 0;
+    for _ in 0..10 {
+        x86_64::instructions::interrupts::int3();
+        count += 1;
+    }
+    assert_eq!(count, 10);
+}
@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rsos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rsos::memory::{self, BootInfoFrameAllocator, KERNEL_MEMORY};
+use x86_64::{
+    structures::paging::{
+        mapper::{MappedFrame, TranslateResult},
+        FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Translate,
+    },
+    VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rsos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    rsos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rsos::test_panic_handler(info)
+}
+
+fn read_frame(frame: PhysFrame) -> u64 {
+    let memory = KERNEL_MEMORY.lock();
+    let offset = memory.as_ref().unwrap().mapper.phys_offset();
+    let ptr: *const u64 = (offset + frame.start_address().as_u64()).as_ptr();
+    unsafe { ptr.read_volatile() }
+}
+
+fn mapped_frame(page: Page) -> PhysFrame {
+    let memory = KERNEL_MEMORY.lock();
+    match memory
+        .as_ref()
+        .unwrap()
+        .mapper
+        .translate(page.start_address())
+    {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            ..
+        } => frame,
+        other => panic!("page not mapped: {:?}", other),
+    }
+}
+
+#[test_case]
+fn write_copies_frame() {
+    let page = Page::containing_address(VirtAddr::new(0x_6666_0000_0000));
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+
+    let original = {
+        let mut memory = KERNEL_MEMORY.lock();
+        let memory = memory.as_mut().unwrap();
+        let frame = memory.frame_allocator.allocate_frame().unwrap();
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        unsafe {
+            memory
+                .mapper
+                .map_to(page, frame, flags, &mut memory.frame_allocator)
+                .unwrap()
+                .flush();
+            ptr.write_volatile(0x1111);
+        }
+        memory::mark_copy_on_write(page, &mut memory.mapper)
+            .unwrap()
+            .flush();
+        frame
+    };
+
+    // the lock is released, so the page fault handler can resolve this write
+    unsafe { ptr.write_volatile(0x2222) };
+
+    let copy = mapped_frame(page);
+    assert_ne!(copy, original);
+    assert_eq!(read_frame(original), 0x1111);
+    assert_eq!(read_frame(copy), 0x2222);
+    assert_eq!(unsafe { ptr.read_volatile() }, 0x2222);
+}
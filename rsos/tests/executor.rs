@@ -0,0 +1,210 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rsos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{rc::Rc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::cell::RefCell;
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use rsos::task::{executor::Executor, Task, TaskLocal};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rsos::allocator;
+    use rsos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rsos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    rsos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rsos::test_panic_handler(info)
+}
+
+/// Returns `Pending` once after waking itself, so other tasks get to run.
+struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.yielded {
+            Poll::Ready(())
+        } else {
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+static COUNTER: TaskLocal<u32> = TaskLocal::new(|| 0);
+
+#[test_case]
+fn task_locals_are_separate() {
+    let results = Rc::new(RefCell::new(Vec::new()));
+    let mut executor = Executor::new();
+
+    for step in [1, 10] {
+        let results = results.clone();
+        executor.spawn(Task::new(async move {
+            for _ in 0..3 {
+                COUNTER.with(|counter| *counter += step);
+                yield_now().await;
+            }
+            results.borrow_mut().push(COUNTER.with(|counter| *counter));
+        }));
+    }
+    executor.run_until_idle();
+
+    let mut results = results.borrow().clone();
+    results.sort();
+    assert_eq!(results, [3, 30]);
+}
+
+#[test_case]
+fn task_local_outside_task() {
+    assert_eq!(
+        COUNTER.try_with(|counter| *counter),
+        Err(rsos::task::local::AccessError::NoTask)
+    );
+}
+
+#[test_case]
+fn metrics_count_activity() {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {}));
+    executor.spawn(Task::new(async {
+        yield_now().await;
+    }));
+
+    let metrics = executor.metrics();
+    assert_eq!(metrics.spawned, 2);
+    assert_eq!(metrics.pending, 2);
+    assert_eq!(metrics.polls, 0);
+
+    executor.run_until_idle();
+
+    let metrics = executor.metrics();
+    assert_eq!(metrics.spawned, 2);
+    assert_eq!(metrics.pending, 0);
+    // the yielding task is polled twice
+    assert_eq!(metrics.polls, 3);
+    assert_eq!(metrics.wakes, 1);
+}
+
+#[test_case]
+fn run_until_idle_returns() {
+    let done = Rc::new(RefCell::new(0));
+    let mut executor = Executor::new();
+    for _ in 0..3 {
+        let done = done.clone();
+        executor.spawn(Task::new(async move {
+            *done.borrow_mut() += 1;
+        }));
+    }
+
+    executor.run_until_idle();
+    assert_eq!(*done.borrow(), 3);
+    assert_eq!(executor.metrics().pending, 0);
+}
+
+#[test_case]
+fn poll_ready_is_a_single_pass() {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        yield_now().await;
+    }));
+
+    // the first pass leaves the task woken but pending
+    assert_eq!(executor.poll_ready(), 1);
+    assert_eq!(executor.metrics().pending, 1);
+    assert_eq!(executor.poll_ready(), 1);
+    assert_eq!(executor.metrics().pending, 0);
+    assert_eq!(executor.poll_ready(), 0);
+}
+
+/// Returns `Pending` forever without registering for a wakeup.
+struct ParkForever;
+
+impl Future for ParkForever {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<()> {
+        Poll::Pending
+    }
+}
+
+#[test_case]
+fn watchdog_reports_lost_wakeup() {
+    use rsos::time;
+
+    let mut executor = Executor::new();
+    executor.set_watchdog(Some(2));
+    executor.spawn(Task::new(ParkForever));
+    executor.run_until_idle();
+    let idle_since = time::uptime_ticks();
+
+    // nothing will wake the task, so only the timer moves things along
+    assert!(!executor.check_watchdog());
+    let mut fired = false;
+    for _ in 0..20 {
+        x86_64::instructions::hlt();
+        if executor.check_watchdog() {
+            fired = true;
+            break;
+        }
+    }
+    assert!(fired);
+    assert!(time::uptime_ticks() - idle_since >= 2);
+    // one warning per stall
+    assert!(!executor.check_watchdog());
+}
+
+#[test_case]
+fn step_polls_one_task_at_a_time() {
+    let mut executor = Executor::new();
+    let tasks = [
+        Task::new(async {
+            yield_now().await;
+        }),
+        Task::new(async {}),
+        Task::new(async {
+            yield_now().await;
+        }),
+    ];
+    let ids: Vec<_> = tasks.iter().map(Task::id).collect();
+    for task in tasks {
+        executor.spawn(task);
+    }
+
+    // spawn order first, then the yielding tasks in the order they woke
+    let expected = [ids[0], ids[1], ids[2], ids[0], ids[2]];
+    for id in expected {
+        assert_eq!(executor.step(), Some(id));
+    }
+    assert_eq!(executor.step(), None);
+    assert_eq!(executor.metrics().pending, 0);
+}
@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use rsos::allocator::{self, HeapStats};
+use rsos::memory::{self, BootInfoFrameAllocator};
+use rsos::vga_buffer::WRITER;
+use rsos::{exit_qemu, serial_print, serial_println, MessageBuffer, QemuExitCode};
+use x86_64::VirtAddr;
+
+/// Large enough that the heap can never satisfy it.
+const REQUEST_SIZE: usize = 64 * 1024 * 1024;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("heap_exhaustion::heap_exhaustion...\t");
+
+    rsos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    let stats: HeapStats = allocator::heap_stats().unwrap();
+    assert!(stats.size < REQUEST_SIZE && stats.used <= stats.size);
+
+    let buffer: Vec<u8> = Vec::with_capacity(REQUEST_SIZE);
+    core::hint::black_box(&buffer);
+
+    serial_println!("[allocation unexpectedly succeeded]");
+    exit_qemu(QemuExitCode::Failed);
+    rsos::hlt_loop();
+}
+
+/// The allocation error handler reports the failed request before it panics; check
+/// the report it left on screen rather than the panic message.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut reported = MessageBuffer::<80>::new();
+    if let Some(writer) = WRITER.try_lock() {
+        let _ = writer.read_row(writer.cursor().0 - 1, &mut reported);
+    }
+
+    let mut expected = MessageBuffer::<80>::new();
+    if let Some(stats) = allocator::heap_stats() {
+        let _ = write!(
+            expected,
+            "ALLOCATION ERROR: size {} align 1; heap {} of {} bytes used",
+            REQUEST_SIZE, stats.used, stats.size
+        );
+    }
+
+    if !expected.as_str().is_empty() && reported.as_str() == expected.as_str() {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Expected: {}", expected.as_str());
+        serial_println!("Reported: {}", reported.as_str());
+        serial_println!("Error: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rsos::hlt_loop();
+}
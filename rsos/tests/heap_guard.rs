@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use rsos::allocator;
+use rsos::memory::{self, BootInfoFrameAllocator};
+use rsos::{exit_qemu, serial_print, serial_println, MessageBuffer, QemuExitCode};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("heap_guard::overrun_detected_on_free...\t");
+
+    if !cfg!(debug_assertions) {
+        serial_println!("[skipped: guard bytes are only checked in debug builds]");
+        exit_qemu(QemuExitCode::Success);
+        rsos::hlt_loop();
+    }
+
+    rsos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    let mut buffer = Box::new([0u8; 16]);
+    // one byte past the end of the allocation
+    unsafe { buffer.as_mut_ptr().add(16).write_volatile(0xaa) };
+    drop(buffer);
+
+    serial_println!("[failed]\n");
+    serial_println!("Error: overrun was not detected\n");
+    exit_qemu(QemuExitCode::Failed);
+    rsos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = MessageBuffer::<64>::new();
+    let _ = write!(message, "{}", info.message());
+
+    if message.as_str().starts_with("heap corruption at ") {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Error: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rsos::hlt_loop();
+}
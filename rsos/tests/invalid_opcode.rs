@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use rsos::{exit_qemu, serial_print, serial_println, MessageBuffer, QemuExitCode};
+
+/// Exit code when the kernel's invalid opcode handler ran.
+const HANDLER_RAN: QemuExitCode = QemuExitCode::Success;
+/// Exit code when execution continued or a different handler panicked.
+const HANDLER_MISSING: QemuExitCode = QemuExitCode::Failed;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("invalid_opcode::invalid_opcode...\t");
+
+    rsos::init();
+    unsafe { core::arch::asm!("ud2") };
+
+    serial_println!("[execution continued after ud2]");
+    exit_qemu(HANDLER_MISSING);
+    rsos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = MessageBuffer::<64>::new();
+    let _ = write!(message, "{}", info.message());
+
+    if message.as_str().starts_with("EXCEPTION: INVALID OPCODE") {
+        serial_println!("[ok]");
+        exit_qemu(HANDLER_RAN);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Error: {}\n", info);
+        exit_qemu(HANDLER_MISSING);
+    }
+    rsos::hlt_loop();
+}
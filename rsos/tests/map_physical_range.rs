@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rsos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rsos::memory::{self, BootInfoFrameAllocator};
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{OffsetPageTable, PageTableFlags},
+    PhysAddr, VirtAddr,
+};
+
+static MEMORY: Mutex<Option<(OffsetPageTable<'static>, BootInfoFrameAllocator)>> = Mutex::new(None);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rsos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    *MEMORY.lock() = Some((mapper, frame_allocator));
+
+    test_main();
+    rsos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rsos::test_panic_handler(info)
+}
+
+// the tests map pieces of the VGA text memory, which is plain RAM to QEMU
+
+#[test_case]
+fn map_unaligned_range() {
+    let mut memory = MEMORY.lock();
+    let (mapper, frame_allocator) = memory.as_mut().unwrap();
+
+    let phys = PhysAddr::new(0xb8f10);
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+    let (virt, flush) = memory::map_physical_range(phys, 0x20, flags, mapper, frame_allocator)
+        .expect("map_physical_range failed");
+    flush.flush();
+    assert_eq!(virt.as_u64() % 4096, 0xf10);
+
+    let ptr: *mut u16 = virt.as_mut_ptr();
+    let offset_ptr: *const u16 = (mapper.phys_offset() + phys.as_u64()).as_ptr();
+    unsafe {
+        ptr.write_volatile(0x0f21);
+        assert_eq!(offset_ptr.read_volatile(), 0x0f21);
+    }
+}
+
+#[test_case]
+fn map_range_spanning_pages() {
+    let mut memory = MEMORY.lock();
+    let (mapper, frame_allocator) = memory.as_mut().unwrap();
+
+    let phys = PhysAddr::new(0xb8ff8);
+    let (virt, flush) = memory::map_physical_range(
+        phys,
+        0x10,
+        PageTableFlags::WRITABLE,
+        mapper,
+        frame_allocator,
+    )
+    .expect("map_physical_range failed");
+    flush.flush();
+
+    let ptr: *mut u64 = (virt + 8u64).as_mut_ptr();
+    let offset_ptr: *const u64 = (mapper.phys_offset() + 0xb9000u64).as_ptr();
+    unsafe {
+        ptr.write_volatile(0x_0f42_0f41_0f40_0f3f);
+        assert_eq!(offset_ptr.read_volatile(), 0x_0f42_0f41_0f40_0f3f);
+    }
+}
@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rsos::backtrace::{self, Registers};
+use rsos::memory;
+use rsos::{exit_qemu, serial_print, serial_println, MessageBuffer, QemuExitCode};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("panic_backtrace::backtrace_printed_on_panic...\t");
+
+    rsos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let _mapper = unsafe { memory::init(phys_mem_offset) };
+
+    outer();
+
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    rsos::hlt_loop();
+}
+
+#[inline(never)]
+fn outer() {
+    inner();
+}
+
+#[inline(never)]
+fn inner() {
+    panic!("expected panic");
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    let registers = Registers::capture();
+    let mut output = MessageBuffer::<2048>::new();
+    let _ = backtrace::write(&mut output, &registers);
+
+    if output
+        .as_str()
+        .lines()
+        .any(|line| line.starts_with("  #0 0x"))
+    {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Error: no backtrace addresses in:\n{}", output.as_str());
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rsos::hlt_loop();
+}
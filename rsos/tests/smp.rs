@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rsos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rsos::memory::{self, BootInfoFrameAllocator};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rsos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    rsos::apic::init(&mut mapper, &mut frame_allocator).expect("APIC initialization failed");
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    rsos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rsos::test_panic_handler(info)
+}
+
+/// QEMU runs the tests with `-smp 2`, see `test-args` in Cargo.toml.
+#[test_case]
+fn application_processors_come_online() {
+    let started = rsos::smp::boot_aps();
+    assert!(started >= 1);
+    assert_eq!(rsos::smp::online_aps(), started);
+}
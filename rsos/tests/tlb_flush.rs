@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rsos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rsos::memory::{self, BootInfoFrameAllocator, KERNEL_MEMORY};
+use x86_64::{
+    structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame},
+    VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rsos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    rsos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rsos::test_panic_handler(info)
+}
+
+fn write_frame(phys_offset: VirtAddr, frame: PhysFrame, value: u64) {
+    let ptr: *mut u64 = (phys_offset + frame.start_address().as_u64()).as_mut_ptr();
+    unsafe { ptr.write_volatile(value) };
+}
+
+#[test_case]
+fn remap_observes_new_frame() {
+    let mut memory = KERNEL_MEMORY.lock();
+    let memory = memory.as_mut().unwrap();
+    let phys_offset = memory.mapper.phys_offset();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    let page = Page::containing_address(VirtAddr::new(0x_6666_1000_0000));
+    let ptr: *const u64 = page.start_address().as_ptr();
+    let first = memory.frame_allocator.allocate_frame().unwrap();
+    let second = memory.frame_allocator.allocate_frame().unwrap();
+    write_frame(phys_offset, first, 1);
+    write_frame(phys_offset, second, 2);
+
+    unsafe {
+        memory
+            .mapper
+            .map_to(page, first, flags, &mut memory.frame_allocator)
+            .unwrap()
+            .flush();
+        // this read loads the translation into the TLB
+        assert_eq!(ptr.read_volatile(), 1);
+
+        memory::remap(
+            page,
+            second,
+            flags,
+            &mut memory.mapper,
+            &mut memory.frame_allocator,
+        )
+        .unwrap()
+        .flush();
+        assert_eq!(ptr.read_volatile(), 2);
+    }
+}
+
+/// Maps `page` to a frame holding 1, reads it so the translation is cached, remaps
+/// it to a frame holding 2 without flushing, runs `flush` and returns the value read.
+fn read_after_remap(page: Page, flush: impl FnOnce()) -> u64 {
+    let mut memory = KERNEL_MEMORY.lock();
+    let memory = memory.as_mut().unwrap();
+    let phys_offset = memory.mapper.phys_offset();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    let ptr: *const u64 = page.start_address().as_ptr();
+    let first = memory.frame_allocator.allocate_frame().unwrap();
+    let second = memory.frame_allocator.allocate_frame().unwrap();
+    write_frame(phys_offset, first, 1);
+    write_frame(phys_offset, second, 2);
+
+    unsafe {
+        memory
+            .mapper
+            .map_to(page, first, flags, &mut memory.frame_allocator)
+            .unwrap()
+            .flush();
+        assert_eq!(ptr.read_volatile(), 1);
+
+        memory::remap(
+            page,
+            second,
+            flags,
+            &mut memory.mapper,
+            &mut memory.frame_allocator,
+        )
+        .unwrap()
+        .ignore();
+        flush();
+        ptr.read_volatile()
+    }
+}
+
+#[test_case]
+fn flush_observes_new_frame() {
+    let page = Page::containing_address(VirtAddr::new(0x_6666_2000_0000));
+    assert_eq!(
+        read_after_remap(page, || memory::flush(page.start_address())),
+        2
+    );
+}
+
+#[test_case]
+fn flush_all_observes_new_frame() {
+    let page = Page::containing_address(VirtAddr::new(0x_6666_3000_0000));
+    assert_eq!(read_after_remap(page, memory::flush_all), 2);
+}
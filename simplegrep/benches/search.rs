@@ -0,0 +1,135 @@
+//! Wall-clock comparisons of matcher fast paths. Run with `cargo bench`.
+
+use sg::automaton::{NFA, NfaOptions};
+use sg::compiler::Program;
+use sg::dfa::Dfa;
+use sg::lazy_dfa::LazyDfa;
+use sg::parser::Parser;
+use sg::regex::Regex;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 20;
+
+fn time<F: FnMut() -> bool>(mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(f());
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn compile(pattern: &str) -> NFA {
+    NFA::from_regex(&Parser::new(pattern).parse().unwrap())
+}
+
+fn literal_prefix(line: &str) {
+    let mut fast = compile("foo.*bar");
+    // The line lacks the required characters too; measure the scan alone
+    fast.required = 0;
+    let mut slow = fast.clone();
+    slow.prefix.clear();
+
+    let with_prefix = time(|| fast.matches(line));
+    let without_prefix = time(|| slow.matches(line));
+    println!(
+        "literal prefix, long non-matching line: {with_prefix:?} with scan, {without_prefix:?} without"
+    );
+}
+
+fn anchored(line: &str) {
+    let line = format!("{line}foo");
+    let fast = Program::compile(
+        &Parser::new("^foo").parse().unwrap(),
+        &NfaOptions::default(),
+    );
+    let mut slow = fast.clone();
+    slow.anchored = false;
+
+    let early_out = time(|| fast.captures_from(&line, 0).is_some());
+    let every_offset = time(|| slow.captures_from(&line, 0).is_some());
+    println!(
+        "^foo, long line not starting with foo: {early_out:?} trying offset 0 only, {every_offset:?} trying every offset"
+    );
+}
+
+fn literal(line: &str) {
+    let line = format!("{line}needle{line}needle");
+    let fast = Regex::new("needle").unwrap();
+    let slow = Regex::from_nodes(
+        &[Parser::new("needle").parse().unwrap()],
+        &NfaOptions::default(),
+    );
+
+    let substring = time(|| fast.find_all(&line).len() == 2);
+    let simulated = time(|| slow.find_all(&line).len() == 2);
+    println!(
+        "needle, two matches in a long line: {substring:?} with substring search, {simulated:?} running the VM"
+    );
+}
+
+fn lazy_dfa(lines: &[String]) {
+    // A full DFA for this needs a state for every combination of the last eight characters
+    let mut nfa = compile("a[ab]{7}c");
+    // No line has a `c`, which would otherwise reject them all before either runs
+    nfa.required = 0;
+    let mut dfa = LazyDfa::new(&nfa);
+
+    let simulated = time(|| lines.iter().any(|line| nfa.matches(line)));
+    let cached = time(|| lines.iter().any(|line| dfa.is_match(line)));
+    println!(
+        "a[ab]{{7}}c over {} short lines: {simulated:?} simulating the NFA, {cached:?} with the lazy DFA ({} states)",
+        lines.len(),
+        dfa.cached_states()
+    );
+}
+
+fn dfa(lines: &[String]) {
+    let mut nfa = compile("(ab|ba)*bbb(a|b)*aaa");
+    nfa.required = 0;
+    let dfa = Dfa::new(&nfa).unwrap();
+
+    let simulated = time(|| lines.iter().filter(|line| nfa.matches(line)).count() > 0);
+    let table = time(|| lines.iter().filter(|line| dfa.is_match(line)).count() > 0);
+    println!(
+        "(ab|ba)*bbb(a|b)*aaa over {} short lines: {simulated:?} simulating the NFA, {table:?} with the DFA ({} states)",
+        lines.len(),
+        dfa.state_count()
+    );
+}
+
+fn required_chars(lines: &[String]) {
+    // No literal prefix to scan for, but no line contains the `@`
+    let fast = compile("\\w+@\\w+");
+    let mut slow = fast.clone();
+    slow.required = 0;
+
+    let filtered = time(|| lines.iter().any(|line| fast.matches(line)));
+    let unfiltered = time(|| lines.iter().any(|line| slow.matches(line)));
+    println!(
+        "\\w+@\\w+ over {} non-matching lines: {filtered:?} with required characters, {unfiltered:?} without",
+        lines.len()
+    );
+}
+
+fn main() {
+    let line = "x".repeat(1 << 20);
+    literal_prefix(&line);
+    anchored(&line);
+    literal(&line);
+
+    let mut seed = 1u32;
+    let lines: Vec<String> = (0..2000)
+        .map(|_| {
+            (0..200)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    if seed >> 16 & 1 == 0 { 'a' } else { 'b' }
+                })
+                .collect()
+        })
+        .collect();
+    lazy_dfa(&lines);
+    required_chars(&lines);
+    dfa(&lines);
+}
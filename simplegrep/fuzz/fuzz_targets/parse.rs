@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sg::parser::Parser;
+
+// Any pattern must either parse or produce an error; panics and hangs are bugs.
+// Run with `cargo +nightly fuzz run parse fuzz/corpus/parse -- -timeout=5`.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(pattern) = std::str::from_utf8(data) {
+        let _ = Parser::new(pattern).parse();
+    }
+});
@@ -0,0 +1,130 @@
+#!/usr/bin/env python3
+"""Writes src/unicode_tables.rs from the Unicode database bundled with Python.
+
+Run from the simplegrep directory: python3 scripts/unicode_tables.py
+
+General categories come straight from `unicodedata.category`. Python does not
+ship the Scripts.txt property, so each script is taken to be the characters
+whose names begin with one of its prefixes, which misses a few symbols that
+Scripts.txt assigns to a script (like U+2126 OHM SIGN in Greek).
+"""
+
+import unicodedata
+
+CATEGORIES = [
+    ("Lu", "Uppercase_Letter"),
+    ("Ll", "Lowercase_Letter"),
+    ("Lt", "Titlecase_Letter"),
+    ("Lm", "Modifier_Letter"),
+    ("Lo", "Other_Letter"),
+    ("Mn", "Nonspacing_Mark"),
+    ("Mc", "Spacing_Mark"),
+    ("Me", "Enclosing_Mark"),
+    ("Nd", "Decimal_Number"),
+    ("Nl", "Letter_Number"),
+    ("No", "Other_Number"),
+    ("Pc", "Connector_Punctuation"),
+    ("Pd", "Dash_Punctuation"),
+    ("Ps", "Open_Punctuation"),
+    ("Pe", "Close_Punctuation"),
+    ("Pi", "Initial_Punctuation"),
+    ("Pf", "Final_Punctuation"),
+    ("Po", "Other_Punctuation"),
+    ("Sm", "Math_Symbol"),
+    ("Sc", "Currency_Symbol"),
+    ("Sk", "Modifier_Symbol"),
+    ("So", "Other_Symbol"),
+    ("Zs", "Space_Separator"),
+    ("Zl", "Line_Separator"),
+    ("Zp", "Paragraph_Separator"),
+    ("Cc", "Control"),
+    ("Cf", "Format"),
+    ("Co", "Private_Use"),
+]
+
+SCRIPTS = [
+    ("Arabic", ["ARABIC "]),
+    ("Armenian", ["ARMENIAN "]),
+    ("Bengali", ["BENGALI "]),
+    ("Cyrillic", ["CYRILLIC "]),
+    ("Devanagari", ["DEVANAGARI "]),
+    ("Georgian", ["GEORGIAN "]),
+    ("Greek", ["GREEK "]),
+    ("Han", ["CJK UNIFIED IDEOGRAPH", "CJK COMPATIBILITY IDEOGRAPH", "CJK RADICAL ", "KANGXI RADICAL "]),
+    ("Hangul", ["HANGUL ", "HALFWIDTH HANGUL "]),
+    ("Hebrew", ["HEBREW "]),
+    ("Hiragana", ["HIRAGANA "]),
+    ("Katakana", ["KATAKANA ", "HALFWIDTH KATAKANA "]),
+    ("Latin", ["LATIN ", "FULLWIDTH LATIN "]),
+    ("Thai", ["THAI "]),
+]
+
+PER_LINE = 4
+
+
+def characters():
+    for code in range(0x110000):
+        if not 0xD800 <= code <= 0xDFFF:
+            yield chr(code)
+
+
+def ranges(members):
+    result = []
+    for ch in members:
+        code = ord(ch)
+        # The surrogate gap does not separate ranges, as in `CharClass`
+        if result and (result[-1][1] + 1 == code or (result[-1][1], code) == (0xD7FF, 0xE000)):
+            result[-1][1] = code
+        else:
+            result.append([code, code])
+    return result
+
+
+def table(name, members):
+    entries = ["('\\u{%x}', '\\u{%x}')," % (low, high) for low, high in ranges(members)]
+    lines = [" ".join(entries[i : i + PER_LINE]) for i in range(0, len(entries), PER_LINE)]
+    body = "".join("    %s\n" % line for line in lines)
+    return "#[rustfmt::skip]\nconst %s: Ranges = &[\n%s];\n" % (name, body)
+
+
+def main():
+    by_category = {short: [] for short, _ in CATEGORIES}
+    by_script = {name: [] for name, _ in SCRIPTS}
+    for ch in characters():
+        category = unicodedata.category(ch)
+        if category in by_category:
+            by_category[category].append(ch)
+        name = unicodedata.name(ch, "")
+        for script, prefixes in SCRIPTS:
+            if name.startswith(tuple(prefixes)):
+                by_script[script].append(ch)
+
+    out = [
+        "//! Unicode %s general categories and scripts, as sorted inclusive ranges.\n"
+        "//!\n"
+        "//! Generated by `scripts/unicode_tables.py`; do not edit by hand.\n"
+        % unicodedata.unidata_version
+    ]
+    out.append("\n/// Members of a property.\npub type Ranges = &'static [(char, char)];\n")
+    out.append(
+        "\n/// Each general category except unassigned and surrogate code points: its\n"
+        "/// abbreviation, its long name and its members.\n"
+        "pub const GENERAL_CATEGORIES: &[(&str, &str, Ranges)] = &[\n"
+    )
+    for short, long in CATEGORIES:
+        out.append('    ("%s", "%s", %s),\n' % (short, long, short.upper()))
+    out.append("];\n\n/// Each script and its members.\npub const SCRIPTS: &[(&str, Ranges)] = &[\n")
+    for name, _ in SCRIPTS:
+        out.append('    ("%s", %s),\n' % (name, name.upper()))
+    out.append("];\n")
+    for short, _ in CATEGORIES:
+        out.append("\n" + table(short.upper(), by_category[short]))
+    for name, _ in SCRIPTS:
+        out.append("\n" + table(name.upper(), by_script[name]))
+
+    with open("src/unicode_tables.rs", "w") as f:
+        f.write("".join(out))
+
+
+if __name__ == "__main__":
+    main()
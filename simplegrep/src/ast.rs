@@ -1,48 +1,289 @@
-#[derive(Debug, Clone, PartialEq)]
-pub enum RegexNode {
-    Char(char),
-    AnyChar,
-    Digit,
-    WordChar,
-    Whitespace,
-
-    // Sequences
-    Concat(Vec<RegexNode>),
-    Alternation(Vec<RegexNode>),
-
-    // Quantifiers
-    Repeat(Box<RegexNode>, RepeatRange),
-    Plus(Box<RegexNode>),
-    Star(Box<RegexNode>),
-    Question(Box<RegexNode>),
-
-    // Groups
-    Group(Box<RegexNode>),
-
-    // Anchors
-    StartLine,
-    EndLine,
-    StartInput,
-    EndInput,
-    WordBoundary,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct RepeatRange {
-    pub min: usize,
-    pub max: Option<usize>,
-}
-
-impl RepeatRange {
-    pub fn new(min: usize, max: Option<usize>) -> Self {
-        Self { min, max }
-    }
-
-    pub fn exactly(n: usize) -> Self {
-        Self::new(n, Some(n))
-    }
-
-    pub fn min(n: usize) -> Self {
-        Self::new(n, None)
-    }
-}
+use crate::class::CharClass;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegexNode {
+    /// Matches the empty string: an empty pattern, group or alternative, or `x{0}`.
+    Empty,
+    Char(char),
+    AnyChar,
+    Digit,
+    WordChar,
+    Whitespace,
+    /// Bracket expression: its members and whether it is negated.
+    CharClass {
+        class: CharClass,
+        negated: bool,
+    },
+
+    // Sequences
+    Concat(Vec<RegexNode>),
+    Alternation(Vec<RegexNode>),
+
+    // Quantifiers
+    Repeat(Box<RegexNode>, RepeatRange),
+    Plus(Box<RegexNode>),
+    Star(Box<RegexNode>),
+    Question(Box<RegexNode>),
+    /// Its quantifier operand, preferring as few repetitions as possible:
+    /// `*?`, `+?`, `??` or `{n,m}?`.
+    Lazy(Box<RegexNode>),
+
+    // Groups
+    /// Group with its 1-based capture index in order of opening parentheses,
+    /// or `None` for a non-capturing `(?:...)` group.
+    Group(Box<RegexNode>, Option<usize>),
+    /// Its operand compiled with inline flags turned on or off, from `(?ims-ims)` or
+    /// `(?ims-ims:...)`.
+    Flags(Box<RegexNode>, Flags),
+
+    // Anchors
+    StartLine,
+    EndLine,
+    StartInput,
+    EndInput,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+/// Flags set or cleared inline with `(?ims-ims)` or `(?ims-ims:...)`. `Some(true)`
+/// turns a flag on and `Some(false)` off for the operand, overriding the enclosing
+/// pattern and the matching options (so `(?-i)` wins over `-i`); `None` leaves the
+/// flag as those have it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Flags {
+    /// `i`: letters match regardless of case.
+    pub case_insensitive: Option<bool>,
+    /// `m`: `^` and `$` also match just after and before each `\n`.
+    pub multi_line: Option<bool>,
+    /// `s`: `.` also matches `\n`.
+    pub dot_all: Option<bool>,
+}
+
+impl Flags {
+    /// These flags with the ones `inner` sets or clears applied on top.
+    pub fn merge(self, inner: Flags) -> Flags {
+        Flags {
+            case_insensitive: inner.case_insensitive.or(self.case_insensitive),
+            multi_line: inner.multi_line.or(self.multi_line),
+            dot_all: inner.dot_all.or(self.dot_all),
+        }
+    }
+}
+
+pub fn is_word(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+impl RegexNode {
+    /// Returns the literal text every match must begin with (possibly empty).
+    pub fn literal_prefix(&self) -> String {
+        let mut prefix = String::new();
+        self.collect_prefix(&mut prefix);
+        prefix
+    }
+
+    /// Upper bound on the number of NFA states this node compiles to. Bounded
+    /// repetition copies its operand, so this is what repetition limits are checked against.
+    pub fn state_count(&self) -> usize {
+        match self {
+            RegexNode::Concat(nodes) => nodes.iter().map(RegexNode::state_count).sum(),
+            RegexNode::Alternation(nodes) => {
+                nodes.iter().map(RegexNode::state_count).sum::<usize>() + 1
+            }
+            RegexNode::Plus(node) | RegexNode::Star(node) | RegexNode::Question(node) => {
+                node.state_count() + 1
+            }
+            RegexNode::Group(node, None) | RegexNode::Flags(node, _) | RegexNode::Lazy(node) => {
+                node.state_count()
+            }
+            RegexNode::Group(node, Some(_)) => node.state_count() + 2,
+            RegexNode::Empty => 0,
+            RegexNode::Repeat(node, range) => {
+                let body = node.state_count();
+                let optional = match range.max {
+                    Some(max) => (max - range.min).saturating_mul(body + 1),
+                    None => 1,
+                };
+                range.min.saturating_mul(body).saturating_add(optional)
+            }
+            _ => 1,
+        }
+    }
+
+    /// Rewrites literals and bracket expressions to also accept the other case of
+    /// each letter, except under `(?-i)`, for analyses that must see a pattern the
+    /// way it matches case-insensitively.
+    pub fn fold_case(&self) -> RegexNode {
+        let fold = |node: &RegexNode| Box::new(node.fold_case());
+        match self {
+            RegexNode::Char(ch) => {
+                let class = CharClass::char(*ch).case_folded();
+                match class.single() {
+                    Some(ch) => RegexNode::Char(ch),
+                    None => RegexNode::CharClass {
+                        class,
+                        negated: false,
+                    },
+                }
+            }
+            RegexNode::CharClass { class, negated } => RegexNode::CharClass {
+                class: class.case_folded(),
+                negated: *negated,
+            },
+            RegexNode::Concat(nodes) => {
+                RegexNode::Concat(nodes.iter().map(Self::fold_case).collect())
+            }
+            RegexNode::Alternation(nodes) => {
+                RegexNode::Alternation(nodes.iter().map(Self::fold_case).collect())
+            }
+            RegexNode::Repeat(node, range) => RegexNode::Repeat(fold(node), range.clone()),
+            RegexNode::Plus(node) => RegexNode::Plus(fold(node)),
+            RegexNode::Star(node) => RegexNode::Star(fold(node)),
+            RegexNode::Question(node) => RegexNode::Question(fold(node)),
+            RegexNode::Lazy(node) => RegexNode::Lazy(fold(node)),
+            RegexNode::Flags(_, flags) if flags.case_insensitive == Some(false) => self.clone(),
+            RegexNode::Flags(node, flags) => RegexNode::Flags(fold(node), *flags),
+            RegexNode::Group(node, index) => RegexNode::Group(fold(node), *index),
+            other => other.clone(),
+        }
+    }
+
+    /// The text this node matches if it is nothing but literal characters, like `abc`.
+    pub fn as_literal(&self) -> Option<String> {
+        match self {
+            RegexNode::Char(ch) => Some(ch.to_string()),
+            RegexNode::Concat(nodes) => nodes
+                .iter()
+                .map(|node| match node {
+                    RegexNode::Char(ch) => Some(*ch),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        }
+    }
+
+    /// Returns a bitmap of the ASCII characters every match contains, bit `c` standing
+    /// for the character `c`. Zero when no character is certain to appear.
+    pub fn required_ascii(&self) -> u128 {
+        match self {
+            RegexNode::Char(ch) if ch.is_ascii() => 1 << *ch as u32,
+            RegexNode::CharClass {
+                class,
+                negated: false,
+            } => class
+                .single()
+                .filter(char::is_ascii)
+                .map_or(0, |ch| 1 << ch as u32),
+            RegexNode::Concat(nodes) => nodes
+                .iter()
+                .fold(0, |required, node| required | node.required_ascii()),
+            RegexNode::Alternation(nodes) => nodes
+                .iter()
+                .map(RegexNode::required_ascii)
+                .reduce(|a, b| a & b)
+                .unwrap_or(0),
+            RegexNode::Flags(node, flags) if flags.case_insensitive == Some(true) => {
+                node.fold_case().required_ascii()
+            }
+            RegexNode::Group(node, _)
+            | RegexNode::Plus(node)
+            | RegexNode::Lazy(node)
+            | RegexNode::Flags(node, _) => node.required_ascii(),
+            RegexNode::Repeat(node, range) if range.min > 0 => node.required_ascii(),
+            _ => 0,
+        }
+    }
+
+    /// Returns true if every match must begin at the start of the input.
+    pub fn is_anchored(&self) -> bool {
+        match self {
+            RegexNode::StartLine | RegexNode::StartInput => true,
+            RegexNode::Concat(nodes) => nodes.first().is_some_and(RegexNode::is_anchored),
+            RegexNode::Alternation(nodes) => nodes.iter().all(RegexNode::is_anchored),
+            RegexNode::Group(node, _) | RegexNode::Plus(node) | RegexNode::Lazy(node) => {
+                node.is_anchored()
+            }
+            RegexNode::Repeat(node, range) if range.min > 0 => node.is_anchored(),
+            // In multi-line mode `^` also matches after every `\n`
+            RegexNode::Flags(node, flags) if flags.multi_line != Some(true) => node.is_anchored(),
+            _ => false,
+        }
+    }
+
+    /// Appends this node's required leading literal to `prefix`, returning true
+    /// if the node matched exactly that text so the caller may keep extending it.
+    fn collect_prefix(&self, prefix: &mut String) -> bool {
+        match self {
+            RegexNode::Char(ch) => {
+                prefix.push(*ch);
+                true
+            }
+            RegexNode::Concat(nodes) => nodes.iter().all(|node| node.collect_prefix(prefix)),
+            RegexNode::Flags(node, flags) if flags.case_insensitive == Some(true) => {
+                node.fold_case().collect_prefix(prefix)
+            }
+            RegexNode::Group(node, _) | RegexNode::Lazy(node) | RegexNode::Flags(node, _) => {
+                node.collect_prefix(prefix)
+            }
+            RegexNode::Plus(node) => {
+                node.collect_prefix(prefix);
+                false
+            }
+            RegexNode::Repeat(node, range) if range.min > 0 => {
+                node.collect_prefix(prefix);
+                false
+            }
+            RegexNode::Alternation(nodes) => {
+                let branches: Vec<String> = nodes.iter().map(RegexNode::literal_prefix).collect();
+                prefix.push_str(common_prefix(&branches));
+                false
+            }
+            // Anchors take up no input, so the literal after them still leads the match
+            RegexNode::Empty
+            | RegexNode::StartLine
+            | RegexNode::EndLine
+            | RegexNode::StartInput
+            | RegexNode::EndInput
+            | RegexNode::WordBoundary
+            | RegexNode::NotWordBoundary => true,
+            _ => false,
+        }
+    }
+}
+
+/// Returns the longest prefix shared by all `strings`.
+pub fn common_prefix<S: AsRef<str>>(strings: &[S]) -> &str {
+    let Some((first, rest)) = strings.split_first() else {
+        return "";
+    };
+    let first = first.as_ref();
+    let len = rest.iter().fold(first.len(), |len, other| {
+        first[..len]
+            .char_indices()
+            .zip(other.as_ref().chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(len.min(other.as_ref().len()), |((i, _), _)| i)
+    });
+    &first[..len]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepeatRange {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl RepeatRange {
+    pub fn new(min: usize, max: Option<usize>) -> Self {
+        Self { min, max }
+    }
+
+    pub fn exactly(n: usize) -> Self {
+        Self::new(n, Some(n))
+    }
+
+    pub fn min(n: usize) -> Self {
+        Self::new(n, None)
+    }
+}
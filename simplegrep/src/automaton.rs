@@ -1,191 +1,1306 @@
-use crate::ast::{RegexNode, RepeatRange};
-
-#[derive(Debug, Clone, PartialEq)]
-pub enum NFAState {
-    Start,
-    Match,
-    Transition(char, usize),
-    EpsilonTransition(usize),
-}
-
-#[derive(Debug, Clone)]
-pub struct NFA {
-    pub states: Vec<NFAState>,
-    pub start: usize,
-    pub accept: usize,
-}
-
-impl NFA {
-    pub fn new() -> Self {
-        Self {
-            states: vec![NFAState::Start],
-            start: 0,
-            accept: 0,
-        }
-    }
-
-    pub fn from_regex(node: &RegexNode) -> Self {
-        let mut nfa = Self::new();
-        nfa.build_from_node(node, 0);
-        nfa
-    }
-
-    fn build_from_node(&mut self, node: &RegexNode, current_state: usize) -> usize {
-        match node {
-            RegexNode::Char(ch) => {
-                let new_state = self.states.len();
-                self.states[current_state] = NFAState::Transition(*ch, new_state);
-                self.states.push(NFAState::Match);
-                new_state
-            }
-            RegexNode::AnyChar => {
-                // Simplified: match any character
-                let new_state = self.states.len();
-                self.states[current_state] = NFAState::Transition('\0', new_state); // \0 means "any"
-                self.states.push(NFAState::Match);
-                new_state
-            }
-            RegexNode::Concat(nodes) => {
-                let mut current = current_state;
-                for node in nodes {
-                    current = self.build_from_node(node, current);
-                }
-                current
-            }
-            RegexNode::Alternation(nodes) => {
-                let start_state = self.states.len();
-                self.states.push(NFAState::Start);
-
-                let mut accept_state = None;
-                for node in nodes {
-                    let branch_accept = self.build_from_node(node, start_state);
-                    if accept_state.is_none() {
-                        accept_state = Some(branch_accept);
-                    } else {
-                        // Merge accept states
-                        self.states[branch_accept] = NFAState::Match;
-                    }
-                }
-
-                if let Some(accept) = accept_state {
-                    accept
-                } else {
-                    start_state
-                }
-            }
-            RegexNode::Star(node) => {
-                let start_state = self.states.len();
-                self.states.push(NFAState::Start);
-
-                let branch_accept = self.build_from_node(node, start_state);
-                self.states[branch_accept] = NFAState::EpsilonTransition(start_state);
-
-                start_state
-            }
-            RegexNode::Plus(node) => {
-                let start_state = self.states.len();
-                self.states.push(NFAState::Start);
-
-                let branch_accept = self.build_from_node(node, start_state);
-                self.states[branch_accept] = NFAState::EpsilonTransition(start_state);
-
-                start_state
-            }
-            RegexNode::Question(node) => {
-                let start_state = self.states.len();
-                self.states.push(NFAState::Start);
-
-                let branch_accept = self.build_from_node(node, start_state);
-                self.states[branch_accept] = NFAState::Match;
-
-                start_state
-            }
-            RegexNode::Repeat(node, range) => {
-                // Simplified repetition
-                let mut current = current_state;
-
-                // Minimum repetitions
-                for _ in 0..range.min {
-                    current = self.build_from_node(node, current);
-                }
-
-                // Optional extra repetitions
-                if let Some(max) = range.max {
-                    for _ in range.min..max {
-                        current = self.build_from_node(node, current);
-                    }
-                } else {
-                    // Handle unbounded repetition (simplified)
-                    // In a full implementation, you'd add epsilon transitions for repetition
-                }
-
-                current
-            }
-            RegexNode::Group(node) => self.build_from_node(node, current_state),
-            RegexNode::Digit => {
-                // Simplified: match digits 0-9
-                let new_state = self.states.len();
-                self.states[current_state] = NFAState::Transition('\0', new_state);
-                self.states.push(NFAState::Match);
-                new_state
-            }
-            RegexNode::WordChar => {
-                // Simplified: match word characters [a-zA-Z0-9_]
-                let new_state = self.states.len();
-                self.states[current_state] = NFAState::Transition('\0', new_state);
-                self.states.push(NFAState::Match);
-                new_state
-            }
-            RegexNode::Whitespace => {
-                // Simplified: match whitespace
-                let new_state = self.states.len();
-                self.states[current_state] = NFAState::Transition('\0', new_state);
-                self.states.push(NFAState::Match);
-                new_state
-            }
-            RegexNode::StartLine
-            | RegexNode::EndLine
-            | RegexNode::StartInput
-            | RegexNode::EndInput
-            | RegexNode::WordBoundary => {
-                // Simplified handling of anchors
-                let new_state = self.states.len();
-                self.states[current_state] = NFAState::Transition('\0', new_state);
-                self.states.push(NFAState::Match);
-                new_state
-            }
-        }
-    }
-
-    pub fn matches(&self, input: &str) -> bool {
-        let mut current_states = vec![self.start];
-
-        for ch in input.chars() {
-            let mut next_states = Vec::new();
-
-            for &state in &current_states {
-                if let Some(new_states) = self.transition(state, ch) {
-                    next_states.extend(new_states);
-                }
-            }
-
-            if next_states.is_empty() {
-                return false;
-            }
-
-            current_states = next_states;
-        }
-
-        current_states.contains(&self.accept)
-    }
-
-    fn transition(&self, state: usize, ch: char) -> Option<Vec<usize>> {
-        match &self.states[state] {
-            NFAState::Transition(expected, next) if *expected == '\0' || *expected == ch => {
-                Some(vec![*next])
-            }
-            NFAState::EpsilonTransition(next) => Some(vec![*next]),
-            _ => None,
-        }
-    }
-}
+use crate::ast::{self, Flags, RegexNode, is_word};
+use crate::class::CharClass;
+use crate::dfa::{DEFAULT_MAX_STATES, Dfa};
+use crate::lazy_dfa::LazyDfa;
+use crate::parser::Parser;
+use std::fmt::Write;
+
+/// Zero-width conditions checked against the characters around the current position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assertion {
+    StartLine,
+    EndLine,
+    StartInput,
+    EndInput,
+    WordBoundary,
+    /// `\B`: both sides are word characters, or neither is.
+    NotWordBoundary,
+    /// `^` in multi-line mode: at the start of the input or just after a `\n`.
+    MultiLineStart,
+    /// `$` in multi-line mode: at the end of the input or just before a `\n`.
+    MultiLineEnd,
+}
+
+impl Assertion {
+    pub(crate) fn holds(self, prev: Option<char>, next: Option<char>) -> bool {
+        match self {
+            Assertion::StartLine | Assertion::StartInput => prev.is_none(),
+            Assertion::EndLine | Assertion::EndInput => next.is_none(),
+            Assertion::MultiLineStart => prev.is_none_or(|ch| ch == '\n'),
+            Assertion::MultiLineEnd => next.is_none_or(|ch| ch == '\n'),
+            Assertion::WordBoundary => prev.is_some_and(is_word) != next.is_some_and(is_word),
+            Assertion::NotWordBoundary => prev.is_some_and(is_word) == next.is_some_and(is_word),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NFAState {
+    Char(char, usize),
+    AnyChar(usize),
+    /// Any member of the set; negated bracket expressions are complemented when built.
+    Class(CharClass, usize),
+    Assert(Assertion, usize),
+    /// Records the current position in capture slot `.0`, then continues at `.1`.
+    Save(usize, usize),
+    /// Epsilon transitions to every listed state, in priority order.
+    Split(Vec<usize>),
+    /// Accepting state of the pattern with the given index.
+    Match(usize),
+}
+
+impl NFAState {
+    fn targets(&self) -> &[usize] {
+        match self {
+            NFAState::Char(_, next)
+            | NFAState::AnyChar(next)
+            | NFAState::Class(_, next)
+            | NFAState::Assert(_, next)
+            | NFAState::Save(_, next) => std::slice::from_ref(next),
+            NFAState::Split(targets) => targets,
+            NFAState::Match(_) => &[],
+        }
+    }
+
+    fn retarget(&mut self, map: impl Fn(usize) -> Option<usize>) {
+        match self {
+            NFAState::Char(_, next)
+            | NFAState::AnyChar(next)
+            | NFAState::Class(_, next)
+            | NFAState::Assert(_, next)
+            | NFAState::Save(_, next) => {
+                *next = map(*next).expect("live state leads to a dead one")
+            }
+            NFAState::Split(targets) => *targets = targets.iter().filter_map(|&t| map(t)).collect(),
+            NFAState::Match(_) => {}
+        }
+    }
+}
+
+/// Flags that change how patterns are compiled and run.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NfaOptions {
+    /// Match letters regardless of case, as if the whole pattern were in `(?i:...)`.
+    pub case_insensitive: bool,
+    /// Let `.` match `\n` as well.
+    pub dot_all: bool,
+    /// Let `^` and `$` match just after and before each `\n` as well.
+    pub multi_line: bool,
+    /// Only look for matches that begin where the search starts.
+    pub anchored: bool,
+    /// Only accept matches spanning the whole input, as if each pattern were
+    /// written `\A(?:...)\z`, like `grep -x` does for lines.
+    pub whole_line: bool,
+    /// Report the longest of the matches starting leftmost, as POSIX specifies,
+    /// instead of the one the first alternative and greedy repetition lead to.
+    pub leftmost_longest: bool,
+}
+
+/// Applies the options that rewrite the pattern itself before it is compiled.
+///
+/// Case-insensitivity becomes a `Flags` node around the whole pattern, so that the
+/// literal prefix and required characters are worked out with it, and any `(?-i)`
+/// inside still takes precedence.
+pub(crate) fn prepare(node: &RegexNode, options: &NfaOptions) -> RegexNode {
+    let node = if options.case_insensitive {
+        RegexNode::Flags(
+            Box::new(node.clone()),
+            Flags {
+                case_insensitive: Some(true),
+                ..Flags::default()
+            },
+        )
+    } else {
+        node.clone()
+    };
+    if options.whole_line {
+        RegexNode::Concat(vec![
+            RegexNode::StartInput,
+            RegexNode::Group(Box::new(node), None),
+            RegexNode::EndInput,
+        ])
+    } else {
+        node
+    }
+}
+
+/// Input length from which `NFA::matches` switches to a lazy DFA.
+pub const LAZY_DFA_MIN_INPUT: usize = 4096;
+
+/// A Thompson NFA with one accepting state per compiled pattern.
+#[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct NFA {
+    pub states: Vec<NFAState>,
+    pub start: usize,
+    pub pattern_count: usize,
+    /// Number of groups per match, counting the whole match as group 0.
+    pub group_count: usize,
+    /// Literal text every match starts with; start positions without it are skipped.
+    pub prefix: String,
+    /// Bitmap of ASCII characters every match contains (see `RegexNode::required_ascii`);
+    /// inputs missing one are rejected without running the automaton.
+    pub required: u128,
+    /// Whether every pattern can only match at the start of the input.
+    pub anchored: bool,
+    pub options: NfaOptions,
+}
+
+impl NFA {
+    pub fn from_regex(node: &RegexNode) -> Self {
+        Self::from_regex_with_options(node, &NfaOptions::default())
+    }
+
+    pub fn from_regex_with_options(node: &RegexNode, options: &NfaOptions) -> Self {
+        let node = &prepare(node, options);
+        let mut nfa = Self {
+            states: vec![NFAState::Match(0)],
+            start: 0,
+            pattern_count: 1,
+            group_count: 1,
+            prefix: node.literal_prefix(),
+            required: node.required_ascii(),
+            anchored: options.anchored || (!options.multi_line && node.is_anchored()),
+            options: *options,
+        };
+        nfa.start = nfa.build_pattern(node, 0);
+        nfa.trim();
+        nfa
+    }
+
+    /// Parses each pattern and combines them under a single top-level alternation,
+    /// so one pass over the input reports every pattern that matched.
+    pub fn from_patterns(patterns: &[&str]) -> Result<Self, String> {
+        let nodes = patterns
+            .iter()
+            .map(|pattern| Parser::new(pattern).parse())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::from_nodes(&nodes))
+    }
+
+    /// Combines already parsed patterns; see `from_patterns`.
+    pub fn from_nodes(nodes: &[RegexNode]) -> Self {
+        Self::from_nodes_with_options(nodes, &NfaOptions::default())
+    }
+
+    pub fn from_nodes_with_options(nodes: &[RegexNode], options: &NfaOptions) -> Self {
+        let nodes: &[RegexNode] = &nodes
+            .iter()
+            .map(|node| prepare(node, options))
+            .collect::<Vec<_>>();
+        let mut nfa = Self {
+            states: Vec::new(),
+            start: 0,
+            pattern_count: nodes.len(),
+            group_count: 1,
+            prefix: String::new(),
+            required: nodes
+                .iter()
+                .map(RegexNode::required_ascii)
+                .reduce(|a, b| a & b)
+                .unwrap_or(0),
+            anchored: options.anchored
+                || (!options.multi_line
+                    && !nodes.is_empty()
+                    && nodes.iter().all(RegexNode::is_anchored)),
+            options: *options,
+        };
+        let mut starts = Vec::with_capacity(nodes.len());
+        let mut prefixes = Vec::with_capacity(nodes.len());
+        for (index, node) in nodes.iter().enumerate() {
+            let accept = nfa.push(NFAState::Match(index));
+            starts.push(nfa.build_pattern(node, accept));
+            prefixes.push(node.literal_prefix());
+        }
+        nfa.start = nfa.push(NFAState::Split(starts));
+        nfa.prefix = ast::common_prefix(&prefixes).to_string();
+        nfa.trim();
+        nfa
+    }
+
+    fn push(&mut self, state: NFAState) -> usize {
+        self.states.push(state);
+        self.states.len() - 1
+    }
+
+    /// Compiles a whole pattern, wrapped in the saves for capture group 0.
+    fn build_pattern(&mut self, node: &RegexNode, accept: usize) -> usize {
+        let end = self.push(NFAState::Save(1, accept));
+        let body = self.build_from_node(node, end);
+        self.push(NFAState::Save(0, body))
+    }
+
+    /// Compiles `node` so that a successful match continues at `next`,
+    /// returning the entry state of the compiled fragment.
+    fn build_from_node(&mut self, node: &RegexNode, next: usize) -> usize {
+        match node {
+            // Consumes nothing, so the fragment is just its continuation
+            RegexNode::Empty => next,
+            // Folding case here, per literal, lets `(?-i)` switch it back off
+            RegexNode::Char(ch) if self.options.case_insensitive => {
+                let class = CharClass::char(*ch).case_folded();
+                match class.single() {
+                    Some(ch) => self.push(NFAState::Char(ch, next)),
+                    None => self.push(NFAState::Class(class, next)),
+                }
+            }
+            RegexNode::Char(ch) => self.push(NFAState::Char(*ch, next)),
+            RegexNode::AnyChar if self.options.dot_all => self.push(NFAState::AnyChar(next)),
+            RegexNode::AnyChar => self.push(NFAState::Class(CharClass::char('\n').negate(), next)),
+            RegexNode::Digit => self.push(NFAState::Class(CharClass::digit(), next)),
+            RegexNode::WordChar => self.push(NFAState::Class(CharClass::word(), next)),
+            RegexNode::Whitespace => self.push(NFAState::Class(CharClass::whitespace(), next)),
+            RegexNode::CharClass { class, negated } => {
+                let class = if self.options.case_insensitive {
+                    class.case_folded()
+                } else {
+                    class.clone()
+                };
+                let class = if *negated { class.negate() } else { class };
+                self.push(NFAState::Class(class, next))
+            }
+            RegexNode::Concat(nodes) => nodes
+                .iter()
+                .rev()
+                .fold(next, |next, node| self.build_from_node(node, next)),
+            RegexNode::Alternation(nodes) => {
+                let branches = nodes
+                    .iter()
+                    .map(|node| self.build_from_node(node, next))
+                    .collect();
+                self.push(NFAState::Split(branches))
+            }
+            RegexNode::Lazy(node) => self.build_quantifier(node, next, false),
+            RegexNode::Star(_)
+            | RegexNode::Plus(_)
+            | RegexNode::Question(_)
+            | RegexNode::Repeat(..) => self.build_quantifier(node, next, true),
+            RegexNode::Group(node, None) => self.build_from_node(node, next),
+            RegexNode::Flags(node, flags) => {
+                // The options stand for the flags in effect while the operand is built
+                let outer = self.options;
+                self.options.multi_line = flags.multi_line.unwrap_or(outer.multi_line);
+                self.options.dot_all = flags.dot_all.unwrap_or(outer.dot_all);
+                self.options.case_insensitive =
+                    flags.case_insensitive.unwrap_or(outer.case_insensitive);
+                let fragment = self.build_from_node(node, next);
+                self.options = outer;
+                fragment
+            }
+            RegexNode::Group(node, Some(index)) => {
+                self.group_count = self.group_count.max(index + 1);
+                let end = self.push(NFAState::Save(2 * index + 1, next));
+                let body = self.build_from_node(node, end);
+                self.push(NFAState::Save(2 * index, body))
+            }
+            RegexNode::StartLine if self.options.multi_line => {
+                self.push(NFAState::Assert(Assertion::MultiLineStart, next))
+            }
+            RegexNode::EndLine if self.options.multi_line => {
+                self.push(NFAState::Assert(Assertion::MultiLineEnd, next))
+            }
+            RegexNode::StartLine => self.push(NFAState::Assert(Assertion::StartLine, next)),
+            RegexNode::EndLine => self.push(NFAState::Assert(Assertion::EndLine, next)),
+            RegexNode::StartInput => self.push(NFAState::Assert(Assertion::StartInput, next)),
+            RegexNode::EndInput => self.push(NFAState::Assert(Assertion::EndInput, next)),
+            RegexNode::WordBoundary => self.push(NFAState::Assert(Assertion::WordBoundary, next)),
+            RegexNode::NotWordBoundary => {
+                self.push(NFAState::Assert(Assertion::NotWordBoundary, next))
+            }
+        }
+    }
+
+    /// Compiles a quantifier node. Greedy ones try another repetition before `next`,
+    /// lazy ones try `next` first; only the order of the split targets differs.
+    fn build_quantifier(&mut self, node: &RegexNode, next: usize, greedy: bool) -> usize {
+        match node {
+            RegexNode::Star(node) => self.build_loop(node, next, greedy),
+            RegexNode::Plus(node) => self.build_plus(node, next, greedy),
+            RegexNode::Question(node) => {
+                let body = self.build_from_node(node, next);
+                self.push(NFAState::Split(choices(body, next, greedy)))
+            }
+            RegexNode::Repeat(node, range) => {
+                // Optional copies are nested so each one can exit straight to `next`
+                let (mut current, required) = match range.max {
+                    Some(max) => {
+                        let optional = (range.min..max).fold(next, |current, _| {
+                            let body = self.build_from_node(node, current);
+                            self.push(NFAState::Split(choices(body, next, greedy)))
+                        });
+                        (optional, range.min)
+                    }
+                    // x{n,} is x{n-1}x+, so the last required copy doubles as the loop body
+                    None if range.min > 0 => (self.build_plus(node, next, greedy), range.min - 1),
+                    None => (self.build_loop(node, next, greedy), 0),
+                };
+                for _ in 0..required {
+                    current = self.build_from_node(node, current);
+                }
+                current
+            }
+            // The parser only makes quantifiers lazy
+            other => self.build_from_node(other, next),
+        }
+    }
+
+    /// Removes states that are unreachable from `start` or that cannot reach any
+    /// accepting state, renumbering the remaining transitions.
+    pub fn trim(&mut self) {
+        let mut reachable = vec![false; self.states.len()];
+        let mut stack = vec![self.start];
+        while let Some(state) = stack.pop() {
+            if !std::mem::replace(&mut reachable[state], true) {
+                stack.extend(self.states[state].targets());
+            }
+        }
+
+        let mut predecessors = vec![Vec::new(); self.states.len()];
+        for (state, nfa_state) in self.states.iter().enumerate() {
+            for &target in nfa_state.targets() {
+                predecessors[target].push(state);
+            }
+        }
+        let mut live = vec![false; self.states.len()];
+        let mut stack: Vec<usize> = (0..self.states.len())
+            .filter(|&state| matches!(self.states[state], NFAState::Match(_)))
+            .collect();
+        while let Some(state) = stack.pop() {
+            if !std::mem::replace(&mut live[state], true) {
+                stack.extend(&predecessors[state]);
+            }
+        }
+
+        if !live[self.start] {
+            // Nothing can match; keep a lone start state with no way out
+            self.states = vec![NFAState::Split(Vec::new())];
+            self.start = 0;
+            return;
+        }
+
+        let mut renumbered = vec![None; self.states.len()];
+        let mut next_index = 0;
+        for state in 0..self.states.len() {
+            if reachable[state] && live[state] {
+                renumbered[state] = Some(next_index);
+                next_index += 1;
+            }
+        }
+
+        let states = std::mem::take(&mut self.states);
+        self.states = states
+            .into_iter()
+            .enumerate()
+            .filter(|(state, _)| renumbered[*state].is_some())
+            .map(|(_, mut nfa_state)| {
+                nfa_state.retarget(|target| renumbered[target]);
+                nfa_state
+            })
+            .collect();
+        self.start = renumbered[self.start].unwrap();
+    }
+
+    fn build_plus(&mut self, node: &RegexNode, next: usize, greedy: bool) -> usize {
+        let split = self.push(NFAState::Split(Vec::new()));
+        let body = self.build_from_node(node, split);
+        self.states[split] = NFAState::Split(choices(body, next, greedy));
+        body
+    }
+
+    fn build_loop(&mut self, node: &RegexNode, next: usize, greedy: bool) -> usize {
+        let split = self.push(NFAState::Split(Vec::new()));
+        let body = self.build_from_node(node, split);
+        self.states[split] = NFAState::Split(choices(body, next, greedy));
+        split
+    }
+
+    /// Describes how the automaton will be run, for `--explain`.
+    pub fn explain(&self) -> String {
+        let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+        let mut out = String::new();
+        let _ = writeln!(out, "patterns: {}", self.pattern_count);
+        let _ = writeln!(out, "states: {}", self.states.len());
+        let _ = writeln!(out, "capture groups: {}", self.group_count - 1);
+        match Dfa::new(self) {
+            Some(dfa) => {
+                let _ = writeln!(out, "matcher: DFA");
+                let _ = writeln!(out, "DFA: yes ({} states)", dfa.state_count());
+            }
+            None => {
+                let _ = writeln!(out, "matcher: lazy DFA");
+                let _ = writeln!(out, "DFA: no (more than {} states)", DEFAULT_MAX_STATES);
+            }
+        }
+        if self.prefix.is_empty() {
+            let _ = writeln!(out, "literal prefix: none");
+        } else {
+            let _ = writeln!(out, "literal prefix: {:?}", self.prefix);
+        }
+        if self.required == 0 {
+            let _ = writeln!(out, "required characters: none");
+        } else {
+            let required: String = (0..128u8)
+                .filter(|&byte| self.required >> byte & 1 == 1)
+                .map(char::from)
+                .collect();
+            let _ = writeln!(out, "required characters: {:?}", required);
+        }
+        let _ = writeln!(out, "anchored: {}", yes_no(self.anchored));
+        let _ = writeln!(out, "backtracking: no");
+        out
+    }
+
+    /// Returns true if any compiled pattern matches somewhere in `input`.
+    ///
+    /// Inputs of `LAZY_DFA_MIN_INPUT` bytes or more are run on a lazy DFA, which
+    /// repays the cost of building its states on long inputs. The DFA is dropped
+    /// afterwards; to match many lines, keep one `LazyDfa` across them instead.
+    pub fn matches(&self, input: &str) -> bool {
+        if !self.has_required(input.as_bytes()) {
+            return false;
+        }
+        if input.len() >= LAZY_DFA_MIN_INPUT
+            && let Some(matched) = LazyDfa::new(self).try_is_match(input)
+        {
+            return matched;
+        }
+        self.simulate(input)
+    }
+
+    /// Answers `matches` by simulating the automaton directly, without a lazy DFA.
+    pub(crate) fn simulate(&self, input: &str) -> bool {
+        self.search(input, true).0.iter().any(|&matched| matched)
+    }
+
+    /// Like `matches`, but for input that need not be valid UTF-8.
+    ///
+    /// Valid sequences are matched as characters. Each invalid byte is one unit that
+    /// `.` and classes treat like U+FFFD, so `.` and `[^a]` match it (except
+    /// `.` never matches `\n`), while literal characters never do.
+    pub fn matches_bytes(&self, input: &[u8]) -> bool {
+        if !self.has_required(input) {
+            return false;
+        }
+        let mut current = StateSet::new(self.states.len());
+        let mut next = StateSet::new(self.states.len());
+
+        let mut units = units(input).peekable();
+        let mut pos = 0;
+        let mut prev = None;
+        loop {
+            let may_start = self.may_start_at(pos, 0);
+            if current.states.is_empty() && !may_start {
+                break;
+            }
+            let unit = units.next();
+            if may_start {
+                self.add_state(&mut current, self.start, prev, unit.map(Unit::as_char));
+            }
+            if current
+                .states
+                .iter()
+                .any(|&state| matches!(self.states[state], NFAState::Match(_)))
+            {
+                return true;
+            }
+
+            let Some(unit) = unit else { break };
+            pos += unit.len();
+            let following = units.peek().map(|unit| unit.as_char());
+            for &state in &current.states {
+                if let Some(target) = self.step_unit(state, unit) {
+                    self.add_state(&mut next, target, Some(unit.as_char()), following);
+                }
+            }
+            prev = Some(unit.as_char());
+
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        false
+    }
+
+    /// Returns the indices of all patterns that match somewhere in `line`, in ascending order.
+    pub fn matching_patterns(&self, line: &str) -> Vec<usize> {
+        if !self.has_required(line.as_bytes()) {
+            return Vec::new();
+        }
+        self.search(line, false)
+            .0
+            .iter()
+            .enumerate()
+            .filter(|(_, matched)| **matched)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Returns the earliest offset at which any match ends, stopping the simulation
+    /// there instead of extending the match.
+    pub fn shortest_match(&self, input: &str) -> Option<usize> {
+        if !self.has_required(input.as_bytes()) {
+            return None;
+        }
+        self.search(input, true).1
+    }
+
+    /// Whether `input` contains every character in `required`, a cheap check that
+    /// lets most non-matching lines skip the simulation.
+    pub(crate) fn has_required(&self, input: &[u8]) -> bool {
+        let mut missing = self.required;
+        while missing != 0 {
+            let byte = missing.trailing_zeros() as u8;
+            if !input.contains(&byte) {
+                return false;
+            }
+            missing &= missing - 1;
+        }
+        true
+    }
+
+    /// Runs the automaton anchored at byte offset `start` and returns the end of the
+    /// longest match beginning there. `start` must lie on a character boundary.
+    pub fn longest_match_at(&self, input: &str, start: usize) -> Option<usize> {
+        let mut current = StateSet::new(self.states.len());
+        let mut next = StateSet::new(self.states.len());
+
+        let rest = &input[start..];
+        let prev = input[..start].chars().next_back();
+        self.add_state(&mut current, self.start, prev, rest.chars().next());
+
+        let mut longest = None;
+        let mut chars = rest.char_indices().peekable();
+        loop {
+            if current
+                .states
+                .iter()
+                .any(|&state| matches!(self.states[state], NFAState::Match(_)))
+            {
+                longest = Some(start + chars.peek().map_or(rest.len(), |&(i, _)| i));
+            }
+            let Some((_, ch)) = chars.next() else { break };
+            let following = chars.peek().map(|&(_, ch)| ch);
+            for &state in &current.states {
+                if let Some(target) = self.step(state, ch) {
+                    self.add_state(&mut next, target, Some(ch), following);
+                }
+            }
+            if next.states.is_empty() {
+                break;
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+
+        longest
+    }
+
+    /// Simulates the automaton from every start position at once, recording which
+    /// patterns reached their accepting state and the offset where a match first ended.
+    fn search(&self, input: &str, stop_at_first: bool) -> (Vec<bool>, Option<usize>) {
+        let mut matched = vec![false; self.pattern_count];
+        let mut first_end = None;
+        let mut current = StateSet::new(self.states.len());
+        let mut next = StateSet::new(self.states.len());
+
+        let mut pos = 0;
+        let mut prev = None;
+        loop {
+            let may_start = self.may_start_at(pos, 0);
+            if current.states.is_empty() && !may_start {
+                // Anchored, and every thread from offset 0 has died
+                break;
+            }
+            if current.states.is_empty() && !self.prefix.is_empty() && !self.anchored {
+                // Nothing in flight, so jump straight to the next candidate start
+                let Some(offset) = input[pos..].find(&self.prefix) else {
+                    break;
+                };
+                pos += offset;
+                prev = input[..pos].chars().next_back();
+            }
+
+            let ch = input[pos..].chars().next();
+            if may_start && input[pos..].starts_with(&self.prefix) {
+                #[cfg(test)]
+                START_ATTEMPTS.with(|attempts| attempts.set(attempts.get() + 1));
+                self.add_state(&mut current, self.start, prev, ch);
+            }
+            for &state in &current.states {
+                if let NFAState::Match(index) = self.states[state] {
+                    matched[index] = true;
+                    first_end.get_or_insert(pos);
+                }
+            }
+            if stop_at_first && matched.contains(&true) {
+                break;
+            }
+
+            let Some(ch) = ch else { break };
+            pos += ch.len_utf8();
+            let following = input[pos..].chars().next();
+            for &state in &current.states {
+                if let Some(target) = self.step(state, ch) {
+                    self.add_state(&mut next, target, Some(ch), following);
+                }
+            }
+            prev = Some(ch);
+
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+
+        (matched, first_end)
+    }
+
+    /// Whether a match may begin at byte offset `pos` of a search begun at `start`.
+    /// Patterns anchored with `^` or `\A` only match at offset 0, so searches for
+    /// them end as soon as the threads started there have died.
+    fn may_start_at(&self, pos: usize, start: usize) -> bool {
+        if self.options.anchored {
+            pos == start
+        } else {
+            !self.anchored || pos == 0
+        }
+    }
+
+    pub(crate) fn step(&self, state: usize, ch: char) -> Option<usize> {
+        match self.states[state] {
+            NFAState::Class(ref class, next) if class.contains(ch) => Some(next),
+            NFAState::Char(expected, next) if expected == ch => Some(next),
+            NFAState::AnyChar(next) => Some(next),
+            _ => None,
+        }
+    }
+
+    fn step_unit(&self, state: usize, unit: Unit) -> Option<usize> {
+        match unit {
+            Unit::Char(ch) => self.step(state, ch),
+            Unit::Byte(_) => match self.states[state] {
+                NFAState::Class(ref class, next) if class.contains(char::REPLACEMENT_CHARACTER) => {
+                    Some(next)
+                }
+                NFAState::AnyChar(next) => Some(next),
+                _ => None,
+            },
+        }
+    }
+
+    /// Adds `state` and everything reachable from it through epsilon transitions
+    /// and assertions that hold between `prev` and `next`.
+    pub(crate) fn add_state(
+        &self,
+        set: &mut StateSet,
+        state: usize,
+        prev: Option<char>,
+        next: Option<char>,
+    ) {
+        let mut stack = vec![state];
+        while let Some(state) = stack.pop() {
+            if !set.insert(state) {
+                continue;
+            }
+            match &self.states[state] {
+                NFAState::Split(targets) => stack.extend(targets.iter().rev()),
+                NFAState::Assert(assertion, target) if assertion.holds(prev, next) => {
+                    stack.push(*target)
+                }
+                NFAState::Save(_, target) => stack.push(*target),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Split targets for repeating `body` or moving on to `next`, in priority order.
+fn choices(body: usize, next: usize, greedy: bool) -> Vec<usize> {
+    if greedy {
+        vec![body, next]
+    } else {
+        vec![next, body]
+    }
+}
+
+/// An insertion-ordered set of state indices with constant-time membership checks.
+pub(crate) struct StateSet {
+    pub(crate) states: Vec<usize>,
+    contains: Vec<bool>,
+}
+
+impl StateSet {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            states: Vec::with_capacity(capacity),
+            contains: vec![false; capacity],
+        }
+    }
+
+    pub(crate) fn insert(&mut self, state: usize) -> bool {
+        if self.contains[state] {
+            return false;
+        }
+        self.contains[state] = true;
+        self.states.push(state);
+        true
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for &state in &self.states {
+            self.contains[state] = false;
+        }
+        self.states.clear();
+    }
+}
+
+/// One step of byte input: a character, or a byte that is not part of valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unit {
+    Char(char),
+    Byte(u8),
+}
+
+impl Unit {
+    /// The character that stands in for the unit in classes and assertions.
+    fn as_char(self) -> char {
+        match self {
+            Unit::Char(ch) => ch,
+            Unit::Byte(_) => char::REPLACEMENT_CHARACTER,
+        }
+    }
+
+    /// Number of input bytes the unit spans.
+    fn len(self) -> usize {
+        match self {
+            Unit::Char(ch) => ch.len_utf8(),
+            Unit::Byte(_) => 1,
+        }
+    }
+}
+
+/// Splits `input` into characters and stray bytes.
+fn units(input: &[u8]) -> impl Iterator<Item = Unit> + '_ {
+    input.utf8_chunks().flat_map(|chunk| {
+        chunk
+            .valid()
+            .chars()
+            .map(Unit::Char)
+            .chain(chunk.invalid().iter().map(|&byte| Unit::Byte(byte)))
+    })
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Number of times a search started a match attempt at some position.
+    pub(crate) static START_ATTEMPTS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Runs `f`, counting the match attempts it starts.
+#[cfg(test)]
+pub(crate) fn start_attempts<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    START_ATTEMPTS.with(|attempts| attempts.set(0));
+    let result = f();
+    (result, START_ATTEMPTS.with(|attempts| attempts.get()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nfa(pattern: &str) -> NFA {
+        NFA::from_regex(&Parser::new(pattern).parse().unwrap())
+    }
+
+    #[test]
+    fn dot_all_option() {
+        let node = Parser::new("a.b").parse().unwrap();
+        let default = NFA::from_regex(&node);
+        assert!(default.matches("axb"));
+        assert!(!default.matches("a\nb"));
+
+        let options = NfaOptions {
+            dot_all: true,
+            ..NfaOptions::default()
+        };
+        let dot_all = NFA::from_regex_with_options(&node, &options);
+        assert!(dot_all.matches("axb"));
+        assert!(dot_all.matches("a\nb"));
+    }
+
+    #[test]
+    fn case_insensitive_and_anchored_options() {
+        let node = Parser::new("ab").parse().unwrap();
+        let options = NfaOptions {
+            case_insensitive: true,
+            anchored: true,
+            ..NfaOptions::default()
+        };
+        let nfa = NFA::from_regex_with_options(&node, &options);
+        assert!(nfa.matches("ABc"));
+        assert!(!nfa.matches("cAB"));
+    }
+
+    #[test]
+    fn matches_anywhere_unless_whole_line() {
+        let nodes = [
+            Parser::new("fo+").parse().unwrap(),
+            Parser::new("a|bc").parse().unwrap(),
+        ];
+        let anywhere = NFA::from_nodes(&nodes);
+        assert!(anywhere.matches("xfoox"));
+        assert!(anywhere.matches("xbcx"));
+
+        let options = NfaOptions {
+            whole_line: true,
+            ..NfaOptions::default()
+        };
+        let whole = NFA::from_nodes_with_options(&nodes, &options);
+        assert!(whole.matches("fooo"));
+        assert!(whole.matches("bc"));
+        assert!(!whole.matches("xfoox"));
+        assert!(!whole.matches("foox"));
+        // The alternation stays inside the anchors, unlike `^a|bc$`
+        assert!(!whole.matches("ab"));
+        assert!(!whole.matches("abc"));
+        assert_eq!(whole.group_count, 1);
+    }
+
+    #[test]
+    fn inline_case_insensitive() {
+        let whole = nfa("(?i)hello");
+        assert!(whole.matches("HELLO"));
+        assert!(whole.matches("say HeLlO"));
+        assert!(!whole.matches("HELP"));
+
+        let scoped = nfa("^(?i:ab)CD$");
+        assert!(scoped.matches("ABCD"));
+        assert!(scoped.matches("abCD"));
+        assert!(!scoped.matches("abcd"));
+        assert!(!scoped.matches("ABcd"));
+
+        let class = nfa("(?i)[a-c][^x]");
+        assert!(class.matches("Bz"));
+        assert!(!class.matches("BX"));
+        assert!(nfa("(?i)[α-γ]").matches("Β"));
+    }
+
+    #[test]
+    fn inline_multi_line_and_dot_all() {
+        let text = "one\ntwo\nthree";
+        assert!(!nfa("^two$").matches(text));
+        let lines = nfa("(?m)^two$");
+        assert!(lines.matches(text));
+        assert!(!lines.anchored);
+        assert!(!lines.matches("one two\nthree"));
+        assert!(nfa("(?m:e$)\n").matches(text));
+        assert!(!nfa("(?m:e)$\n").matches(text));
+
+        assert!(!nfa("o.t").matches(text));
+        assert!(nfa("(?s)o.t").matches(text));
+        assert!(nfa("(?s:o.)t").matches(text));
+        assert!(!nfa("(?s)o(?-s).t").matches(text));
+
+        // Clearing a flag overrides the matching options
+        let options = NfaOptions {
+            dot_all: true,
+            ..NfaOptions::default()
+        };
+        let compile = |pattern| {
+            NFA::from_regex_with_options(&Parser::new(pattern).parse().unwrap(), &options)
+        };
+        assert!(compile("e.t").matches(text));
+        assert!(!compile("(?-s:e.)t").matches(text));
+    }
+
+    #[test]
+    fn multi_digit_repetition() {
+        let exact = nfa("^a{12}$");
+        assert!(exact.matches(&"a".repeat(12)));
+        assert!(!exact.matches(&"a".repeat(11)));
+        assert!(!exact.matches(&"a".repeat(13)));
+
+        let bounded = nfa("^(ab){2,10}$");
+        assert!(bounded.matches(&"ab".repeat(10)));
+        assert!(!bounded.matches(&"ab".repeat(11)));
+
+        let unbounded = nfa("^x{10,}$");
+        assert!(unbounded.matches(&"x".repeat(500)));
+        assert!(!unbounded.matches(&"x".repeat(9)));
+    }
+
+    #[test]
+    fn bracket_expressions_match() {
+        let lower = nfa("^[a-z]+$");
+        assert!(lower.matches("hello"));
+        assert!(!lower.matches("Hello"));
+
+        let set = nfa("^x[abc]y$");
+        assert!(set.matches("xby"));
+        assert!(!set.matches("xdy"));
+        assert!(!set.matches("xy"));
+
+        let not_digit = nfa("^[^0-9]$");
+        assert!(not_digit.matches("a"));
+        assert!(not_digit.matches("é"));
+        assert!(!not_digit.matches("7"));
+        assert!(!not_digit.matches(""));
+
+        let identifier = nfa("^[[:alpha:]_][[:alnum:]_]*$");
+        assert!(identifier.matches("_tmp9"));
+        assert!(!identifier.matches("9lives"));
+        let punct = nfa("[[:punct:]]");
+        assert!(punct.matches("a,b"));
+        assert!(!punct.matches("a b"));
+    }
+
+    #[test]
+    fn quoted_metacharacters_match_literally() {
+        let quoted = nfa("^\\Qa.b*\\E$");
+        assert!(quoted.matches("a.b*"));
+        assert!(!quoted.matches("axbbb"));
+        assert!(!quoted.matches("a.b"));
+
+        let mixed = nfa("\\Q(x)\\E+");
+        assert!(mixed.matches("(x))))"));
+        assert!(!mixed.matches("x"));
+    }
+
+    #[test]
+    fn large_exact_repeat_stays_linear() {
+        let nfa = nfa("^a{100}$");
+        assert!(nfa.states.len() <= 110, "{} states", nfa.states.len());
+        assert!(nfa.matches(&"a".repeat(100)));
+        assert!(!nfa.matches(&"a".repeat(99)));
+        assert!(!nfa.matches(&"a".repeat(101)));
+    }
+
+    #[test]
+    fn open_repeat_reuses_last_copy_as_loop() {
+        let nfa = nfa("(?:abc){3,}");
+        // three copies of three chars, one loop split, plus save and match states
+        assert_eq!(nfa.states.len(), 3 * 3 + 1 + 3);
+        assert!(nfa.matches("abcabcabc"));
+        assert!(nfa.matches("abcabcabcabc"));
+        assert!(!nfa.matches("abcabc"));
+    }
+
+    #[test]
+    fn zero_repetition_matches_empty() {
+        let nfa = nfa("^a{0}b$");
+        assert!(nfa.matches("b"));
+        assert!(!nfa.matches("ab"));
+        assert!(self::nfa("x(?:)y|()z").matches("xy"));
+        assert!(self::nfa("(a|)b").matches("b"));
+        assert!(self::nfa("").matches(""));
+    }
+
+    #[test]
+    fn empty_node_consumes_no_input() {
+        let node = RegexNode::Concat(vec![
+            RegexNode::Char('a'),
+            RegexNode::Empty,
+            RegexNode::Group(Box::new(RegexNode::Empty), Some(1)),
+            RegexNode::Char('b'),
+        ]);
+        let nfa = NFA::from_regex(&node);
+        assert!(nfa.matches("xab"));
+        assert!(!nfa.matches("a b"));
+        assert_eq!(
+            NFA::from_regex(&RegexNode::Empty).shortest_match("abc"),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn alternation_under_star() {
+        let nfa = nfa("(ab|cd)*e");
+        assert!(nfa.matches("e"));
+        assert!(nfa.matches("abcde"));
+        assert!(nfa.matches("xxcdabe"));
+        assert!(!nfa.matches("abcd"));
+    }
+
+    #[test]
+    fn nested_loops_that_can_match_empty() {
+        // The epsilon closure must terminate even though the inner body can be skipped
+        let star = nfa("^(a*)*b$");
+        assert!(star.matches("b"));
+        assert!(star.matches("aaab"));
+        assert!(!star.matches("aaa"));
+
+        let plus = nfa("^(a|b*)+c$");
+        assert!(plus.matches("c"));
+        assert!(plus.matches("abbac"));
+        assert!(!plus.matches("abd"));
+    }
+
+    #[test]
+    fn matches_anywhere_in_line() {
+        assert!(nfa("foo").matches("xfoox"));
+        assert!(!nfa("foo").matches("fo"));
+        assert!(nfa("^foo").matches("foox"));
+        assert!(!nfa("^foo").matches("xfoo"));
+        assert!(nfa("a\\d+$").matches("xa12"));
+        assert!(!nfa("a\\d+$").matches("a12x"));
+    }
+
+    #[test]
+    fn anchored_pattern_is_only_tried_at_start() {
+        let anchored = nfa("^foo");
+        let line = "x".repeat(100) + "foo";
+        // Offset 0 lacks the literal prefix, so not even one attempt is needed
+        assert_eq!(start_attempts(|| anchored.matches(&line)), (false, 0));
+        assert_eq!(start_attempts(|| anchored.matches("foox")), (true, 1));
+        assert_eq!(
+            start_attempts(|| NFA::from_patterns(&["\\Aa", "^b"]).unwrap().matches("xab")),
+            (false, 1)
+        );
+
+        let unanchored = nfa("x*foo");
+        assert!(start_attempts(|| unanchored.matches(&line)).1 > 1);
+        assert!(NFA::from_patterns(&["^a", "b"]).unwrap().matches("xab"));
+    }
+
+    #[test]
+    fn assertions_take_up_no_input() {
+        assert!(nfa("a\\Bb").matches("xaby"));
+        assert!(nfa("a\\b-").matches("a-"));
+        assert!(!nfa("a\\bb").matches("ab"));
+        assert!(!nfa("a^b").matches("ab"));
+        assert!(!nfa("a$b").matches("ab"));
+
+        let inside_word = nfa("\\Bcat");
+        assert!(inside_word.matches("concat"));
+        assert!(!inside_word.matches("cat"));
+        assert!(!inside_word.matches("a cat"));
+        let not_at_end = nfa("cat\\B");
+        assert!(not_at_end.matches("cats"));
+        assert!(!not_at_end.matches("cat."));
+
+        // Between two non-word characters, or in an empty input, there is no boundary
+        let no_boundary = nfa("^\\B$");
+        assert!(no_boundary.matches(""));
+        assert!(!nfa("\\B").matches("a"));
+        assert!(nfa("\\B").matches("a, b"));
+    }
+
+    #[test]
+    fn matches_bytes_with_invalid_utf8() {
+        let input = b"id=\xff\xfe7 caf\xc3\xa9";
+        assert!(nfa("=..\\d").matches_bytes(input));
+        assert!(nfa("=[^a][^\\w]7").matches_bytes(input));
+        assert!(nfa("café$").matches_bytes(input));
+        assert!(nfa("\\b7\\b").matches_bytes(input));
+        assert!(!nfa("=.7").matches_bytes(input));
+        assert!(!nfa("=\u{fffd}").matches_bytes(input));
+        assert!(nfa("=\u{fffd}").matches_bytes("=\u{fffd}".as_bytes()));
+        assert!(!nfa("a.b").matches_bytes(b"a\nb"));
+        assert!(!nfa("^\\w+$").matches_bytes(b"ab\x80"));
+
+        for pattern in ["^a.c$", "b+", "\\bé", "x|y{2}", "[^x]$"] {
+            let nfa = nfa(pattern);
+            for input in ["abc", "ébb", "yy", "a é", "", "x"] {
+                assert_eq!(
+                    nfa.matches_bytes(input.as_bytes()),
+                    nfa.matches(input),
+                    "{:?} on {:?}",
+                    pattern,
+                    input
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bounded_repeat() {
+        let nfa = nfa("^a{2,3}$");
+        assert!(!nfa.matches("a"));
+        assert!(nfa.matches("aa"));
+        assert!(nfa.matches("aaa"));
+        assert!(!nfa.matches("aaaa"));
+    }
+
+    #[test]
+    fn matching_patterns_reports_each_pattern() {
+        let nfa = NFA::from_patterns(&["foo", "b\\w+", "^qux"]).unwrap();
+        assert_eq!(nfa.matching_patterns("a foo and a bar"), vec![0, 1]);
+        assert_eq!(nfa.matching_patterns("qux foo"), vec![0, 2]);
+        assert_eq!(nfa.matching_patterns("nothing here"), Vec::<usize>::new());
+        assert!(nfa.matches("bz"));
+    }
+
+    #[test]
+    fn trim_removes_unreachable_and_dead_states() {
+        let mut nfa = nfa("a(b|c)d");
+        let original = nfa.clone();
+        // Unreachable from start
+        nfa.states.push(NFAState::Char('x', 0));
+        // Reachable from start but never reaches an accepting state
+        let dead = nfa.states.len();
+        nfa.states.push(NFAState::Char('y', dead));
+        if let NFAState::Split(targets) = &mut nfa.states[nfa.start] {
+            targets.push(dead);
+        } else {
+            let start = nfa.start;
+            nfa.states.push(NFAState::Split(vec![start, dead]));
+            nfa.start = nfa.states.len() - 1;
+        }
+
+        nfa.trim();
+        assert_eq!(nfa.states.len(), original.states.len() + 1);
+        assert!(
+            !nfa.states
+                .iter()
+                .any(|s| matches!(s, NFAState::Char('x' | 'y', _)))
+        );
+        for input in ["abd", "xacdx", "ad", "yyy", ""] {
+            assert_eq!(nfa.matches(input), original.matches(input), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn trim_never_matching_automaton() {
+        let mut nfa = nfa("a");
+        nfa.states[0] = NFAState::Split(Vec::new());
+        nfa.trim();
+        assert_eq!(nfa.states.len(), 1);
+        assert!(!nfa.matches("a"));
+    }
+
+    #[test]
+    fn explain_reports_states_and_anchoring() {
+        let explained = nfa("abc").explain();
+        // Match, two saves, three chars
+        assert!(explained.contains("states: 6\n"), "{}", explained);
+        assert!(explained.contains("anchored: no\n"));
+        assert!(explained.contains("literal prefix: \"abc\"\n"));
+
+        let explained = NFA::from_patterns(&["^(a|b)", "\\Ac"]).unwrap().explain();
+        assert!(explained.contains("patterns: 2\n"));
+        assert!(explained.contains("capture groups: 1\n"));
+        assert!(explained.contains("anchored: yes\n"));
+        assert!(explained.contains("literal prefix: none\n"));
+
+        assert!(!NFA::from_patterns(&["^a", "b"]).unwrap().anchored);
+    }
+
+    #[test]
+    fn literal_prefix_extraction() {
+        assert_eq!(nfa("foo.*bar").prefix, "foo");
+        assert_eq!(nfa("^(ab)+c").prefix, "ab");
+        assert_eq!(nfa("abc|abd").prefix, "ab");
+        assert_eq!(nfa("a?b").prefix, "");
+        assert_eq!(NFA::from_patterns(&["foo", "fob"]).unwrap().prefix, "fo");
+    }
+
+    #[test]
+    fn literal_prefix_scan_agrees_with_full_scan() {
+        let patterns = [
+            "foo.*bar",
+            "^foo",
+            "ab+c",
+            "\\bcat\\b",
+            "\\Bat\\B",
+            "é+x",
+            "abc|abd",
+            "x{2}y",
+        ];
+        let inputs = [
+            "",
+            "foo bar",
+            "xfoo",
+            "fofoo and bar",
+            "aabbbc",
+            "concat cat",
+            "ééx",
+            "zabd",
+            "xxxy",
+            "foo",
+        ];
+        for pattern in patterns {
+            let fast = nfa(pattern);
+            let mut slow = fast.clone();
+            slow.prefix.clear();
+            for input in inputs {
+                assert_eq!(
+                    fast.matches(input),
+                    slow.matches(input),
+                    "{:?} on {:?}",
+                    pattern,
+                    input
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn required_characters_extraction() {
+        let required = |pattern: &str| {
+            let required = nfa(pattern).required;
+            (0..128u8)
+                .filter(|&byte| required >> byte & 1 == 1)
+                .map(char::from)
+                .collect::<String>()
+        };
+        assert_eq!(required("\\w+@\\w+\\.com"), ".@cmo");
+        assert_eq!(required("(ab|cb)x?y+"), "by");
+        assert_eq!(required("a*b{0,2}c{2}"), "c");
+        assert_eq!(required("é[x]"), "x");
+        assert_eq!(required("(?i)k"), "");
+        assert_eq!(required("(?i)k1"), "1");
+        assert_eq!(
+            NFA::from_patterns(&["ab", "bc"]).unwrap().required,
+            1 << b'b'
+        );
+    }
+
+    #[test]
+    fn required_characters_filter_agrees_with_full_match() {
+        let patterns = [
+            "\\w+@\\w+",
+            "(ab|cb)x?y+",
+            "a*b{0,2}c{2}",
+            "(?i)k1",
+            "[^x]z|yz",
+            "a.c",
+        ];
+        let inputs = [
+            "",
+            "me@home",
+            "no at sign",
+            "abyy cbxy",
+            "by",
+            "cc",
+            "bbc",
+            "K1",
+            "k 1",
+            "xz",
+            "yz",
+            "abc",
+            "ac",
+        ];
+        for pattern in patterns {
+            let fast = nfa(pattern);
+            let mut slow = fast.clone();
+            slow.required = 0;
+            for input in inputs {
+                assert_eq!(
+                    fast.matches(input),
+                    slow.matches(input),
+                    "{:?} on {:?}",
+                    pattern,
+                    input
+                );
+                assert_eq!(fast.shortest_match(input), slow.shortest_match(input));
+                assert_eq!(fast.matching_patterns(input), slow.matching_patterns(input));
+            }
+        }
+    }
+
+    #[test]
+    fn long_inputs_use_lazy_dfa() {
+        let padding = "x".repeat(LAZY_DFA_MIN_INPUT);
+        assert!(nfa("fo+ bar$").matches(&format!("{padding}foo bar")));
+        assert!(!nfa("fo+ bar$").matches(&format!("{padding}foo bar!")));
+        assert!(nfa("^x+$").matches(&padding));
+        assert!(!nfa("^ab").matches(&padding));
+    }
+}
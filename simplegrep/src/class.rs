@@ -0,0 +1,390 @@
+use crate::unicode_tables;
+
+/// Characters `char::is_whitespace` accepts, as inclusive ranges.
+const WHITESPACE: &[(char, char)] = &[
+    ('\t', '\r'),
+    (' ', ' '),
+    ('\u{85}', '\u{85}'),
+    ('\u{a0}', '\u{a0}'),
+    ('\u{1680}', '\u{1680}'),
+    ('\u{2000}', '\u{200a}'),
+    ('\u{2028}', '\u{2029}'),
+    ('\u{202f}', '\u{202f}'),
+    ('\u{205f}', '\u{205f}'),
+    ('\u{3000}', '\u{3000}'),
+];
+
+/// Ranges up to this many characters are case folded one character at a time;
+/// longer ones only fold their ASCII letters.
+const MAX_FOLDED_RANGE: u32 = 256;
+
+/// A set of characters stored as sorted, non-overlapping, non-adjacent inclusive
+/// ranges, so equal sets always compare equal and membership is a binary search.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CharClass {
+    ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    /// The empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_ranges(ranges: impl IntoIterator<Item = (char, char)>) -> Self {
+        let mut ranges: Vec<(char, char)> = ranges
+            .into_iter()
+            .filter(|(low, high)| low <= high)
+            .collect();
+        ranges.sort_unstable();
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(ranges.len());
+        for (low, high) in ranges {
+            match merged.last_mut() {
+                Some((_, last)) if after(*last).is_none_or(|next| low <= next) => {
+                    *last = (*last).max(high)
+                }
+                _ => merged.push((low, high)),
+            }
+        }
+        Self { ranges: merged }
+    }
+
+    pub fn char(ch: char) -> Self {
+        Self::range(ch, ch)
+    }
+
+    pub fn range(low: char, high: char) -> Self {
+        Self::from_ranges([(low, high)])
+    }
+
+    /// Every character, as `.` matches with dot-all.
+    pub fn any() -> Self {
+        Self::range('\0', char::MAX)
+    }
+
+    /// `\d`: the ASCII digits.
+    pub fn digit() -> Self {
+        Self::range('0', '9')
+    }
+
+    /// `\w`: ASCII letters, digits and `_`, as `is_word` decides.
+    pub fn word() -> Self {
+        Self::from_ranges([('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')])
+    }
+
+    /// `\s`: everything `char::is_whitespace` accepts.
+    pub fn whitespace() -> Self {
+        Self::from_ranges(WHITESPACE.iter().copied())
+    }
+
+    /// The POSIX class `[:name:]` of a bracket expression, over ASCII as in the C locale.
+    pub fn posix(name: &str) -> Option<Self> {
+        let ranges: &[(char, char)] = match name {
+            "alnum" => &[('0', '9'), ('A', 'Z'), ('a', 'z')],
+            "alpha" => &[('A', 'Z'), ('a', 'z')],
+            "blank" => &[('\t', '\t'), (' ', ' ')],
+            "cntrl" => &[('\0', '\x1f'), ('\x7f', '\x7f')],
+            "digit" => &[('0', '9')],
+            "graph" => &[('!', '~')],
+            "lower" => &[('a', 'z')],
+            "print" => &[(' ', '~')],
+            "punct" => &[('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+            "space" => &[('\t', '\r'), (' ', ' ')],
+            "upper" => &[('A', 'Z')],
+            "xdigit" => &[('0', '9'), ('A', 'F'), ('a', 'f')],
+            _ => return None,
+        };
+        Some(Self::from_ranges(ranges.iter().copied()))
+    }
+
+    /// The Unicode property `\p{name}`: a general category like `Lu` or `Letter`, or
+    /// a script like `Greek`. Names are compared ignoring case, spaces, `_` and `-`.
+    pub fn unicode_property(name: &str) -> Option<Self> {
+        let loose = |name: &str| -> String {
+            name.chars()
+                .filter(|ch| !matches!(ch, ' ' | '_' | '-'))
+                .flat_map(char::to_lowercase)
+                .collect()
+        };
+        let name = loose(name);
+        // The groups of categories sharing a first letter, like `L` for `Lu`, `Ll`, ...
+        let group = match name.as_str() {
+            "l" | "letter" => "L",
+            "lc" | "casedletter" => "LC",
+            "m" | "mark" | "combiningmark" => "M",
+            "n" | "number" => "N",
+            "p" | "punct" | "punctuation" => "P",
+            "s" | "symbol" => "S",
+            "z" | "separator" => "Z",
+            "c" | "other" => "C",
+            _ => "",
+        };
+        let mut ranges = Vec::new();
+        for &(short, long, members) in unicode_tables::GENERAL_CATEGORIES {
+            let in_group = match group {
+                "" => false,
+                "LC" => matches!(short, "Lu" | "Ll" | "Lt"),
+                group => short.starts_with(group),
+            };
+            if in_group || loose(short) == name || loose(long) == name {
+                ranges.extend_from_slice(members);
+            }
+        }
+        if ranges.is_empty() {
+            let (_, members) = unicode_tables::SCRIPTS
+                .iter()
+                .find(|(script, _)| loose(script) == name)?;
+            ranges.extend_from_slice(members);
+        }
+        Some(Self::from_ranges(ranges))
+    }
+
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The only member, if the set has exactly one.
+    pub fn single(&self) -> Option<char> {
+        match self.ranges[..] {
+            [(low, high)] if low == high => Some(low),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, ch: char) -> bool {
+        self.ranges
+            .binary_search_by(|&(low, high)| {
+                if high < ch {
+                    std::cmp::Ordering::Less
+                } else if low > ch {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+
+    pub fn union(&self, other: &CharClass) -> CharClass {
+        Self::from_ranges(self.ranges.iter().chain(&other.ranges).copied())
+    }
+
+    pub fn intersect(&self, other: &CharClass) -> CharClass {
+        self.negate().union(&other.negate()).negate()
+    }
+
+    /// Every character not in the set. Surrogates are not characters, so they
+    /// are never members of either.
+    pub fn negate(&self) -> CharClass {
+        let mut ranges = Vec::with_capacity(self.ranges.len() + 1);
+        let mut next = Some('\0');
+        for &(low, high) in &self.ranges {
+            if let Some(start) = next
+                && start < low
+            {
+                ranges.push((start, before(low).unwrap()));
+            }
+            next = after(high);
+        }
+        if let Some(start) = next {
+            ranges.push((start, char::MAX));
+        }
+        Self { ranges }
+    }
+
+    /// Adds the other-case variants of every member, so case-insensitive matching
+    /// costs nothing at match time.
+    pub fn case_folded(&self) -> CharClass {
+        let mut folded = self.ranges.clone();
+        for &(low, high) in &self.ranges {
+            if high as u32 - low as u32 <= MAX_FOLDED_RANGE {
+                for ch in low..=high {
+                    folded.extend(
+                        other_cases(ch)
+                            .filter(|other| !(low..=high).contains(other))
+                            .map(|other| (other, other)),
+                    );
+                }
+            } else {
+                for (from, to) in [('a', 'A'), ('A', 'a')] {
+                    let start = low.max(from);
+                    let end = high.min((from as u8 + 25) as char);
+                    if start <= end {
+                        let shift = |ch: char| (ch as u8 - from as u8 + to as u8) as char;
+                        folded.push((shift(start), shift(end)));
+                    }
+                }
+            }
+        }
+        Self::from_ranges(folded)
+    }
+}
+
+/// The next character after `ch`, skipping the surrogate gap.
+fn after(ch: char) -> Option<char> {
+    match ch {
+        '\u{d7ff}' => Some('\u{e000}'),
+        ch => char::from_u32(ch as u32 + 1),
+    }
+}
+
+/// The character before `ch`, skipping the surrogate gap.
+fn before(ch: char) -> Option<char> {
+    match ch {
+        '\u{e000}' => Some('\u{d7ff}'),
+        ch => char::from_u32((ch as u32).checked_sub(1)?),
+    }
+}
+
+/// The single-character case variants of `ch`, other than `ch` itself.
+fn other_cases(ch: char) -> impl Iterator<Item = char> {
+    fn single(mut chars: impl Iterator<Item = char>) -> Option<char> {
+        let first = chars.next();
+        if chars.next().is_none() { first } else { None }
+    }
+    [single(ch.to_lowercase()), single(ch.to_uppercase())]
+        .into_iter()
+        .flatten()
+        .filter(move |&other| other != ch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::is_word;
+
+    #[test]
+    fn ranges_are_normalized() {
+        let class = CharClass::from_ranges([('m', 'p'), ('a', 'c'), ('d', 'f'), ('n', 'z')]);
+        assert_eq!(class.ranges(), [('a', 'f'), ('m', 'z')]);
+        assert_eq!(CharClass::from_ranges([('z', 'a')]), CharClass::new());
+        assert_eq!(
+            CharClass::from_ranges([('a', '\u{d7ff}'), ('\u{e000}', '\u{e001}')]).ranges(),
+            [('a', '\u{e001}')]
+        );
+    }
+
+    #[test]
+    fn union() {
+        let class = CharClass::digit().union(&CharClass::char('x'));
+        assert_eq!(class.ranges(), [('0', '9'), ('x', 'x')]);
+        assert_eq!(
+            CharClass::range('a', 'm').union(&CharClass::range('h', 'z')),
+            CharClass::range('a', 'z')
+        );
+        assert_eq!(
+            CharClass::digit().union(&CharClass::range('A', 'Z')),
+            CharClass::word()
+                .intersect(&CharClass::char('_').negate())
+                .intersect(&CharClass::range('a', 'z').negate())
+        );
+    }
+
+    #[test]
+    fn negation() {
+        let class = CharClass::range('b', 'y').negate();
+        assert_eq!(class.ranges(), [('\0', 'a'), ('z', char::MAX)]);
+        assert!(class.contains('a') && class.contains('é') && class.contains(char::MAX));
+        assert!(!class.contains('b') && !class.contains('m'));
+        assert_eq!(class.negate(), CharClass::range('b', 'y'));
+        assert_eq!(CharClass::new().negate(), CharClass::any());
+        assert_eq!(CharClass::any().negate(), CharClass::new());
+    }
+
+    #[test]
+    fn membership() {
+        let word = CharClass::word();
+        let space = CharClass::whitespace();
+        for ch in [
+            'a',
+            'Z',
+            '5',
+            '_',
+            ' ',
+            '\t',
+            '-',
+            'é',
+            '\u{3000}',
+            '\u{10ffff}',
+        ] {
+            assert_eq!(word.contains(ch), is_word(ch), "{ch:?}");
+            assert_eq!(space.contains(ch), ch.is_whitespace(), "{ch:?}");
+        }
+        assert!(CharClass::char('é').contains('é'));
+        assert!(!CharClass::char('é').contains('e'));
+        assert_eq!(CharClass::char('é').single(), Some('é'));
+    }
+
+    #[test]
+    fn posix_classes_match_ascii_predicates() {
+        type Predicate = fn(&char) -> bool;
+        let predicates: [(&str, Predicate); 12] = [
+            ("alnum", char::is_ascii_alphanumeric),
+            ("alpha", char::is_ascii_alphabetic),
+            ("blank", |ch| *ch == ' ' || *ch == '\t'),
+            ("cntrl", char::is_ascii_control),
+            ("digit", char::is_ascii_digit),
+            ("graph", char::is_ascii_graphic),
+            ("lower", char::is_ascii_lowercase),
+            ("print", |ch| ch.is_ascii_graphic() || *ch == ' '),
+            ("punct", char::is_ascii_punctuation),
+            ("space", |ch| ch.is_ascii_whitespace() || *ch == '\u{b}'),
+            ("upper", char::is_ascii_uppercase),
+            ("xdigit", char::is_ascii_hexdigit),
+        ];
+        for (name, predicate) in predicates {
+            let class = CharClass::posix(name).unwrap();
+            for ch in (0..=0xff).map(char::from) {
+                assert_eq!(class.contains(ch), predicate(&ch), "[:{name}:] {ch:?}");
+            }
+        }
+        assert_eq!(CharClass::posix("word"), None);
+    }
+
+    #[test]
+    fn unicode_properties() {
+        let property = |name| CharClass::unicode_property(name).unwrap();
+        assert!(property("Lu").contains('É'));
+        assert!(!property("Lu").contains('é'));
+        assert_eq!(property("Lu"), property("uppercase letter"));
+        assert_eq!(property("lu"), property("Uppercase-Letter"));
+        assert_eq!(
+            property("LC"),
+            property("Lu").union(&property("Ll")).union(&property("Lt"))
+        );
+
+        let letter = property("L");
+        assert_eq!(letter, property("Letter"));
+        for ch in ['a', 'Ж', 'ß', 'ǅ', 'ʰ', '中'] {
+            assert!(letter.contains(ch), "{ch:?}");
+        }
+        for ch in ['1', ' ', '-', '\u{301}'] {
+            assert!(!letter.contains(ch), "{ch:?}");
+        }
+        assert!(property("Nd").contains('٣'));
+        assert!(property("Sc").contains('€'));
+        assert!(property("Zs").contains('\u{3000}'));
+
+        let greek = property("greek");
+        assert!(greek.contains('λ') && greek.contains('Ω'));
+        assert!(!greek.contains('w'));
+        assert!(property("Han").contains('中'));
+        assert!(property("Cyrillic").contains('ж'));
+        assert_eq!(CharClass::unicode_property("Klingon"), None);
+    }
+
+    #[test]
+    fn case_folding() {
+        assert_eq!(
+            CharClass::char('k').case_folded().ranges(),
+            [('K', 'K'), ('k', 'k')]
+        );
+        assert!(CharClass::range('α', 'γ').case_folded().contains('Β'));
+        let wide = CharClass::range('a', '\u{2000}').case_folded();
+        assert!(wide.contains('A') && wide.contains('Z') && !wide.contains('@'));
+    }
+}
@@ -0,0 +1,580 @@
+use crate::ast::RegexNode;
+use crate::automaton::{self, Assertion, NfaOptions};
+use crate::class::CharClass;
+
+/// One instruction of a compiled [`Program`]. Instructions that consume a
+/// character or hold continue at the next one unless they say otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Char(char),
+    /// Any member of the set; negated bracket expressions are complemented when compiled.
+    Class(CharClass),
+    /// Any character at all, for `.` under `(?s)`.
+    Any,
+    Assert(Assertion),
+    /// Continues at both targets, preferring the first.
+    Split(usize, usize),
+    Jmp(usize),
+    /// Records the current position in the given capture slot.
+    Save(usize),
+    Match,
+}
+
+/// Patterns lowered to bytecode for the Pike VM in [`Program::captures_from`], which
+/// `Regex` uses to find matches and their groups.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub insts: Vec<Inst>,
+    /// Number of groups per match, counting the whole match as group 0.
+    pub group_count: usize,
+    /// Whether every match must begin at the start of the input.
+    pub anchored: bool,
+    pub options: NfaOptions,
+}
+
+impl Program {
+    /// Compiles `node`, wrapped in the saves for capture group 0.
+    pub fn compile(node: &RegexNode, options: &NfaOptions) -> Self {
+        Self::compile_all(std::slice::from_ref(node), options)
+    }
+
+    /// Compiles several patterns as alternatives of one program, the first one
+    /// taking priority, like `NFA::from_nodes_with_options` does.
+    pub fn compile_all(nodes: &[RegexNode], options: &NfaOptions) -> Self {
+        let nodes: Vec<RegexNode> = nodes
+            .iter()
+            .map(|node| automaton::prepare(node, options))
+            .collect();
+        let mut program = Self {
+            insts: Vec::new(),
+            group_count: 1,
+            anchored: options.anchored
+                || (!options.multi_line
+                    && !nodes.is_empty()
+                    && nodes.iter().all(RegexNode::is_anchored)),
+            options: *options,
+        };
+        let Some((last, rest)) = nodes.split_last() else {
+            // No patterns, so nothing can match
+            program.push(Inst::Class(CharClass::new()));
+            return program;
+        };
+        for node in rest {
+            let split = program.push(Inst::Split(0, 0));
+            program.emit_pattern(node);
+            program.insts[split] = Inst::Split(split + 1, program.insts.len());
+        }
+        program.emit_pattern(last);
+        program
+    }
+
+    fn emit_pattern(&mut self, node: &RegexNode) {
+        self.push(Inst::Save(0));
+        self.emit(node);
+        self.push(Inst::Save(1));
+        self.push(Inst::Match);
+    }
+
+    fn push(&mut self, inst: Inst) -> usize {
+        self.insts.push(inst);
+        self.insts.len() - 1
+    }
+
+    /// Appends the code for `node`, which falls through to whatever comes next.
+    fn emit(&mut self, node: &RegexNode) {
+        match node {
+            RegexNode::Empty => {}
+            RegexNode::Char(ch) if self.options.case_insensitive => {
+                let class = CharClass::char(*ch).case_folded();
+                match class.single() {
+                    Some(ch) => self.push(Inst::Char(ch)),
+                    None => self.push(Inst::Class(class)),
+                };
+            }
+            RegexNode::Char(ch) => {
+                self.push(Inst::Char(*ch));
+            }
+            RegexNode::AnyChar if self.options.dot_all => {
+                self.push(Inst::Any);
+            }
+            RegexNode::AnyChar => {
+                self.push(Inst::Class(CharClass::char('\n').negate()));
+            }
+            RegexNode::Digit => {
+                self.push(Inst::Class(CharClass::digit()));
+            }
+            RegexNode::WordChar => {
+                self.push(Inst::Class(CharClass::word()));
+            }
+            RegexNode::Whitespace => {
+                self.push(Inst::Class(CharClass::whitespace()));
+            }
+            RegexNode::CharClass { class, negated } => {
+                let class = if self.options.case_insensitive {
+                    class.case_folded()
+                } else {
+                    class.clone()
+                };
+                let class = if *negated { class.negate() } else { class };
+                self.push(Inst::Class(class));
+            }
+            RegexNode::Concat(nodes) => nodes.iter().for_each(|node| self.emit(node)),
+            RegexNode::Alternation(nodes) => self.emit_alternation(nodes),
+            RegexNode::Lazy(node) => self.emit_quantifier(node, false),
+            RegexNode::Star(_)
+            | RegexNode::Plus(_)
+            | RegexNode::Question(_)
+            | RegexNode::Repeat(..) => self.emit_quantifier(node, true),
+            RegexNode::Group(node, None) => self.emit(node),
+            RegexNode::Group(node, Some(index)) => {
+                self.group_count = self.group_count.max(index + 1);
+                self.push(Inst::Save(2 * index));
+                self.emit(node);
+                self.push(Inst::Save(2 * index + 1));
+            }
+            RegexNode::Flags(node, flags) => {
+                // Same scoping as `NFA::build_from_node`
+                let outer = self.options;
+                self.options.multi_line = flags.multi_line.unwrap_or(outer.multi_line);
+                self.options.dot_all = flags.dot_all.unwrap_or(outer.dot_all);
+                self.options.case_insensitive =
+                    flags.case_insensitive.unwrap_or(outer.case_insensitive);
+                self.emit(node);
+                self.options = outer;
+            }
+            RegexNode::StartLine if self.options.multi_line => {
+                self.push(Inst::Assert(Assertion::MultiLineStart));
+            }
+            RegexNode::EndLine if self.options.multi_line => {
+                self.push(Inst::Assert(Assertion::MultiLineEnd));
+            }
+            RegexNode::StartLine => {
+                self.push(Inst::Assert(Assertion::StartLine));
+            }
+            RegexNode::EndLine => {
+                self.push(Inst::Assert(Assertion::EndLine));
+            }
+            RegexNode::StartInput => {
+                self.push(Inst::Assert(Assertion::StartInput));
+            }
+            RegexNode::EndInput => {
+                self.push(Inst::Assert(Assertion::EndInput));
+            }
+            RegexNode::WordBoundary => {
+                self.push(Inst::Assert(Assertion::WordBoundary));
+            }
+            RegexNode::NotWordBoundary => {
+                self.push(Inst::Assert(Assertion::NotWordBoundary));
+            }
+        }
+    }
+
+    /// `a|b|c` becomes `split L1, L2; L1: a; jmp end; L2: split L3, L4; ...`.
+    fn emit_alternation(&mut self, nodes: &[RegexNode]) {
+        let Some((last, rest)) = nodes.split_last() else {
+            // No alternatives, so nothing can match
+            self.push(Inst::Class(CharClass::new()));
+            return;
+        };
+        let mut jumps = Vec::with_capacity(rest.len());
+        for node in rest {
+            let split = self.push(Inst::Split(0, 0));
+            self.emit(node);
+            jumps.push(self.push(Inst::Jmp(0)));
+            self.insts[split] = Inst::Split(split + 1, self.insts.len());
+        }
+        self.emit(last);
+        let end = self.insts.len();
+        for jump in jumps {
+            self.insts[jump] = Inst::Jmp(end);
+        }
+    }
+
+    /// Greedy quantifiers prefer another repetition, lazy ones prefer moving on;
+    /// only the order of the split targets differs.
+    fn emit_quantifier(&mut self, node: &RegexNode, greedy: bool) {
+        match node {
+            RegexNode::Star(node) => self.emit_star(node, greedy),
+            RegexNode::Plus(node) => self.emit_plus(node, greedy),
+            RegexNode::Question(node) => {
+                let split = self.push(Inst::Split(0, 0));
+                self.emit(node);
+                self.insts[split] = choices(split + 1, self.insts.len(), greedy);
+            }
+            RegexNode::Repeat(node, range) => match range.max {
+                Some(max) => {
+                    for _ in 0..range.min {
+                        self.emit(node);
+                    }
+                    // Every optional copy may skip straight past the rest, as in
+                    // `x(x(x)?)?`, so they nest like in the NFA
+                    let splits: Vec<usize> = (range.min..max)
+                        .map(|_| {
+                            let split = self.push(Inst::Split(0, 0));
+                            self.emit(node);
+                            split
+                        })
+                        .collect();
+                    let end = self.insts.len();
+                    for split in splits {
+                        self.insts[split] = choices(split + 1, end, greedy);
+                    }
+                }
+                None if range.min > 0 => {
+                    for _ in 1..range.min {
+                        self.emit(node);
+                    }
+                    self.emit_plus(node, greedy);
+                }
+                None => self.emit_star(node, greedy),
+            },
+            // The parser only makes quantifiers lazy
+            other => self.emit(other),
+        }
+    }
+
+    /// `x*` becomes `L1: split L2, L3; L2: x; jmp L1; L3:`.
+    fn emit_star(&mut self, node: &RegexNode, greedy: bool) {
+        let split = self.push(Inst::Split(0, 0));
+        self.emit(node);
+        self.push(Inst::Jmp(split));
+        self.insts[split] = choices(split + 1, self.insts.len(), greedy);
+    }
+
+    /// `x+` becomes `L1: x; split L1, L2; L2:`.
+    fn emit_plus(&mut self, node: &RegexNode, greedy: bool) {
+        let body = self.insts.len();
+        self.emit(node);
+        let split = self.push(Inst::Split(0, 0));
+        self.insts[split] = choices(body, split + 1, greedy);
+    }
+
+    /// Finds the leftmost match starting at or after byte offset `start` and returns
+    /// its capture slots (`2 * group` and `2 * group + 1` hold each group's span).
+    ///
+    /// Threads are kept in priority order, so earlier alternatives and greedy
+    /// repetition win as in backtracking engines. Every thread advances in step over
+    /// the input and at most one is kept per instruction, so the time taken is linear
+    /// in the input however ambiguous the pattern. With `leftmost_longest` set, the
+    /// longest match from the leftmost start wins instead.
+    pub fn captures_from(&self, input: &str, start: usize) -> Option<Vec<Option<usize>>> {
+        let mut current = Threads::new(self.insts.len());
+        let mut next = Threads::new(self.insts.len());
+        let mut matched = None;
+
+        let mut pos = start;
+        let mut prev = input[..start].chars().next_back();
+        loop {
+            let may_start = if self.options.anchored {
+                pos == start
+            } else {
+                !self.anchored || pos == 0
+            };
+            if matched.is_none() && current.list.is_empty() && !may_start {
+                // Every thread died and no later position can begin a match
+                break;
+            }
+            let ch = input[pos..].chars().next();
+            if matched.is_none() && may_start {
+                #[cfg(test)]
+                automaton::START_ATTEMPTS.with(|attempts| attempts.set(attempts.get() + 1));
+                let slots = vec![None; 2 * self.group_count];
+                self.add_thread(&mut current, 0, slots, pos, prev, ch);
+            }
+
+            let following = ch.and_then(|ch| input[pos + ch.len_utf8()..].chars().next());
+            for (pc, slots) in current.list.drain(..) {
+                if self.insts[pc] == Inst::Match {
+                    if !self.options.leftmost_longest {
+                        // Lower-priority threads can no longer win
+                        matched = Some(slots);
+                        break;
+                    }
+                    // Leftmost start first, then the latest end
+                    if matched.as_ref().is_none_or(|best: &Vec<Option<usize>>| {
+                        (slots[0], best[1]) < (best[0], slots[1])
+                    }) {
+                        matched = Some(slots);
+                    }
+                    continue;
+                }
+                if let Some(best) = &matched
+                    && slots[0] > best[0]
+                {
+                    // Started right of the best match, so it cannot beat it
+                    continue;
+                }
+                if let Some(ch) = ch
+                    && self.step(pc, ch)
+                {
+                    let pos = pos + ch.len_utf8();
+                    self.add_thread(&mut next, pc + 1, slots, pos, Some(ch), following);
+                }
+            }
+
+            let Some(ch) = ch else { break };
+            if next.list.is_empty() && matched.is_some() {
+                break;
+            }
+            pos += ch.len_utf8();
+            prev = Some(ch);
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+
+        matched
+    }
+
+    /// Follows jumps, splits, saves and assertions from `pc` in priority order,
+    /// appending the resulting consuming and accepting threads to `threads`.
+    fn add_thread(
+        &self,
+        threads: &mut Threads,
+        pc: usize,
+        slots: Vec<Option<usize>>,
+        pos: usize,
+        prev: Option<char>,
+        next: Option<char>,
+    ) {
+        let mut stack = vec![(pc, slots)];
+        while let Some((pc, mut slots)) = stack.pop() {
+            if std::mem::replace(&mut threads.seen[pc], true) {
+                continue;
+            }
+            match self.insts[pc] {
+                Inst::Jmp(target) => stack.push((target, slots)),
+                Inst::Split(first, second) => {
+                    stack.push((second, slots.clone()));
+                    stack.push((first, slots));
+                }
+                Inst::Assert(assertion) => {
+                    if assertion.holds(prev, next) {
+                        stack.push((pc + 1, slots));
+                    }
+                }
+                Inst::Save(slot) => {
+                    slots[slot] = Some(pos);
+                    stack.push((pc + 1, slots));
+                }
+                _ => threads.list.push((pc, slots)),
+            }
+        }
+    }
+
+    /// Whether the instruction at `pc` consumes `ch`.
+    fn step(&self, pc: usize, ch: char) -> bool {
+        match &self.insts[pc] {
+            Inst::Char(expected) => *expected == ch,
+            Inst::Class(class) => class.contains(ch),
+            Inst::Any => true,
+            _ => false,
+        }
+    }
+}
+
+/// The split for repeating the code at `body` or moving on to `next`, in priority order.
+fn choices(body: usize, next: usize, greedy: bool) -> Inst {
+    if greedy {
+        Inst::Split(body, next)
+    } else {
+        Inst::Split(next, body)
+    }
+}
+
+/// Pike VM threads in priority order, each with its own capture slots.
+struct Threads {
+    list: Vec<(usize, Vec<Option<usize>>)>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(capacity: usize) -> Self {
+        Self {
+            list: Vec::new(),
+            seen: vec![false; capacity],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.seen.fill(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn program(pattern: &str) -> Program {
+        Program::compile(
+            &Parser::new(pattern).parse().unwrap(),
+            &NfaOptions::default(),
+        )
+    }
+
+    #[test]
+    fn lowers_to_bytecode() {
+        assert_eq!(
+            program("a|bc").insts,
+            vec![
+                Inst::Save(0),
+                Inst::Split(2, 4),
+                Inst::Char('a'),
+                Inst::Jmp(6),
+                Inst::Char('b'),
+                Inst::Char('c'),
+                Inst::Save(1),
+                Inst::Match,
+            ]
+        );
+        assert_eq!(
+            program("(a)*?").insts,
+            vec![
+                Inst::Save(0),
+                Inst::Split(6, 2),
+                Inst::Save(2),
+                Inst::Char('a'),
+                Inst::Save(3),
+                Inst::Jmp(1),
+                Inst::Save(1),
+                Inst::Match,
+            ]
+        );
+        assert_eq!(
+            program("a{1,2}").insts,
+            vec![
+                Inst::Save(0),
+                Inst::Char('a'),
+                Inst::Split(3, 4),
+                Inst::Char('a'),
+                Inst::Save(1),
+                Inst::Match,
+            ]
+        );
+    }
+
+    #[test]
+    fn captures_follow_priority() {
+        let cases: [(&str, &str, &[usize]); 8] = [
+            ("(a|ab)(c|bcd)(d*)", "abcd", &[0, 4, 0, 1, 1, 4, 4, 4]),
+            ("(a+?)(a*)", "aaaa", &[0, 4, 0, 1, 1, 4]),
+            ("(a){2,3}?(a*)", "aaaa", &[0, 4, 1, 2, 2, 4]),
+            ("^(?:(a)|b)+$", "abab", &[0, 4, 2, 3]),
+            ("(?m)^(b)$", "a\nb\n", &[2, 3, 2, 3]),
+            ("é(.)", "éé\n", &[0, 4, 2, 4]),
+            ("x(\\w+)\\b", "xabc def", &[0, 4, 1, 4]),
+            ("(?i)h(?-i:E)llo", "hello HElLo", &[6, 11]),
+        ];
+        for (pattern, input, expected) in cases {
+            let expected: Vec<_> = expected.iter().copied().map(Some).collect();
+            assert_eq!(
+                program(pattern).captures_from(input, 0),
+                Some(expected),
+                "{pattern} on {input:?}"
+            );
+        }
+
+        let node = RegexNode::Concat(vec![
+            RegexNode::Char('a'),
+            RegexNode::Empty,
+            RegexNode::Group(Box::new(RegexNode::Empty), Some(1)),
+            RegexNode::Char('b'),
+        ]);
+        assert_eq!(
+            Program::compile(&node, &NfaOptions::default()).captures_from("xab", 0),
+            Some(vec![Some(1), Some(3), Some(2), Some(2)])
+        );
+    }
+
+    #[test]
+    fn several_patterns_in_priority_order() {
+        let nodes = |patterns: &[&str]| -> Vec<RegexNode> {
+            patterns
+                .iter()
+                .map(|pattern| Parser::new(pattern).parse().unwrap())
+                .collect()
+        };
+        let options = NfaOptions::default();
+        let program = Program::compile_all(&nodes(&["b+", "a"]), &options);
+        assert_eq!(
+            program.captures_from("xab", 0),
+            Some(vec![Some(1), Some(2)])
+        );
+        let program = Program::compile_all(&nodes(&["a", "ab"]), &options);
+        assert_eq!(program.captures_from("ab", 0), Some(vec![Some(0), Some(1)]));
+        let program = Program::compile_all(&nodes(&["(a)", "(b)(c)"]), &options);
+        assert_eq!(program.group_count, 3);
+        assert_eq!(
+            program.captures_from("bc", 0),
+            Some(vec![Some(0), Some(2), Some(0), Some(1), Some(1), Some(2)])
+        );
+        assert_eq!(
+            Program::compile_all(&[], &options).captures_from("a", 0),
+            None
+        );
+    }
+
+    #[test]
+    fn anchored_program_is_only_tried_at_start() {
+        let anchored = program("^foo");
+        let line = "x".repeat(100) + "foo";
+        assert_eq!(
+            automaton::start_attempts(|| anchored.captures_from(&line, 0)),
+            (None, 1)
+        );
+        assert_eq!(
+            automaton::start_attempts(|| anchored.captures_from(&line, 5)),
+            (None, 0)
+        );
+
+        let options = NfaOptions {
+            case_insensitive: true,
+            anchored: true,
+            ..NfaOptions::default()
+        };
+        let program = Program::compile(&Parser::new("ab").parse().unwrap(), &options);
+        assert_eq!(
+            program.captures_from("xab", 1),
+            Some(vec![Some(1), Some(3)])
+        );
+        assert_eq!(program.captures_from("xxab", 1), None);
+    }
+
+    #[test]
+    fn options_apply_to_the_program() {
+        let node = Parser::new("(?-i:a)b").parse().unwrap();
+        let options = NfaOptions {
+            case_insensitive: true,
+            whole_line: true,
+            ..NfaOptions::default()
+        };
+        let program = Program::compile(&node, &options);
+        assert_eq!(program.captures_from("aB", 0), Some(vec![Some(0), Some(2)]));
+        assert_eq!(program.captures_from("AB", 0), None);
+        assert_eq!(program.captures_from("xaB", 0), None);
+
+        let options = NfaOptions {
+            leftmost_longest: true,
+            ..NfaOptions::default()
+        };
+        let program = Program::compile(&Parser::new("a|ab").parse().unwrap(), &options);
+        assert_eq!(
+            program.captures_from("xab", 0),
+            Some(vec![Some(1), Some(3)])
+        );
+    }
+
+    #[test]
+    fn linear_on_input_that_makes_backtracking_explode() {
+        let input = "x".repeat(5000);
+        let program = program("(x+x+)+y");
+        assert_eq!(program.captures_from(&input, 0), None);
+
+        let input = format!("{input}y");
+        assert_eq!(
+            program.captures_from(&input, 0),
+            Some(vec![Some(0), Some(5001), Some(0), Some(5000)])
+        );
+    }
+}
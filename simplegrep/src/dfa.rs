@@ -0,0 +1,291 @@
+use crate::automaton::{NFA, NFAState, StateSet};
+use crate::lazy_dfa::Prev;
+use std::collections::{BTreeSet, HashMap};
+
+/// Number of states `Dfa::new` builds before giving up on a pattern.
+pub const DEFAULT_MAX_STATES: usize = 4096;
+
+/// Largest transition table, in entries, built before giving up on a pattern.
+const MAX_TABLE_SIZE: usize = 1 << 22;
+
+/// State every input leads to once all threads have died.
+const DEAD: usize = 0;
+/// State every input leads to once a match has been seen.
+const MATCHED: usize = 1;
+/// The start state, followed by the states found from it.
+const START: usize = 2;
+
+/// Identifies a DFA state: the NFA states reached before following epsilon
+/// transitions, sorted, and the kind of character consumed to get there.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    pending: Box<[usize]>,
+    prev: Prev,
+}
+
+/// A DFA built up front by subset construction, so matching a line costs one
+/// table lookup per character and no allocation.
+///
+/// Characters are grouped into classes that no state of the NFA tells apart (and
+/// that agree on being word characters or `\n`, which assertions look at), and the
+/// table has one column per class. Building stops with `None` when the pattern needs
+/// more than `max_states` states, as patterns like `a[ab]{20}$` do; `LazyDfa` or
+/// plain NFA simulation handle those instead.
+pub struct Dfa<'n> {
+    nfa: &'n NFA,
+    /// The first character of each class, ascending, starting at `'\0'`.
+    class_starts: Vec<char>,
+    ascii_classes: [usize; 128],
+    /// `table[state * class_count + class]` is the state after a character of `class`.
+    table: Vec<usize>,
+    /// Whether a match ends at the end of the input in each state.
+    accepts_at_end: Vec<bool>,
+}
+
+impl<'n> Dfa<'n> {
+    pub fn new(nfa: &'n NFA) -> Option<Self> {
+        Self::with_max_states(nfa, DEFAULT_MAX_STATES)
+    }
+
+    pub fn with_max_states(nfa: &'n NFA, max_states: usize) -> Option<Self> {
+        let class_starts = class_starts(nfa);
+        let class_count = class_starts.len();
+        let mut ascii_classes = [0; 128];
+        for (byte, class) in ascii_classes.iter_mut().enumerate() {
+            *class = class_of(&class_starts, char::from(byte as u8));
+        }
+        let anchored = nfa.anchored || nfa.options.anchored;
+
+        let mut table = vec![DEAD; START * class_count];
+        table[MATCHED * class_count..].fill(MATCHED);
+        let mut accepts_at_end = vec![false, true];
+        let mut closure = StateSet::new(nfa.states.len());
+
+        // Keys of the states from `START` on, in order of discovery
+        let mut keys = vec![Key {
+            pending: Box::new([nfa.start]),
+            prev: Prev::Start,
+        }];
+        let mut ids: HashMap<Key, usize> = HashMap::from([(keys[0].clone(), START)]);
+
+        let mut index = 0;
+        while index < keys.len() {
+            let state_count = START + keys.len();
+            if state_count > max_states || state_count * class_count > MAX_TABLE_SIZE {
+                return None;
+            }
+            let key = keys[index].clone();
+            accepts_at_end.push(accepts(nfa, &mut closure, &key, None));
+
+            for &representative in &class_starts {
+                if accepts(nfa, &mut closure, &key, Some(representative)) {
+                    table.push(MATCHED);
+                    continue;
+                }
+                let mut pending: Vec<usize> = closure
+                    .states
+                    .iter()
+                    .filter_map(|&state| nfa.step(state, representative))
+                    .collect();
+                if !anchored {
+                    pending.push(nfa.start);
+                }
+                if pending.is_empty() {
+                    table.push(DEAD);
+                    continue;
+                }
+                pending.sort_unstable();
+                pending.dedup();
+
+                let target = Key {
+                    pending: pending.into_boxed_slice(),
+                    prev: Prev::of(representative),
+                };
+                let target = *ids.entry(target.clone()).or_insert_with(|| {
+                    keys.push(target);
+                    START + keys.len() - 1
+                });
+                table.push(target);
+            }
+            index += 1;
+        }
+
+        Some(Self {
+            nfa,
+            class_starts,
+            ascii_classes,
+            table,
+            accepts_at_end,
+        })
+    }
+
+    /// Returns true if any pattern matches somewhere in `input`, like `NFA::matches`.
+    pub fn is_match(&self, input: &str) -> bool {
+        if !self.nfa.has_required(input.as_bytes()) {
+            return false;
+        }
+        let class_count = self.class_starts.len();
+        let mut state = START;
+        for ch in input.chars() {
+            let class = match ch {
+                ch if ch.is_ascii() => self.ascii_classes[ch as usize],
+                ch => class_of(&self.class_starts, ch),
+            };
+            state = self.table[state * class_count + class];
+            if state == MATCHED {
+                return true;
+            }
+            if state == DEAD {
+                return false;
+            }
+        }
+        self.accepts_at_end[state]
+    }
+
+    /// Number of states, counting the fixed dead and matched states.
+    pub fn state_count(&self) -> usize {
+        self.accepts_at_end.len()
+    }
+}
+
+/// Fills `closure` for `key` followed by `next` and reports whether it accepts.
+fn accepts(nfa: &NFA, closure: &mut StateSet, key: &Key, next: Option<char>) -> bool {
+    closure.clear();
+    for &state in key.pending.iter() {
+        nfa.add_state(closure, state, key.prev.representative(), next);
+    }
+    closure
+        .states
+        .iter()
+        .any(|&state| matches!(nfa.states[state], NFAState::Match(_)))
+}
+
+/// Splits the characters into classes that every state of `nfa` treats alike.
+fn class_starts(nfa: &NFA) -> Vec<char> {
+    // Word characters and `\n` are split off too, since assertions tell them apart
+    let mut starts: BTreeSet<char> = ['\0', '\n', '\u{b}', '0', ':', 'A', '[', '_', '`', 'a', '{']
+        .into_iter()
+        .collect();
+    let mut add_range = |low: char, high: char| {
+        starts.insert(low);
+        if let Some(after) = char_after(high) {
+            starts.insert(after);
+        }
+    };
+    for state in &nfa.states {
+        match state {
+            NFAState::Char(ch, _) => add_range(*ch, *ch),
+            NFAState::Class(class, _) => {
+                for &(low, high) in class.ranges() {
+                    add_range(low, high);
+                }
+            }
+            _ => {}
+        }
+    }
+    starts.into_iter().collect()
+}
+
+fn char_after(ch: char) -> Option<char> {
+    match ch {
+        '\u{d7ff}' => Some('\u{e000}'),
+        ch => char::from_u32(ch as u32 + 1),
+    }
+}
+
+fn class_of(class_starts: &[char], ch: char) -> usize {
+    class_starts.partition_point(|&start| start <= ch) - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automaton::NfaOptions;
+    use crate::parser::Parser;
+
+    fn nfa(pattern: &str) -> NFA {
+        NFA::from_patterns(&[pattern]).unwrap()
+    }
+
+    #[test]
+    fn agrees_with_nfa_simulation() {
+        let patterns = [
+            "abc",
+            "a(b|c)*d",
+            "^ab",
+            "ab$",
+            "\\bword\\b",
+            "\\Bor\\B",
+            "x{2,3}y",
+            "[^a-c]+z",
+            "(a|ab)(c|bcd)",
+            "é.ü",
+            "^$",
+            "a*",
+            "(?m)^b$",
+            "(?s)a.b",
+            "\\w+@\\w+",
+        ];
+        let inputs = [
+            "",
+            "abc",
+            "xabcx",
+            "abbcbd",
+            "ab",
+            "cab",
+            "a word here",
+            "swordfish",
+            "xxy",
+            "xy",
+            "dddz",
+            "abcd",
+            "héllo éxü",
+            "aaaa",
+            "a\nb\nc",
+            "ab\n",
+            "me@home",
+            "@",
+        ];
+        for pattern in patterns {
+            let nfa = nfa(pattern);
+            let dfa = Dfa::new(&nfa).unwrap();
+            for input in inputs {
+                assert_eq!(
+                    dfa.is_match(input),
+                    nfa.matches(input),
+                    "{pattern:?} on {input:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn several_patterns_and_options() {
+        let nfa = NFA::from_patterns(&["^cat", "dog$"]).unwrap();
+        let dfa = Dfa::new(&nfa).unwrap();
+        assert!(dfa.is_match("catalog"));
+        assert!(dfa.is_match("hotdog"));
+        assert!(!dfa.is_match("dogcat"));
+
+        let options = NfaOptions {
+            anchored: true,
+            ..NfaOptions::default()
+        };
+        let node = Parser::new("b").parse().unwrap();
+        let nfa = NFA::from_regex_with_options(&node, &options);
+        let dfa = Dfa::new(&nfa).unwrap();
+        assert!(dfa.is_match("bc"));
+        assert!(!dfa.is_match("ab"));
+    }
+
+    #[test]
+    fn gives_up_on_too_many_states() {
+        // The n-th character from the end being `a` needs 2^n states
+        let nfa = nfa("a[ab]{8}$");
+        assert!(Dfa::with_max_states(&nfa, 64).is_none());
+        let dfa = Dfa::with_max_states(&nfa, 1024).unwrap();
+        assert!(dfa.state_count() > 256);
+        assert!(dfa.is_match("xabbbbbbbb"));
+        assert!(!dfa.is_match("abbbbbbbbb"));
+    }
+}
@@ -0,0 +1,428 @@
+use crate::ast::is_word;
+use crate::automaton::{NFA, NFAState, StateSet};
+use std::collections::HashMap;
+
+/// Number of DFA states kept before the least recently used ones are evicted.
+pub const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
+/// A search gives up on the DFA once it has evicted more than a cache's worth of
+/// states while averaging fewer than this many characters per eviction.
+const MIN_CHARS_PER_EVICTION: usize = 8;
+
+/// What the assertions can observe about the character before a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Prev {
+    Start,
+    Word,
+    /// A `\n`, which multi-line `^` looks for.
+    Newline,
+    Other,
+}
+
+impl Prev {
+    pub(crate) fn of(ch: char) -> Self {
+        match ch {
+            '\n' => Prev::Newline,
+            ch if is_word(ch) => Prev::Word,
+            _ => Prev::Other,
+        }
+    }
+
+    /// A character standing in for the whole class when evaluating assertions.
+    pub(crate) fn representative(self) -> Option<char> {
+        match self {
+            Prev::Start => None,
+            Prev::Word => Some('a'),
+            Prev::Newline => Some('\n'),
+            Prev::Other => Some(' '),
+        }
+    }
+}
+
+/// Identifies a DFA state: the NFA states reached before following epsilon
+/// transitions, sorted, and the kind of character consumed to get there.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    pending: Box<[usize]>,
+    prev: Prev,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Transition {
+    /// Whether a match ends just before the character.
+    matched: bool,
+    target: usize,
+    /// Generation of the target's slot when the transition was cached; if the slot
+    /// has since been reused for another state, the transition is stale.
+    generation: u32,
+}
+
+struct DfaState {
+    key: Key,
+    ascii: Box<[Option<Transition>; 128]>,
+    other: HashMap<char, Transition>,
+    /// Tick of the last time a transition was taken from this state.
+    last_used: u64,
+    /// Times this slot has held a state.
+    generation: u32,
+}
+
+/// A DFA built on demand while matching, one state per distinct set of active NFA
+/// states, so each input character costs a table lookup once its transition is known.
+///
+/// The cache holds at most `capacity` states. When it is full the least recently used
+/// state is evicted and recomputed if it is reached again, which bounds memory on
+/// patterns whose full DFA would be exponentially large. If a search keeps evicting
+/// states it has only just built, the cache is thrashing and `is_match` finishes
+/// with plain NFA simulation instead.
+pub struct LazyDfa<'n> {
+    nfa: &'n NFA,
+    capacity: usize,
+    states: Vec<DfaState>,
+    ids: HashMap<Key, usize>,
+    closure: StateSet,
+    /// Cached ids of the idle state after each kind of character.
+    idle: [Option<usize>; 4],
+    tick: u64,
+    /// States evicted so far, for tests and tuning.
+    pub evictions: usize,
+    /// Searches that gave up on the DFA because the cache thrashed.
+    pub fallbacks: usize,
+}
+
+impl<'n> LazyDfa<'n> {
+    pub fn new(nfa: &'n NFA) -> Self {
+        Self::with_capacity(nfa, DEFAULT_CACHE_CAPACITY)
+    }
+
+    pub fn with_capacity(nfa: &'n NFA, capacity: usize) -> Self {
+        Self {
+            nfa,
+            // The state being left must never be the one evicted
+            capacity: capacity.max(2),
+            states: Vec::new(),
+            ids: HashMap::new(),
+            closure: StateSet::new(nfa.states.len()),
+            idle: [None; 4],
+            tick: 0,
+            evictions: 0,
+            fallbacks: 0,
+        }
+    }
+
+    /// Returns true if any pattern matches somewhere in `input`, like `NFA::matches`.
+    ///
+    /// States built for one input are kept for the next, so keep one `LazyDfa` for
+    /// all the lines of a search rather than one per line.
+    pub fn is_match(&mut self, input: &str) -> bool {
+        if !self.nfa.has_required(input.as_bytes()) {
+            return false;
+        }
+        self.try_is_match(input).unwrap_or_else(|| {
+            self.fallbacks += 1;
+            self.nfa.simulate(input)
+        })
+    }
+
+    /// Like `is_match`, but returns `None` if the cache starts thrashing.
+    pub fn try_is_match(&mut self, input: &str) -> Option<bool> {
+        let nfa = self.nfa;
+        let evictions = self.evictions;
+        let mut current = self.idle_state(Prev::Start);
+        let mut pos = 0;
+        loop {
+            if !nfa.prefix.is_empty() && !self.anchored() && self.is_idle(current) {
+                // Nothing in flight, so jump straight to the next candidate start
+                let Some(offset) = input[pos..].find(&nfa.prefix) else {
+                    return Some(false);
+                };
+                if offset > 0 {
+                    pos += offset;
+                    let prev = input[..pos]
+                        .chars()
+                        .next_back()
+                        .map_or(Prev::Start, Prev::of);
+                    current = self.idle_state(prev);
+                }
+            }
+
+            let Some(ch) = input[pos..].chars().next() else {
+                return Some(self.closure_matches(current, None));
+            };
+            let transition = self.transition(current, ch);
+            if transition.matched {
+                return Some(true);
+            }
+            current = transition.target;
+            if self.states[current].key.pending.is_empty() {
+                // Anchored and every thread died
+                return Some(false);
+            }
+            pos += ch.len_utf8();
+
+            let evicted = self.evictions - evictions;
+            if evicted > self.capacity && evicted * MIN_CHARS_PER_EVICTION > pos {
+                return None;
+            }
+        }
+    }
+
+    /// Number of DFA states currently cached.
+    pub fn cached_states(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Whether matches can only start at the beginning of the input.
+    fn anchored(&self) -> bool {
+        self.nfa.anchored || self.nfa.options.anchored
+    }
+
+    /// The state with no match in progress, only the start state waiting.
+    fn idle_state(&mut self, prev: Prev) -> usize {
+        if let Some(id) = self.idle[prev as usize] {
+            return id;
+        }
+        let id = self.intern(Key {
+            pending: Box::new([self.nfa.start]),
+            prev,
+        });
+        self.idle[prev as usize] = Some(id);
+        id
+    }
+
+    fn is_idle(&self, id: usize) -> bool {
+        *self.states[id].key.pending == [self.nfa.start]
+    }
+
+    fn transition(&mut self, id: usize, ch: char) -> Transition {
+        self.tick += 1;
+        let state = &mut self.states[id];
+        state.last_used = self.tick;
+        let cached = match ch {
+            ch if ch.is_ascii() => state.ascii[ch as usize],
+            ch => state.other.get(&ch).copied(),
+        };
+        if let Some(transition) = cached
+            && self.states[transition.target].generation == transition.generation
+        {
+            return transition;
+        }
+
+        let matched = self.closure_matches(id, Some(ch));
+        let nfa = self.nfa;
+        let mut pending: Vec<usize> = self
+            .closure
+            .states
+            .iter()
+            .filter_map(|&state| nfa.step(state, ch))
+            .collect();
+        if !self.anchored() {
+            pending.push(nfa.start);
+        }
+        pending.sort_unstable();
+        pending.dedup();
+
+        let target = self.intern(Key {
+            pending: pending.into_boxed_slice(),
+            prev: Prev::of(ch),
+        });
+        let transition = Transition {
+            matched,
+            target,
+            generation: self.states[target].generation,
+        };
+        let state = &mut self.states[id];
+        if ch.is_ascii() {
+            state.ascii[ch as usize] = Some(transition);
+        } else {
+            state.other.insert(ch, transition);
+        }
+        transition
+    }
+
+    /// Fills `closure` for state `id` followed by `next` and reports whether it accepts.
+    fn closure_matches(&mut self, id: usize, next: Option<char>) -> bool {
+        let key = &self.states[id].key;
+        self.closure.clear();
+        for &state in key.pending.iter() {
+            self.nfa
+                .add_state(&mut self.closure, state, key.prev.representative(), next);
+        }
+        self.closure
+            .states
+            .iter()
+            .any(|&state| matches!(self.nfa.states[state], NFAState::Match(_)))
+    }
+
+    fn intern(&mut self, key: Key) -> usize {
+        if let Some(&id) = self.ids.get(&key) {
+            return id;
+        }
+        let state = DfaState {
+            key: key.clone(),
+            ascii: Box::new([None; 128]),
+            other: HashMap::new(),
+            last_used: self.tick,
+            generation: 0,
+        };
+        if self.states.len() < self.capacity {
+            self.ids.insert(key, self.states.len());
+            self.states.push(state);
+            return self.states.len() - 1;
+        }
+
+        let id = (0..self.states.len())
+            .min_by_key(|&id| self.states[id].last_used)
+            .expect("the cache holds at least two states");
+        let evicted = std::mem::replace(&mut self.states[id], state);
+        self.states[id].generation = evicted.generation.wrapping_add(1);
+        self.ids.remove(&evicted.key);
+        self.ids.insert(key, id);
+        for idle in &mut self.idle {
+            if *idle == Some(id) {
+                *idle = None;
+            }
+        }
+        self.evictions += 1;
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nfa(pattern: &str) -> NFA {
+        NFA::from_patterns(&[pattern]).unwrap()
+    }
+
+    #[test]
+    fn agrees_with_nfa_simulation() {
+        let patterns = [
+            "abc",
+            "a(b|c)*d",
+            "^ab",
+            "ab$",
+            "\\bword\\b",
+            "\\Bor\\B",
+            "x{2,3}y",
+            "[^a-c]+z",
+            "(a|ab)(c|bcd)",
+            "é.ü",
+            "^$",
+            "a*",
+            "(?m)^b$",
+            "(?s)a.b",
+        ];
+        let inputs = [
+            "",
+            "abc",
+            "xabcx",
+            "abbcbd",
+            "ab",
+            "cab",
+            "a word here",
+            "swordfish",
+            "xxy",
+            "xy",
+            "dddz",
+            "abcd",
+            "héllo éxü",
+            "aaaa",
+            "a\nb\nc",
+            "ab\n",
+        ];
+        for pattern in patterns {
+            let nfa = nfa(pattern);
+            let mut dfa = LazyDfa::new(&nfa);
+            for input in inputs {
+                assert_eq!(
+                    dfa.is_match(input),
+                    nfa.matches(input),
+                    "{pattern:?} on {input:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn states_carry_over_between_inputs() {
+        let nfa = nfa("a(b|c)*d");
+        let mut dfa = LazyDfa::new(&nfa);
+        assert!(dfa.is_match("xabcbdx"));
+        let built = dfa.cached_states();
+        assert!(dfa.is_match("acbd"));
+        assert!(!dfa.is_match("xabcx"));
+        assert_eq!(dfa.cached_states(), built);
+
+        // Lines missing a required character build nothing
+        let mut fresh = LazyDfa::new(&nfa);
+        assert!(!fresh.is_match("abcabc"));
+        assert_eq!(fresh.cached_states(), 0);
+    }
+
+    #[test]
+    fn tiny_cache_evicts_but_stays_correct() {
+        // The n-th character from the end being `a` needs 2^n DFA states
+        let nfa = nfa("a[ab]{8}$");
+        let mut dfa = LazyDfa::with_capacity(&nfa, 16);
+        let input: String = (0..200)
+            .map(|i: u32| {
+                if i.count_ones().is_multiple_of(2) {
+                    'a'
+                } else {
+                    'b'
+                }
+            })
+            .collect();
+        for end in 0..input.len() {
+            let line = &input[..end];
+            assert_eq!(dfa.is_match(line), nfa.matches(line), "{line:?}");
+        }
+        assert!(dfa.evictions > 0);
+        assert!(dfa.cached_states() <= 16);
+    }
+
+    #[test]
+    fn anchored_pattern_stops_when_threads_die() {
+        let nfa = nfa("^ab");
+        let mut dfa = LazyDfa::new(&nfa);
+        assert!(!dfa.is_match(&"x".repeat(10_000)));
+        assert!(dfa.cached_states() <= 2);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_state() {
+        let mut nfa = nfa("^abc");
+        // `ab` lacks the `c`, which would otherwise reject it before the DFA runs
+        nfa.required = 0;
+        let mut dfa = LazyDfa::with_capacity(&nfa, 3);
+        assert!(!dfa.is_match("ab"));
+        assert!(!dfa.is_match("ab"));
+        assert_eq!((dfa.cached_states(), dfa.evictions), (3, 0));
+
+        // A fourth state evicts the start state, which was left longest ago
+        assert!(dfa.is_match("abc"));
+        assert_eq!(dfa.evictions, 1);
+        assert!(dfa.ids.keys().all(|key| key.prev != Prev::Start));
+        assert!(dfa.is_match("abc"));
+    }
+
+    #[test]
+    fn thrashing_cache_falls_back_to_simulation() {
+        let nfa = nfa("a[ab]{8}$");
+        let line: String = (0..2000)
+            .map(|i: u32| if i % 7 < 3 { 'a' } else { 'b' })
+            .collect();
+
+        let mut dfa = LazyDfa::with_capacity(&nfa, 4);
+        assert!(dfa.try_is_match(&line).is_none());
+        assert_eq!(dfa.is_match(&line), nfa.simulate(&line));
+        assert_eq!(dfa.fallbacks, 1);
+
+        // With room for the whole DFA nothing is evicted
+        let mut roomy = LazyDfa::new(&nfa);
+        assert_eq!(roomy.try_is_match(&line), Some(nfa.simulate(&line)));
+        assert_eq!(roomy.evictions, 0);
+    }
+}
@@ -0,0 +1,16 @@
+//! A small regular expression engine backing the `sg` command line tool.
+
+pub mod ast;
+pub mod automaton;
+pub mod class;
+pub mod compiler;
+pub mod dfa;
+pub mod lazy_dfa;
+pub mod parser;
+pub mod regex;
+pub mod search;
+pub mod stream;
+pub mod tokens;
+pub mod unicode_tables;
+
+pub use regex::{Captures, Match, Regex};
@@ -1,63 +1,764 @@
-use clap::Parser;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
-
-mod ast;
-mod automaton;
-mod parser;
-mod tokens;
+use clap::{Parser, ValueEnum};
+use sg::automaton::{self, NfaOptions};
+use sg::dfa::Dfa;
+use sg::lazy_dfa::LazyDfa;
+use sg::{Regex, parser, search};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 #[derive(Parser)]
 #[command(name = "simplegrep")]
 #[command(about = "A custom regular expression engine with grep-like CLI")]
 struct Cli {
+    /// Pattern to search for; repeat to match lines containing any of them
+    #[arg(short, long, required = true)]
+    pattern: Vec<String>,
+
+    /// File to search; repeat to search several (standard input if none)
     #[arg(short, long)]
-    pattern: String,
+    file: Vec<String>,
+
+    /// Select only lines that the pattern matches in full, not just in part
+    #[arg(short = 'x', long)]
+    line_regexp: bool,
+
+    /// Select the lines that do not match
+    #[arg(short = 'v', long)]
+    invert_match: bool,
 
+    /// Match letters regardless of case
     #[arg(short, long)]
-    file: Option<String>,
+    ignore_case: bool,
+
+    /// Let ^ and $ also match just after and before each newline within a record, as (?m) does
+    #[arg(long)]
+    multi_line: bool,
+
+    /// Match lines that are not valid UTF-8 byte by byte, so `.` and classes see each
+    /// invalid byte and U+FFFD does not match it; such lines are still printed with
+    /// U+FFFD in place of the invalid bytes
+    #[arg(
+        long,
+        conflicts_with_all = ["only_matching", "column", "count_matches", "replace"]
+    )]
+    binary: bool,
+
+    /// Read and print records ending in a NUL byte instead of lines, so one record may span lines
+    #[arg(short = 'z', long, conflicts_with = "in_place")]
+    null_data: bool,
+
+    /// Prefix each printed line with its 1-based line number
+    #[arg(short = 'n', long)]
+    line_number: bool,
+
+    /// Prefix each selected line with the 1-based byte column of its first match
+    #[arg(long)]
+    column: bool,
+
+    /// Print only the matched parts of selected lines, each on its own line
+    #[arg(short, long, conflicts_with_all = ["invert_match", "replace"])]
+    only_matching: bool,
+
+    /// Print NUM lines of context after each selected line
+    #[arg(
+        short = 'A',
+        long,
+        value_name = "NUM",
+        conflicts_with = "only_matching"
+    )]
+    after_context: Option<usize>,
+
+    /// Print NUM lines of context before each selected line
+    #[arg(
+        short = 'B',
+        long,
+        value_name = "NUM",
+        conflicts_with = "only_matching"
+    )]
+    before_context: Option<usize>,
+
+    /// Print NUM lines of context before and after each selected line; -A and -B override it
+    #[arg(
+        short = 'C',
+        long,
+        value_name = "NUM",
+        conflicts_with = "only_matching"
+    )]
+    context: Option<usize>,
+
+    /// Print the number of selected lines in each file instead of the lines themselves,
+    /// and the total if there are several files
+    #[arg(short, long, conflicts_with = "replace")]
+    count: bool,
+
+    /// Stop reading after NUM selected lines
+    #[arg(short, long, value_name = "NUM")]
+    max_count: Option<usize>,
 
+    /// Print the names of the files with a selected line instead of any lines
+    #[arg(
+        short = 'l',
+        long,
+        conflicts_with_all = ["count", "count_matches", "quiet", "replace", "files_without_match"]
+    )]
+    files_with_matches: bool,
+
+    /// Print the names of the files with no selected line instead of any lines
+    #[arg(
+        short = 'L',
+        long,
+        conflicts_with_all = ["count", "count_matches", "quiet", "replace"]
+    )]
+    files_without_match: bool,
+
+    /// Print nothing; exit with status 0 if any line matched and 1 otherwise
     #[arg(short, long)]
-    invert_match: bool,
+    quiet: bool,
+
+    /// Expand tabs in printed lines to spaces, with tab stops every N columns (default 8)
+    #[arg(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        default_missing_value = "8",
+        require_equals = true,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    expand_tabs: Option<usize>,
+
+    /// Print the number of matches in each file instead of the matching lines, counting
+    /// every match on a line, and the total if there are several files
+    #[arg(long, conflicts_with_all = ["invert_match", "replace", "count"])]
+    count_matches: bool,
+
+    /// Print a summary of the compiled automaton instead of searching
+    #[arg(long)]
+    explain: bool,
+
+    /// Prefer the longest match from the leftmost start, as POSIX does, instead of
+    /// the first alternative that matches (`a|ab` finds `ab`, not `a`)
+    #[arg(long)]
+    posix: bool,
+
+    /// Reject escapes with no meaning, such as \q, instead of matching them literally
+    #[arg(long)]
+    strict: bool,
+
+    /// Print matching lines with each match replaced by TEMPLATE ($1, ${1} and $$ are expanded)
+    #[arg(long, value_name = "TEMPLATE")]
+    replace: Option<String>,
+
+    /// Rewrite the input file with the replacements applied, keeping a backup if SUFFIX is given
+    #[arg(
+        long,
+        value_name = "SUFFIX",
+        num_args = 0..=1,
+        default_missing_value = "",
+        require_equals = true,
+        requires = "replace"
+    )]
+    in_place: Option<String>,
+
+    /// Highlight matches, file names and line numbers: always, never, or auto to do
+    /// so only on a terminal and when NO_COLOR is not set
+    #[arg(
+        long,
+        alias = "colour",
+        value_name = "WHEN",
+        value_enum,
+        default_value_t = ColorChoice::Auto,
+        num_args = 0..=1,
+        default_missing_value = "auto",
+        require_equals = true
+    )]
+    color: ColorChoice,
 }
 
-fn main() -> io::Result<()> {
-    let cli = Cli::parse();
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+/// Exit status for usage, parse and I/O errors, as in grep.
+const EXIT_ERROR: u8 = 2;
+
+/// Escape sequences for the highlighted parts of the output, in grep's default colors.
+const MATCH_COLOR: &str = "\x1b[1;31m";
+const FILENAME_COLOR: &str = "\x1b[35m";
+const LINE_NUMBER_COLOR: &str = "\x1b[32m";
+const SEPARATOR_COLOR: &str = "\x1b[36m";
+const RESET_COLOR: &str = "\x1b[0m";
 
-    // Parse the regular expression
-    let mut parser = parser::Parser::new(&cli.pattern);
-    let regex_ast = match parser.parse() {
-        Ok(ast) => ast,
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let color = match cli.color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            io::stdout().is_terminal()
+                && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        }
+    };
+    match run(
+        &cli,
+        color,
+        &mut io::stdin().lock(),
+        &mut io::stdout().lock(),
+    ) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
         Err(e) => {
-            eprintln!("Error parsing regex: {}", e);
-            std::process::exit(1);
+            eprintln!("Error: {}", e);
+            ExitCode::from(EXIT_ERROR)
         }
+    }
+}
+
+/// Runs the search over `stdin` (unless a file is given) and writes the results to
+/// `out`, highlighted if `color` is set. Returns whether any line was selected (or
+/// replaced); invalid patterns and options are `InvalidInput` errors.
+fn run(cli: &Cli, color: bool, stdin: &mut dyn BufRead, out: &mut dyn Write) -> io::Result<bool> {
+    // Compile all patterns into one automaton
+    let nodes = cli
+        .pattern
+        .iter()
+        .map(|pattern| parser::Parser::new(pattern).strict(cli.strict).parse())
+        .collect::<Result<Vec<_>, _>>();
+    let options = NfaOptions {
+        case_insensitive: cli.ignore_case,
+        leftmost_longest: cli.posix,
+        multi_line: cli.multi_line,
+        whole_line: cli.line_regexp,
+        ..NfaOptions::default()
     };
+    let nodes = nodes.map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("invalid regex: {}", e))
+    })?;
+    let nfa = automaton::NFA::from_nodes_with_options(&nodes, &options);
 
-    // Build NFA from AST
-    let nfa = automaton::NFA::from_regex(&regex_ast);
+    if cli.explain {
+        write!(out, "{}", nfa.explain())?;
+        return Ok(true);
+    }
 
-    // Read input
-    let input: Box<dyn BufRead> = if let Some(filename) = &cli.file {
-        Box::new(BufReader::new(File::open(filename)?))
+    if cli.replace.is_some() && cli.pattern.len() != 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--replace takes a single pattern",
+        ));
+    }
+    let replacer = cli.replace.as_ref().map(|template| {
+        // Already parsed successfully above
+        (
+            Regex::with_options(&cli.pattern[0], &options).unwrap(),
+            template.as_str(),
+        )
+    });
+
+    if let (Some(suffix), Some((regex, template))) = (&cli.in_place, &replacer) {
+        if cli.file.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--in-place needs an input file; standard input cannot be edited",
+            ));
+        }
+        let mut replaced = false;
+        for filename in &cli.file {
+            replaced |= replace_in_place(Path::new(filename), regex, template, suffix)?;
+        }
+        return Ok(replaced);
+    }
+
+    let terminator = if cli.null_data { b'\0' } else { b'\n' };
+
+    // Standard input stands in when no file is named
+    let filenames: Vec<Option<&str>> = if cli.file.is_empty() {
+        vec![None]
     } else {
-        Box::new(io::stdin().lock())
+        cli.file
+            .iter()
+            .map(|filename| Some(filename.as_str()))
+            .collect()
     };
 
-    // Process lines
-    for (line_num, line) in input.lines().enumerate() {
-        let line = line?;
-        let matches = nfa.matches(&line);
+    if cli.quiet {
+        for &filename in &filenames {
+            if search::any_match(
+                open(filename, stdin)?,
+                terminator,
+                &nfa,
+                cli.invert_match,
+                cli.binary,
+            )? {
+                return Ok(true);
+            }
+        }
+        return Ok(false);
+    }
+
+    if cli.files_with_matches || cli.files_without_match {
+        // A file is decided by its first selected line, so no file is read further
+        let mut listed = false;
+        for &filename in &filenames {
+            let selected = search::any_match(
+                open(filename, stdin)?,
+                terminator,
+                &nfa,
+                cli.invert_match,
+                cli.binary,
+            )?;
+            if selected == cli.files_with_matches {
+                let filename = filename.unwrap_or("(standard input)");
+                writeln!(out, "{}", paint(color, FILENAME_COLOR, filename))?;
+                listed = true;
+            }
+        }
+        return Ok(listed);
+    }
 
-        let should_print = if cli.invert_match { !matches } else { matches };
+    if cli.count_matches {
+        let regex = Regex::from_nodes(&nodes, &options);
+        let mut total = 0;
+        for &filename in &filenames {
+            let mut in_file = 0;
+            for line in decoded_lines(open(filename, stdin)?, filename, terminator, false) {
+                in_file += regex.find_iter(&line?.text).count();
+            }
+            write_count(out, filename, in_file, color)?;
+            total += in_file;
+        }
+        if filenames.len() > 1 {
+            writeln!(out, "total:{}", total)?;
+        }
+        return Ok(total > 0);
+    }
 
-        if should_print {
-            if cli.file.is_some() {
-                println!("{}:{}", cli.file.as_ref().unwrap(), line_num + 1);
+    // Lines only need a yes or no, which a DFA answers fastest when the pattern allows one.
+    // Otherwise a lazy DFA builds the states the lines need, keeping them for later lines
+    let dfa = Dfa::new(&nfa);
+    let mut lazy_dfa = LazyDfa::new(&nfa);
+    let finder =
+        (cli.only_matching || cli.column || color).then(|| Regex::from_nodes(&nodes, &options));
+
+    // Process lines, stopping each file after `max_count` selected lines
+    let max_count = cli.max_count.unwrap_or(usize::MAX);
+    let before = cli.before_context.or(cli.context).unwrap_or(0);
+    let after = cli.after_context.or(cli.context).unwrap_or(0);
+    let context = before > 0 || after > 0;
+    // File index and line number of the last line printed, to separate groups with `--`
+    let mut last_printed = None;
+    let mut selected = 0;
+    for (file_index, &filename) in filenames.iter().enumerate() {
+        let mut selected_in_file = 0;
+        // Unprinted lines that may turn out to be before-context, oldest first
+        let mut recent: VecDeque<(usize, String)> = VecDeque::with_capacity(before);
+        let mut after_left = 0;
+        for (line_num, line) in
+            decoded_lines(open(filename, stdin)?, filename, terminator, cli.binary).enumerate()
+        {
+            // Past `max_count`, only the after-context of the last selected line is printed
+            let max_reached = selected_in_file == max_count;
+            if max_reached && after_left == 0 {
+                break;
+            }
+            let search::Line { text: line, raw } = line?;
+            let is_selected = !max_reached && {
+                let matches = match (&raw, &dfa) {
+                    (Some(raw), _) if cli.binary => nfa.matches_bytes(raw),
+                    (_, Some(dfa)) => dfa.is_match(&line),
+                    (_, None) => lazy_dfa.is_match(&line),
+                };
+                matches != cli.invert_match
+            };
+            if !is_selected {
+                if after_left > 0 {
+                    after_left -= 1;
+                    write_group_separator(out, &mut last_printed, (file_index, line_num), color)?;
+                    write_context(out, cli, color, terminator, filename, line_num, &line)?;
+                } else if before > 0 {
+                    if recent.len() == before {
+                        recent.pop_front();
+                    }
+                    recent.push_back((line_num, line));
+                }
+                continue;
+            }
+            selected_in_file += 1;
+            if cli.count {
+                continue;
+            }
+            // Empty matches have nothing to show or highlight, so with -o a line with
+            // only those prints nothing
+            let spans: Vec<(usize, usize)> = match &finder {
+                Some(regex) if !cli.invert_match => regex
+                    .find_iter(&line)
+                    .filter(|found| found.start() < found.end())
+                    .map(|found| (found.start(), found.end()))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            if cli.only_matching && spans.is_empty() {
+                continue;
+            }
+            for (context_num, context_line) in recent.drain(..) {
+                write_group_separator(out, &mut last_printed, (file_index, context_num), color)?;
+                write_context(
+                    out,
+                    cli,
+                    color,
+                    terminator,
+                    filename,
+                    context_num,
+                    &context_line,
+                )?;
             }
-            println!("{}", line);
+            if context {
+                write_group_separator(out, &mut last_printed, (file_index, line_num), color)?;
+                after_left = after;
+            }
+            let line_number = cli.line_number.then_some(line_num + 1);
+            if cli.only_matching {
+                for (start, end) in spans {
+                    let column = cli.column.then_some(start + 1);
+                    write_prefix(out, filename, line_number, column, ":", color)?;
+                    let whole = [(0, end - start)];
+                    let highlight: &[_] = if color { &whole } else { &[] };
+                    write_record(
+                        out,
+                        &line[start..end],
+                        highlight,
+                        cli.expand_tabs,
+                        terminator,
+                    )?;
+                }
+                continue;
+            }
+            let column = match &finder {
+                Some(regex) if cli.column => regex.find(&line).map(|found| found.start() + 1),
+                _ => None,
+            };
+            write_prefix(out, filename, line_number, column, ":", color)?;
+            match &replacer {
+                Some((regex, template)) => {
+                    let replaced = regex.replace_all(&line, template);
+                    write_record(out, &replaced, &[], cli.expand_tabs, terminator)?;
+                }
+                None => {
+                    let highlight: &[_] = if color { &spans } else { &[] };
+                    write_record(out, &line, highlight, cli.expand_tabs, terminator)?;
+                }
+            }
+        }
+        if cli.count {
+            write_count(out, filename, selected_in_file, color)?;
         }
+        selected += selected_in_file;
     }
 
+    if cli.count && filenames.len() > 1 {
+        writeln!(out, "total:{}", selected)?;
+    }
+    Ok(selected > 0)
+}
+
+/// Opens the named file, or reads `stdin` when there is no name.
+fn open<'a>(
+    filename: Option<&str>,
+    stdin: &'a mut dyn BufRead,
+) -> io::Result<Box<dyn BufRead + 'a>> {
+    Ok(match filename {
+        Some(filename) => Box::new(BufReader::new(File::open(filename)?)),
+        None => Box::new(stdin),
+    })
+}
+
+/// Writes what precedes a printed line, as in `file:12:5:`: the file name when files
+/// are named, then the line number and column if given, each followed by `separator`,
+/// which is `:` for selected lines and `-` for context.
+fn write_prefix(
+    out: &mut dyn Write,
+    filename: Option<&str>,
+    line_number: Option<usize>,
+    column: Option<usize>,
+    separator: &str,
+    color: bool,
+) -> io::Result<()> {
+    let separator = paint(color, SEPARATOR_COLOR, separator);
+    if let Some(filename) = filename {
+        write!(
+            out,
+            "{}{}",
+            paint(color, FILENAME_COLOR, filename),
+            separator
+        )?;
+    }
+    for number in [line_number, column].into_iter().flatten() {
+        write!(
+            out,
+            "{}{}",
+            paint(color, LINE_NUMBER_COLOR, number),
+            separator
+        )?;
+    }
     Ok(())
 }
+
+/// Writes the count for one file, after its name if files are named.
+fn write_count(
+    out: &mut dyn Write,
+    filename: Option<&str>,
+    count: usize,
+    color: bool,
+) -> io::Result<()> {
+    write_prefix(out, filename, None, None, ":", color)?;
+    writeln!(out, "{}", count)
+}
+
+/// Writes a line printed as context around a selected one, with no highlighting.
+fn write_context(
+    out: &mut dyn Write,
+    cli: &Cli,
+    color: bool,
+    terminator: u8,
+    filename: Option<&str>,
+    line_num: usize,
+    line: &str,
+) -> io::Result<()> {
+    let line_number = cli.line_number.then_some(line_num + 1);
+    write_prefix(out, filename, line_number, None, "-", color)?;
+    write_record(out, line, &[], cli.expand_tabs, terminator)
+}
+
+/// Records that the line at `position` (file index, line number) is about to be
+/// printed, first writing `--` if it does not follow the last printed line.
+fn write_group_separator(
+    out: &mut dyn Write,
+    last_printed: &mut Option<(usize, usize)>,
+    position: (usize, usize),
+    color: bool,
+) -> io::Result<()> {
+    if let Some((file_index, line_num)) = *last_printed
+        && (file_index, line_num + 1) != position
+    {
+        writeln!(out, "{}", paint(color, SEPARATOR_COLOR, "--"))?;
+    }
+    *last_printed = Some(position);
+    Ok(())
+}
+
+/// Wraps `text` in the escape sequences for `code` if `color` is set.
+fn paint(color: bool, code: &str, text: impl std::fmt::Display) -> String {
+    if color {
+        format!("{}{}{}", code, text, RESET_COLOR)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Writes one line, or record, of output followed by `terminator`, with the byte
+/// spans in `highlight` (sorted, not overlapping) in the match color, expanding tabs
+/// to `expand_tabs` columns if given.
+fn write_record(
+    out: &mut dyn Write,
+    text: &str,
+    highlight: &[(usize, usize)],
+    expand_tabs: Option<usize>,
+    terminator: u8,
+) -> io::Result<()> {
+    // Tab stops count columns from the start of the line, across highlighted spans
+    let mut column = 0;
+    let mut write_plain = |out: &mut dyn Write, text: &str| match expand_tabs {
+        Some(width) => write!(out, "{}", self::expand_tabs(text, width, &mut column)),
+        None => write!(out, "{}", text),
+    };
+    let mut written = 0;
+    for &(start, end) in highlight {
+        write_plain(out, &text[written..start])?;
+        write!(out, "{}", MATCH_COLOR)?;
+        write_plain(out, &text[start..end])?;
+        write!(out, "{}", RESET_COLOR)?;
+        written = end;
+    }
+    write_plain(out, &text[written..])?;
+    write!(out, "{}", char::from(terminator))
+}
+
+/// Replaces each tab with spaces up to the next multiple of `width` columns, with
+/// `text` starting at `column`, which is advanced past it.
+fn expand_tabs<'t>(text: &'t str, width: usize, column: &mut usize) -> Cow<'t, str> {
+    if !text.contains('\t') {
+        *column += text.chars().count();
+        return Cow::Borrowed(text);
+    }
+    let mut expanded = String::with_capacity(text.len() + width);
+    for ch in text.chars() {
+        if ch == '\t' {
+            let spaces = width - *column % width;
+            expanded.extend(std::iter::repeat_n(' ', spaces));
+            *column += spaces;
+        } else {
+            expanded.push(ch);
+            *column += 1;
+        }
+    }
+    Cow::Owned(expanded)
+}
+
+/// Lines, or records ending in `terminator`, of `input`, warning on stderr about
+/// any that had to be decoded lossily and naming `filename` in the warning. With
+/// `binary`, the warning says such lines are matched byte by byte instead.
+fn decoded_lines(
+    input: impl BufRead,
+    filename: Option<&str>,
+    terminator: u8,
+    binary: bool,
+) -> impl Iterator<Item = io::Result<search::Line>> {
+    let filename = filename.unwrap_or("(standard input)");
+    let how = if binary {
+        "matching it byte by byte"
+    } else {
+        "searching it with replacement characters"
+    };
+    search::lossy_records(input, terminator)
+        .enumerate()
+        .map(move |(line_num, line)| {
+            let line = line?;
+            if line.is_lossy() {
+                eprintln!(
+                    "Warning: {}: line {} is not valid UTF-8; {}",
+                    filename,
+                    line_num + 1,
+                    how
+                );
+            }
+            Ok(line)
+        })
+}
+
+/// Applies the replacement to every line of `path` through a temporary file that is
+/// renamed over the original, so readers never see a half-written file. Lines without
+/// a match, and all line endings, are written back byte for byte. Returns whether
+/// any line was replaced.
+fn replace_in_place(
+    path: &Path,
+    regex: &Regex,
+    template: &str,
+    backup_suffix: &str,
+) -> io::Result<bool> {
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "--in-place needs a file path")
+    })?;
+    let mut temp_name = file_name.to_os_string();
+    temp_name.push(".sg-tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let mut input = BufReader::new(File::open(path)?);
+    let mut output = BufWriter::new(File::create(&temp_path)?);
+    let temp = TempFile(Some(temp_path.clone()));
+    let mut raw = Vec::new();
+    let mut replaced = false;
+    while input.read_until(b'\n', &mut raw)? > 0 {
+        let body_len = raw.strip_suffix(b"\n").map_or(raw.len(), |body| {
+            body.strip_suffix(b"\r").map_or(body.len(), <[u8]>::len)
+        });
+        let (body, ending) = raw.split_at(body_len);
+        match std::str::from_utf8(body) {
+            Ok(line) if regex.is_match(line) => {
+                replaced = true;
+                output.write_all(regex.replace_all(line, template).as_bytes())?;
+                output.write_all(ending)?;
+            }
+            _ => output.write_all(&raw)?,
+        }
+        raw.clear();
+    }
+    output.flush()?;
+    drop(output);
+    fs::set_permissions(&temp_path, fs::metadata(path)?.permissions())?;
+
+    if !backup_suffix.is_empty() {
+        let mut backup_name = file_name.to_os_string();
+        backup_name.push(backup_suffix);
+        fs::copy(path, path.with_file_name(backup_name))?;
+    }
+    temp.persist(path)?;
+    Ok(replaced)
+}
+
+/// A temporary file that is removed when dropped, so a failed rewrite leaves
+/// nothing behind, unless it was renamed into place with `persist`.
+struct TempFile(Option<PathBuf>);
+
+impl TempFile {
+    fn persist(mut self, path: &Path) -> io::Result<()> {
+        fs::rename(self.0.as_ref().unwrap(), path)?;
+        self.0 = None;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.0 {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the CLI with `args` over `input`, returning the exit result and output.
+    fn run_with(args: &[&str], input: &str) -> (bool, String) {
+        let cli = Cli::try_parse_from(std::iter::once("sg").chain(args.iter().copied())).unwrap();
+        let mut out = Vec::new();
+        let selected = run(&cli, false, &mut input.as_bytes(), &mut out).unwrap();
+        (selected, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn usage_errors_are_returned() {
+        let run_err = |args: &[&str]| {
+            let cli =
+                Cli::try_parse_from(std::iter::once("sg").chain(args.iter().copied())).unwrap();
+            run(&cli, false, &mut "a\n".as_bytes(), &mut Vec::new()).unwrap_err()
+        };
+        assert_eq!(run_err(&["-p", "(a"]).kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            run_err(&["-p", "a", "-p", "b", "--replace", "x"]).to_string(),
+            "--replace takes a single pattern"
+        );
+        assert_eq!(
+            run_err(&["-p", "a", "--replace", "x", "--in-place"]).kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn writes_results_to_the_given_writer() {
+        let input = "foo\tbar\nbaz\nfood\n";
+        assert_eq!(
+            run_with(&["-p", "fo+", "--expand-tabs=4"], input),
+            (true, "foo bar\nfood\n".to_string())
+        );
+        assert_eq!(
+            run_with(&["-c", "-v", "-p", "fo"], input),
+            (true, "1\n".to_string())
+        );
+        assert_eq!(
+            run_with(&["-p", "(o+)", "--replace", "<$1>"], input),
+            (true, "f<oo>\tbar\nf<oo>d\n".to_string())
+        );
+        assert_eq!(
+            run_with(&["--count-matches", "-p", "q"], input),
+            (false, "0\n".to_string())
+        );
+    }
+}
@@ -1,190 +1,978 @@
-use crate::ast::{RegexNode, RepeatRange};
-use crate::tokens::{Lexer, Token};
-
-pub struct Parser<'a> {
-    lexer: Lexer<'a>,
-    current_token: Token,
-}
-
-impl<'a> Parser<'a> {
-    pub fn new(input: &'a str) -> Self {
-        let mut lexer = Lexer::new(input);
-        let current_token = lexer.next_token();
-        Self {
-            lexer,
-            current_token,
-        }
-    }
-
-    pub fn parse(&mut self) -> Result<RegexNode, String> {
-        self.parse_alternation()
-    }
-
-    fn parse_alternation(&mut self) -> Result<RegexNode, String> {
-        let mut nodes = vec![self.parse_concat()?];
-
-        while self.current_token == Token::Alternation {
-            self.consume_token(Token::Alternation)?;
-            nodes.push(self.parse_concat()?);
-        }
-
-        Ok(if nodes.len() == 1 {
-            nodes.into_iter().next().unwrap()
-        } else {
-            RegexNode::Alternation(nodes)
-        })
-    }
-
-    fn parse_concat(&mut self) -> Result<RegexNode, String> {
-        let mut nodes = vec![self.parse_atom()?];
-
-        while self.current_token != Token::Alternation
-            && self.current_token != Token::CloseParen
-            && self.current_token != Token::EOF
-        {
-            nodes.push(self.parse_atom()?);
-        }
-
-        Ok(if nodes.len() == 1 {
-            nodes.into_iter().next().unwrap()
-        } else {
-            RegexNode::Concat(nodes)
-        })
-    }
-
-    fn parse_atom(&mut self) -> Result<RegexNode, String> {
-        let node = self.parse_primary()?;
-
-        // Handle quantifiers
-        match self.current_token {
-            Token::Star => {
-                self.consume_token(Token::Star)?;
-                Ok(RegexNode::Star(Box::new(node)))
-            }
-            Token::Plus => {
-                self.consume_token(Token::Plus)?;
-                Ok(RegexNode::Plus(Box::new(node)))
-            }
-            Token::Question => {
-                self.consume_token(Token::Question)?;
-                Ok(RegexNode::Question(Box::new(node)))
-            }
-            Token::Range => {
-                self.consume_token(Token::Range)?;
-                let range = self.parse_range()?;
-                Ok(RegexNode::Repeat(Box::new(node), range))
-            }
-            _ => Ok(node),
-        }
-    }
-
-    fn parse_primary(&mut self) -> Result<RegexNode, String> {
-        match self.current_token {
-            Token::Char(ch) => {
-                self.consume_token(Token::Char(ch))?;
-                Ok(RegexNode::Char(ch))
-            }
-            Token::Escape(ch) => {
-                self.consume_token(Token::Escape(ch))?;
-                Ok(self.escape_to_node(ch))
-            }
-            Token::AnyChar => {
-                self.consume_token(Token::AnyChar)?;
-                Ok(RegexNode::AnyChar)
-            }
-            Token::Digit => {
-                self.consume_token(Token::Digit)?;
-                Ok(RegexNode::Digit)
-            }
-            Token::WordChar => {
-                self.consume_token(Token::WordChar)?;
-                Ok(RegexNode::WordChar)
-            }
-            Token::Whitespace => {
-                self.consume_token(Token::Whitespace)?;
-                Ok(RegexNode::Whitespace)
-            }
-            Token::OpenParen => {
-                self.consume_token(Token::OpenParen)?;
-                let node = self.parse()?;
-                self.consume_token(Token::CloseParen)?;
-                Ok(RegexNode::Group(Box::new(node)))
-            }
-            Token::StartLine => {
-                self.consume_token(Token::StartLine)?;
-                Ok(RegexNode::StartLine)
-            }
-            Token::EndLine => {
-                self.consume_token(Token::EndLine)?;
-                Ok(RegexNode::EndLine)
-            }
-            Token::StartInput => {
-                self.consume_token(Token::StartInput)?;
-                Ok(RegexNode::StartInput)
-            }
-            Token::EndInput => {
-                self.consume_token(Token::EndInput)?;
-                Ok(RegexNode::EndInput)
-            }
-            Token::WordBoundary => {
-                self.consume_token(Token::WordBoundary)?;
-                Ok(RegexNode::WordBoundary)
-            }
-            _ => Err(format!("Unexpected token: {:?}", self.current_token)),
-        }
-    }
-
-    fn parse_range(&mut self) -> Result<RepeatRange, String> {
-        // Parse {min,max} or {min,} or {min}
-        let mut min = 0;
-        let mut max = None;
-
-        // Parse min
-        if let Token::Char(ch) = self.current_token {
-            if ch.is_ascii_digit() {
-                min = ch.to_digit(10).unwrap() as usize;
-                self.consume_token(Token::Char(ch))?;
-
-                // Check for comma
-                if self.current_token == Token::Char(',') {
-                    self.consume_token(Token::Char(','))?;
-
-                    // Parse max if present
-                    if let Token::Char(ch) = self.current_token {
-                        if ch.is_ascii_digit() {
-                            max = Some(ch.to_digit(10).unwrap() as usize);
-                            self.consume_token(Token::Char(ch))?;
-                        }
-                    }
-                }
-            }
-        }
-
-        self.consume_token(Token::CloseBracket)?;
-        Ok(RepeatRange::new(min, max))
-    }
-
-    fn escape_to_node(&self, ch: char) -> RegexNode {
-        match ch {
-            'd' => RegexNode::Digit,
-            'w' => RegexNode::WordChar,
-            's' => RegexNode::Whitespace,
-            'A' => RegexNode::StartInput,
-            'z' => RegexNode::EndInput,
-            'b' => RegexNode::WordBoundary,
-            _ => RegexNode::Char(ch),
-        }
-    }
-
-    fn consume_token(&mut self, expected: Token) -> Result<(), String> {
-        if self.current_token == expected {
-            self.current_token = self.lexer.next_token();
-            Ok(())
-        } else {
-            Err(format!(
-                "Expected {:?}, got {:?}",
-                expected, self.current_token
-            ))
-        }
-    }
-}
+use crate::ast::{Flags, RegexNode, RepeatRange};
+use crate::class::CharClass;
+use crate::tokens::{Lexer, Spanned, Token};
+
+/// Limit on the automaton states a single repetition may expand to. Counted
+/// repetition copies its operand, so nested counts like `((a{9}){9}){9}` grow
+/// multiplicatively; anything larger than this is rejected when parsing.
+pub const MAX_REPEAT_STATES: usize = 10_000;
+
+/// Default limit on how deeply groups may nest. Parsing and compiling recurse once
+/// per level, so this keeps pathological patterns from overflowing the stack.
+pub const DEFAULT_MAX_DEPTH: usize = 200;
+
+pub struct Parser<'a> {
+    lexer: Lexer<'a>,
+    current_token: Spanned<Token>,
+    group_count: usize,
+    strict: bool,
+    depth: usize,
+    max_depth: usize,
+    /// Set by `(?flags)` until the end of the enclosing group.
+    flags: Flags,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(input: &'a str) -> Self {
+        let mut lexer = Lexer::new(input);
+        let current_token = lexer.next_token();
+        Self {
+            lexer,
+            current_token,
+            group_count: 0,
+            strict: false,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            flags: Flags::default(),
+        }
+    }
+
+    /// In strict mode an escape that has no meaning, such as `\q`, is an error
+    /// instead of a literal. Escaped punctuation is always allowed.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets how deeply groups may nest before parsing fails with an error.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn parse(&mut self) -> Result<RegexNode, String> {
+        let node = self.parse_alternation()?;
+        if self.current_token != Token::Eof {
+            return Err(format!("Unexpected token: {:?}", self.current_token.value));
+        }
+        Ok(node)
+    }
+
+    fn parse_alternation(&mut self) -> Result<RegexNode, String> {
+        if self.depth > self.max_depth {
+            return Err(format!(
+                "pattern too deeply nested (more than {} levels of groups)",
+                self.max_depth
+            ));
+        }
+        self.depth += 1;
+        let node = self.parse_alternatives();
+        self.depth -= 1;
+        node
+    }
+
+    fn parse_alternatives(&mut self) -> Result<RegexNode, String> {
+        let mut nodes = vec![self.parse_concat()?];
+
+        while self.current_token == Token::Alternation {
+            self.consume_token(Token::Alternation)?;
+            nodes.push(self.parse_concat()?);
+        }
+
+        Ok(if nodes.len() == 1 {
+            nodes.into_iter().next().unwrap()
+        } else {
+            RegexNode::Alternation(nodes)
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<RegexNode, String> {
+        let mut nodes = Vec::new();
+
+        loop {
+            self.parse_flags()?;
+            if self.current_token == Token::Alternation
+                || self.current_token == Token::CloseParen
+                || self.current_token == Token::Eof
+            {
+                break;
+            }
+            nodes.push(self.parse_atom()?);
+        }
+
+        Ok(match nodes.len() {
+            0 => RegexNode::Empty,
+            1 => nodes.into_iter().next().unwrap(),
+            _ => RegexNode::Concat(nodes),
+        })
+    }
+
+    /// Consumes any `(?flags)`, which apply to the rest of the enclosing group.
+    fn parse_flags(&mut self) -> Result<(), String> {
+        while let Token::SetFlags(flags) = self.current_token.value {
+            self.consume_token(Token::SetFlags(flags))?;
+            self.flags = self.flags.merge(flags);
+        }
+        Ok(())
+    }
+
+    /// Parses the body of a group up to its `)`, keeping flags set inside it local.
+    fn parse_group_body(&mut self, flags: Flags) -> Result<RegexNode, String> {
+        let outer = std::mem::replace(&mut self.flags, flags);
+        let node = self.parse_alternation()?;
+        self.consume_token(Token::CloseParen)?;
+        self.flags = outer;
+        Ok(node)
+    }
+
+    /// Attaches the flags in effect to the nodes they change: literals and classes
+    /// under `i`, `.` under `s`, and `^` and `$` under `m`. Flags turned off are kept
+    /// too, since they override the matching options.
+    fn apply_flags(&self, node: RegexNode) -> RegexNode {
+        let flags = self.flags;
+        match node {
+            RegexNode::Char(_) | RegexNode::CharClass { .. }
+                if flags.case_insensitive.is_some() =>
+            {
+                RegexNode::Flags(
+                    Box::new(node),
+                    Flags {
+                        case_insensitive: flags.case_insensitive,
+                        ..Flags::default()
+                    },
+                )
+            }
+            RegexNode::AnyChar if flags.dot_all.is_some() => RegexNode::Flags(
+                Box::new(node),
+                Flags {
+                    dot_all: flags.dot_all,
+                    ..Flags::default()
+                },
+            ),
+            RegexNode::StartLine | RegexNode::EndLine if flags.multi_line.is_some() => {
+                RegexNode::Flags(
+                    Box::new(node),
+                    Flags {
+                        multi_line: flags.multi_line,
+                        ..Flags::default()
+                    },
+                )
+            }
+            node => node,
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<RegexNode, String> {
+        let node = self.parse_primary()?;
+
+        let quantified = matches!(
+            self.current_token.value,
+            Token::Star | Token::Plus | Token::Question | Token::Range
+        );
+        let node = self.parse_quantifier(node)?;
+        // A `?` straight after a quantifier makes it lazy
+        if quantified && self.current_token == Token::Question {
+            self.consume_token(Token::Question)?;
+            if node != RegexNode::Empty {
+                return Ok(RegexNode::Lazy(Box::new(node)));
+            }
+        }
+        Ok(node)
+    }
+
+    fn parse_quantifier(&mut self, node: RegexNode) -> Result<RegexNode, String> {
+        match self.current_token.value {
+            Token::Star => {
+                self.consume_token(Token::Star)?;
+                Ok(RegexNode::Star(Box::new(node)))
+            }
+            Token::Plus => {
+                self.consume_token(Token::Plus)?;
+                Ok(RegexNode::Plus(Box::new(node)))
+            }
+            Token::Question => {
+                self.consume_token(Token::Question)?;
+                Ok(RegexNode::Question(Box::new(node)))
+            }
+            Token::Range => {
+                self.consume_token(Token::Range)?;
+                let range = self.parse_range()?;
+                if range.max == Some(0) {
+                    return Ok(RegexNode::Empty);
+                }
+                let node = RegexNode::Repeat(Box::new(node), range);
+                if node.state_count() > MAX_REPEAT_STATES {
+                    return Err(format!(
+                        "Repetition expands to more than {} automaton states",
+                        MAX_REPEAT_STATES
+                    ));
+                }
+                Ok(node)
+            }
+            _ => Ok(node),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<RegexNode, String> {
+        match self.current_token.value {
+            Token::Char(ch) => {
+                self.consume_token(Token::Char(ch))?;
+                Ok(self.apply_flags(RegexNode::Char(ch)))
+            }
+            Token::Escape(ch) => {
+                if self.strict && !ch.is_ascii_punctuation() {
+                    return Err(format!(
+                        "unrecognized escape sequence '\\{}' at position {}",
+                        ch, self.current_token.start
+                    ));
+                }
+                self.consume_token(Token::Escape(ch))?;
+                Ok(self.apply_flags(self.escape_to_node(ch)))
+            }
+            Token::AnyChar => {
+                self.consume_token(Token::AnyChar)?;
+                Ok(self.apply_flags(RegexNode::AnyChar))
+            }
+            Token::Digit => {
+                self.consume_token(Token::Digit)?;
+                Ok(RegexNode::Digit)
+            }
+            Token::WordChar => {
+                self.consume_token(Token::WordChar)?;
+                Ok(RegexNode::WordChar)
+            }
+            Token::Whitespace => {
+                self.consume_token(Token::Whitespace)?;
+                Ok(RegexNode::Whitespace)
+            }
+            Token::OpenParen => {
+                self.consume_token(Token::OpenParen)?;
+                self.group_count += 1;
+                let index = self.group_count;
+                let node = self.parse_group_body(self.flags)?;
+                Ok(RegexNode::Group(Box::new(node), Some(index)))
+            }
+            Token::OpenNonCapturing => {
+                self.consume_token(Token::OpenNonCapturing)?;
+                let node = self.parse_group_body(self.flags)?;
+                Ok(RegexNode::Group(Box::new(node), None))
+            }
+            Token::OpenFlags(flags) => {
+                self.consume_token(Token::OpenFlags(flags))?;
+                let node = self.parse_group_body(self.flags.merge(flags))?;
+                Ok(RegexNode::Group(Box::new(node), None))
+            }
+            Token::OpenBracket => {
+                let class = self.parse_class()?;
+                self.current_token = self.lexer.next_token();
+                Ok(self.apply_flags(class))
+            }
+            Token::StartLine => {
+                self.consume_token(Token::StartLine)?;
+                Ok(self.apply_flags(RegexNode::StartLine))
+            }
+            Token::EndLine => {
+                self.consume_token(Token::EndLine)?;
+                Ok(self.apply_flags(RegexNode::EndLine))
+            }
+            Token::StartInput => {
+                self.consume_token(Token::StartInput)?;
+                Ok(RegexNode::StartInput)
+            }
+            Token::EndInput => {
+                self.consume_token(Token::EndInput)?;
+                Ok(RegexNode::EndInput)
+            }
+            Token::WordBoundary => {
+                self.consume_token(Token::WordBoundary)?;
+                Ok(RegexNode::WordBoundary)
+            }
+            Token::NotWordBoundary => {
+                self.consume_token(Token::NotWordBoundary)?;
+                Ok(RegexNode::NotWordBoundary)
+            }
+            Token::Property { ref name, negated } => {
+                let class = unicode_property(name)?;
+                self.current_token = self.lexer.next_token();
+                Ok(self.apply_flags(RegexNode::CharClass { class, negated }))
+            }
+            Token::Error(ref message) => Err(message.clone()),
+            _ => Err(format!("Unexpected token: {:?}", self.current_token.value)),
+        }
+    }
+
+    /// Parses a bracket expression straight from the lexer's raw characters, since
+    /// metacharacters other than `\`, `^`, `-` and `]` are literal inside it.
+    ///
+    /// As in POSIX, a `]` right after the opening `[` or `[^` is a literal member, so
+    /// `[]a]` matches `]` or `a`, and there is no empty class: `[]` is unterminated.
+    fn parse_class(&mut self) -> Result<RegexNode, String> {
+        let start = self.current_token.start;
+        let unterminated = || {
+            format!(
+                "unterminated character class starting at position {}",
+                start
+            )
+        };
+
+        let negated = self.lexer.remaining().starts_with('^');
+        if negated {
+            self.lexer.next_char();
+        }
+
+        let mut class = CharClass::new();
+        let mut first = true;
+        loop {
+            let ch = self.lexer.next_char().ok_or_else(unterminated)?;
+            let (item, low) = match ch {
+                ']' if !first => break,
+                '[' if let Some(name) = self.posix_class_name() => match CharClass::posix(name) {
+                    Some(class) => (class, None),
+                    None => return Err(format!("unknown POSIX class [:{}:]", name)),
+                },
+                '\\' => match self.class_escape(&unterminated)? {
+                    (_, Some(ch)) => (CharClass::char(ch), Some(ch)),
+                    ('d', None) => (CharClass::digit(), None),
+                    ('w', None) => (CharClass::word(), None),
+                    ('s', None) => (CharClass::whitespace(), None),
+                    (escaped @ ('p' | 'P'), None) => {
+                        let class = unicode_property(self.lexer.property_name()?)?;
+                        match escaped {
+                            'P' => (class.negate(), None),
+                            _ => (class, None),
+                        }
+                    }
+                    (escaped, None) => (CharClass::char(escaped), Some(escaped)),
+                },
+                ch => (CharClass::char(ch), Some(ch)),
+            };
+            first = false;
+
+            // `-` is a range operator only between two members, so `[-a]` and `[a-]` are literal
+            let rest = self.lexer.remaining();
+            if let Some(low) = low
+                && rest.starts_with('-')
+                && !rest[1..].starts_with(']')
+                && rest.len() > 1
+            {
+                self.lexer.next_char();
+                let high = match self.lexer.next_char().ok_or_else(unterminated)? {
+                    '\\' => {
+                        let (escaped, ch) = self.class_escape(&unterminated)?;
+                        ch.unwrap_or(escaped)
+                    }
+                    high => high,
+                };
+                if high < low {
+                    return Err(format!("invalid range {}-{} in character class", low, high));
+                }
+                class = class.union(&CharClass::range(low, high));
+            } else {
+                class = class.union(&item);
+            }
+        }
+
+        Ok(RegexNode::CharClass { class, negated })
+    }
+
+    /// Reads the name of a POSIX class like `[:alpha:]` after its opening `[`, or
+    /// returns `None` and reads nothing if no such class follows.
+    fn posix_class_name(&mut self) -> Option<&'a str> {
+        let rest = self.lexer.remaining().strip_prefix(':')?;
+        let name = &rest[..rest.find(":]")?];
+        if !name.chars().all(|ch| ch.is_ascii_alphabetic()) {
+            return None;
+        }
+        for _ in 0..name.len() + 3 {
+            self.lexer.next_char();
+        }
+        Some(name)
+    }
+
+    /// Reads the rest of an escape inside a bracket expression, after its `\`. Returns
+    /// the escaped character and, for escapes like `\n` or `\x41`, the one it stands for.
+    fn class_escape(
+        &mut self,
+        unterminated: &impl Fn() -> String,
+    ) -> Result<(char, Option<char>), String> {
+        let start = self.lexer.position() - 1;
+        let escaped = self.lexer.next_char().ok_or_else(unterminated)?;
+        let ch = self.lexer.char_escape(escaped)?;
+        if self.strict
+            && ch.is_none()
+            && !escaped.is_ascii_punctuation()
+            && !matches!(escaped, 'd' | 'w' | 's' | 'p' | 'P')
+        {
+            return Err(format!(
+                "unrecognized escape sequence '\\{}' at position {}",
+                escaped, start
+            ));
+        }
+        Ok((escaped, ch))
+    }
+
+    fn parse_range(&mut self) -> Result<RepeatRange, String> {
+        // Parse {min,max}, {min,}, {,max} or {min}
+        let min = self.parse_count()?;
+
+        let range = if self.current_token == Token::Char(',') {
+            self.consume_token(Token::Char(','))?;
+            let max = self.parse_count()?;
+            if min.is_none() && max.is_none() {
+                return Err("Repetition {,} needs a minimum or a maximum".to_string());
+            }
+            RepeatRange::new(min.unwrap_or(0), max)
+        } else {
+            RepeatRange::exactly(min.ok_or("Expected a repetition count after '{'")?)
+        };
+
+        if let Some(max) = range.max
+            && max < range.min
+        {
+            return Err(format!("Invalid repetition {{{},{}}}", range.min, max));
+        }
+
+        self.consume_token(Token::Char('}'))?;
+        Ok(range)
+    }
+
+    /// Parses an optional repetition count.
+    fn parse_count(&mut self) -> Result<Option<usize>, String> {
+        let mut count = None;
+        while let Token::Char(ch) = self.current_token.value
+            && let Some(digit) = ch.to_digit(10)
+        {
+            self.consume_token(Token::Char(ch))?;
+            count = count
+                .unwrap_or(0usize)
+                .checked_mul(10)
+                .and_then(|count| count.checked_add(digit as usize))
+                .map(Some)
+                .ok_or("Repetition count is too large")?;
+        }
+        Ok(count)
+    }
+
+    fn escape_to_node(&self, ch: char) -> RegexNode {
+        match ch {
+            'd' => RegexNode::Digit,
+            'w' => RegexNode::WordChar,
+            's' => RegexNode::Whitespace,
+            'A' => RegexNode::StartInput,
+            'z' => RegexNode::EndInput,
+            'b' => RegexNode::WordBoundary,
+            'B' => RegexNode::NotWordBoundary,
+            _ => RegexNode::Char(ch),
+        }
+    }
+
+    fn consume_token(&mut self, expected: Token) -> Result<(), String> {
+        if self.current_token == expected {
+            self.current_token = self.lexer.next_token();
+            Ok(())
+        } else {
+            Err(format!(
+                "Expected {:?}, got {:?}",
+                expected, self.current_token.value
+            ))
+        }
+    }
+}
+
+fn unicode_property(name: &str) -> Result<CharClass, String> {
+    CharClass::unicode_property(name).ok_or_else(|| format!("unknown Unicode property {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(pattern: &str) -> Result<RegexNode, String> {
+        Parser::new(pattern).parse()
+    }
+
+    #[test]
+    fn multibyte_literals() {
+        assert_eq!(
+            parse("é€").unwrap(),
+            RegexNode::Concat(vec![RegexNode::Char('é'), RegexNode::Char('€')])
+        );
+        assert_eq!(parse("\\é").unwrap(), RegexNode::Char('é'));
+    }
+
+    #[test]
+    fn whitespace_is_literal() {
+        assert_eq!(
+            parse("a b").unwrap(),
+            RegexNode::Concat(vec![
+                RegexNode::Char('a'),
+                RegexNode::Char(' '),
+                RegexNode::Char('b')
+            ])
+        );
+    }
+
+    #[test]
+    fn repeat_closes_with_brace() {
+        assert_eq!(
+            parse("a{2}").unwrap(),
+            RegexNode::Repeat(Box::new(RegexNode::Char('a')), RepeatRange::exactly(2))
+        );
+        assert_eq!(
+            parse("a{2,}").unwrap(),
+            RegexNode::Repeat(Box::new(RegexNode::Char('a')), RepeatRange::min(2))
+        );
+    }
+
+    #[test]
+    fn groups_numbered_by_opening_paren() {
+        let group = |node, index| RegexNode::Group(Box::new(node), Some(index));
+        assert_eq!(
+            parse("((a)b)(c)").unwrap(),
+            RegexNode::Concat(vec![
+                group(
+                    RegexNode::Concat(vec![group(RegexNode::Char('a'), 2), RegexNode::Char('b')]),
+                    1
+                ),
+                group(RegexNode::Char('c'), 3),
+            ])
+        );
+    }
+
+    #[test]
+    fn non_capturing_group_takes_no_index() {
+        assert_eq!(
+            parse("(?:a)(b)").unwrap(),
+            RegexNode::Concat(vec![
+                RegexNode::Group(Box::new(RegexNode::Char('a')), None),
+                RegexNode::Group(Box::new(RegexNode::Char('b')), Some(1)),
+            ])
+        );
+        assert!(parse("(?:a").is_err());
+    }
+
+    #[test]
+    fn character_classes() {
+        assert_eq!(
+            parse("[^a-c\\d.]").unwrap(),
+            RegexNode::CharClass {
+                class: CharClass::from_ranges([('a', 'c'), ('0', '9'), ('.', '.')]),
+                negated: true,
+            }
+        );
+        assert_eq!(
+            parse("[-a-]").unwrap(),
+            RegexNode::CharClass {
+                class: CharClass::from_ranges([('-', '-'), ('a', 'a')]),
+                negated: false,
+            }
+        );
+        assert!(parse("[z-a]").is_err());
+    }
+
+    #[test]
+    fn posix_classes() {
+        assert_eq!(
+            parse("[[:digit:]_[:upper:]]").unwrap(),
+            RegexNode::CharClass {
+                class: CharClass::from_ranges([('0', '9'), ('_', '_'), ('A', 'Z')]),
+                negated: false,
+            }
+        );
+        assert_eq!(
+            parse("[^[:space:]]").unwrap(),
+            RegexNode::CharClass {
+                class: CharClass::posix("space").unwrap(),
+                negated: true,
+            }
+        );
+        // Without a closing `:]` the `[` is an ordinary member
+        assert_eq!(
+            parse("[[:a]").unwrap(),
+            RegexNode::CharClass {
+                class: CharClass::from_ranges([('[', '['), (':', ':'), ('a', 'a')]),
+                negated: false,
+            }
+        );
+        assert_eq!(
+            parse("[[:word:]]").unwrap_err(),
+            "unknown POSIX class [:word:]"
+        );
+    }
+
+    #[test]
+    fn unicode_properties() {
+        let greek = CharClass::unicode_property("Greek").unwrap();
+        assert_eq!(
+            parse("\\p{Greek}").unwrap(),
+            RegexNode::CharClass {
+                class: greek.clone(),
+                negated: false,
+            }
+        );
+        assert_eq!(
+            parse("\\PL").unwrap(),
+            RegexNode::CharClass {
+                class: CharClass::unicode_property("L").unwrap(),
+                negated: true,
+            }
+        );
+        assert_eq!(
+            parse("[\\p{Greek}\\d]").unwrap(),
+            RegexNode::CharClass {
+                class: greek.union(&CharClass::digit()),
+                negated: false,
+            }
+        );
+        assert_eq!(
+            parse("[\\P{Greek}]").unwrap(),
+            RegexNode::CharClass {
+                class: greek.negate(),
+                negated: false,
+            }
+        );
+        assert_eq!(
+            parse("\\p{Klingon}").unwrap_err(),
+            "unknown Unicode property Klingon"
+        );
+        assert_eq!(
+            parse("a\\p{L").unwrap_err(),
+            "invalid Unicode property at position 1"
+        );
+        assert_eq!(
+            parse("[\\p]").unwrap_err(),
+            "invalid Unicode property at position 1"
+        );
+    }
+
+    #[test]
+    fn word_boundaries() {
+        assert_eq!(
+            parse("\\ba\\B").unwrap(),
+            RegexNode::Concat(vec![
+                RegexNode::WordBoundary,
+                RegexNode::Char('a'),
+                RegexNode::NotWordBoundary,
+            ])
+        );
+    }
+
+    #[test]
+    fn unterminated_class() {
+        assert_eq!(
+            parse("[abc").unwrap_err(),
+            "unterminated character class starting at position 0"
+        );
+        assert_eq!(
+            parse("x(a|[b-").unwrap_err(),
+            "unterminated character class starting at position 4"
+        );
+    }
+
+    #[test]
+    fn empty_class() {
+        // `]` first is a literal member, so `[]` never closes
+        assert_eq!(
+            parse("x[]").unwrap_err(),
+            "unterminated character class starting at position 1"
+        );
+        assert_eq!(
+            parse("[]a]").unwrap(),
+            RegexNode::CharClass {
+                class: CharClass::from_ranges([(']', ']'), ('a', 'a')]),
+                negated: false,
+            }
+        );
+        assert!(parse("[^]").is_err());
+    }
+
+    #[test]
+    fn repeat_without_minimum() {
+        assert_eq!(
+            parse("a{,3}").unwrap(),
+            RegexNode::Repeat(Box::new(RegexNode::Char('a')), RepeatRange::new(0, Some(3)))
+        );
+        assert_eq!(
+            parse("a{,}").unwrap_err(),
+            "Repetition {,} needs a minimum or a maximum"
+        );
+        assert_eq!(parse("a{3,1}").unwrap_err(), "Invalid repetition {3,1}");
+        assert!(parse("a{}").is_err());
+    }
+
+    #[test]
+    fn case_insensitive_flags() {
+        let case = |ch, on| {
+            RegexNode::Flags(
+                Box::new(RegexNode::Char(ch)),
+                Flags {
+                    case_insensitive: Some(on),
+                    ..Flags::default()
+                },
+            )
+        };
+        let folded = |ch| case(ch, true);
+        assert_eq!(
+            parse("(?i)ab").unwrap(),
+            RegexNode::Concat(vec![folded('a'), folded('b')])
+        );
+        assert_eq!(
+            parse("(?i:a)b").unwrap(),
+            RegexNode::Concat(vec![
+                RegexNode::Group(Box::new(folded('a')), None),
+                RegexNode::Char('b'),
+            ])
+        );
+        // An unscoped flag ends with its group but carries across alternatives
+        assert_eq!(
+            parse("((?i)a)b|c").unwrap(),
+            RegexNode::Alternation(vec![
+                RegexNode::Concat(vec![
+                    RegexNode::Group(Box::new(folded('a')), Some(1)),
+                    RegexNode::Char('b'),
+                ]),
+                RegexNode::Char('c'),
+            ])
+        );
+        assert_eq!(
+            parse("a(?i)b|c").unwrap(),
+            RegexNode::Alternation(vec![
+                RegexNode::Concat(vec![RegexNode::Char('a'), folded('b')]),
+                folded('c'),
+            ])
+        );
+        // Turning `i` off is recorded too, so it can override `-i`
+        assert_eq!(
+            parse("(?i)a(?-i)b").unwrap(),
+            RegexNode::Concat(vec![folded('a'), case('b', false)])
+        );
+        assert_eq!(
+            parse("(?-i:a)b").unwrap(),
+            RegexNode::Concat(vec![
+                RegexNode::Group(Box::new(case('a', false)), None),
+                RegexNode::Char('b'),
+            ])
+        );
+    }
+
+    #[test]
+    fn multi_line_and_dot_all_flags() {
+        let with = |node, flags| RegexNode::Flags(Box::new(node), flags);
+        let multi_line = Flags {
+            multi_line: Some(true),
+            ..Flags::default()
+        };
+        let dot_all = |on| Flags {
+            dot_all: Some(on),
+            ..Flags::default()
+        };
+        assert_eq!(
+            parse("(?m)^a$").unwrap(),
+            RegexNode::Concat(vec![
+                with(RegexNode::StartLine, multi_line),
+                RegexNode::Char('a'),
+                with(RegexNode::EndLine, multi_line),
+            ])
+        );
+        assert_eq!(
+            parse("(?s:.(?-s).).").unwrap(),
+            RegexNode::Concat(vec![
+                RegexNode::Group(
+                    Box::new(RegexNode::Concat(vec![
+                        with(RegexNode::AnyChar, dot_all(true)),
+                        with(RegexNode::AnyChar, dot_all(false)),
+                    ])),
+                    None
+                ),
+                RegexNode::AnyChar,
+            ])
+        );
+        // Flags combine, each node keeping only the ones that change it
+        assert_eq!(
+            parse("(?is)a.").unwrap(),
+            RegexNode::Concat(vec![
+                with(
+                    RegexNode::Char('a'),
+                    Flags {
+                        case_insensitive: Some(true),
+                        ..Flags::default()
+                    }
+                ),
+                with(RegexNode::AnyChar, dot_all(true)),
+            ])
+        );
+        assert!(parse("(?x)a").is_err());
+    }
+
+    #[test]
+    fn nested_repetition_is_capped() {
+        assert!(parse("(((a{9}){9}){9}){9}").is_ok());
+        assert_eq!(
+            parse("((((a{9}){9}){9}){9}){9}").unwrap_err(),
+            format!(
+                "Repetition expands to more than {} automaton states",
+                MAX_REPEAT_STATES
+            )
+        );
+    }
+
+    #[test]
+    fn nesting_depth_is_limited() {
+        let nested = |depth| format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(parse(&nested(DEFAULT_MAX_DEPTH)).is_ok());
+        assert_eq!(
+            parse(&nested(DEFAULT_MAX_DEPTH + 1)).unwrap_err(),
+            format!(
+                "pattern too deeply nested (more than {} levels of groups)",
+                DEFAULT_MAX_DEPTH
+            )
+        );
+        // Far past what the stack could take, yet rejected without recursing that deep
+        assert!(parse(&nested(1_000_000)).is_err());
+
+        assert!(Parser::new(&nested(3)).max_depth(3).parse().is_ok());
+        assert!(Parser::new(&nested(4)).max_depth(3).parse().is_err());
+        assert!(Parser::new("a").max_depth(0).parse().is_ok());
+    }
+
+    #[test]
+    fn strict_mode_rejects_unknown_escapes() {
+        assert_eq!(parse("\\q").unwrap(), RegexNode::Char('q'));
+        assert_eq!(
+            Parser::new("ab\\q").strict(true).parse().unwrap_err(),
+            "unrecognized escape sequence '\\q' at position 2"
+        );
+        assert_eq!(
+            Parser::new("é\\ü").strict(true).parse().unwrap_err(),
+            "unrecognized escape sequence '\\ü' at position 2"
+        );
+        // Bracket expressions are held to the same rule
+        assert_eq!(
+            Parser::new("a[\\q]").strict(true).parse().unwrap_err(),
+            "unrecognized escape sequence '\\q' at position 2"
+        );
+        assert!(
+            Parser::new("[\\]\\d\\n\\p{L}-]")
+                .strict(true)
+                .parse()
+                .is_ok()
+        );
+        assert!(parse("[\\q]").is_ok());
+        assert_eq!(
+            Parser::new("\\.\\d\\\\").strict(true).parse().unwrap(),
+            RegexNode::Concat(vec![
+                RegexNode::Char('.'),
+                RegexNode::Digit,
+                RegexNode::Char('\\')
+            ])
+        );
+    }
+
+    #[test]
+    fn empty_constructs() {
+        assert_eq!(parse("").unwrap(), RegexNode::Empty);
+        assert_eq!(
+            parse("a|").unwrap(),
+            RegexNode::Alternation(vec![RegexNode::Char('a'), RegexNode::Empty])
+        );
+        assert_eq!(
+            parse("(?:)").unwrap(),
+            RegexNode::Group(Box::new(RegexNode::Empty), None)
+        );
+        assert_eq!(
+            parse("a{0}b").unwrap(),
+            RegexNode::Concat(vec![RegexNode::Empty, RegexNode::Char('b')])
+        );
+        assert_eq!(parse("(?i)").unwrap(), RegexNode::Empty);
+    }
+
+    #[test]
+    fn character_escapes() {
+        assert_eq!(
+            parse("a\\tb").unwrap(),
+            RegexNode::Concat(vec![
+                RegexNode::Char('a'),
+                RegexNode::Char('\t'),
+                RegexNode::Char('b'),
+            ])
+        );
+        assert_eq!(
+            parse("[\\x00-\\x1f\\n\\x64]").unwrap(),
+            RegexNode::CharClass {
+                class: CharClass::from_ranges([('\0', '\u{1f}'), ('d', 'd')]),
+                negated: false,
+            }
+        );
+        assert!(parse("\\x{41}").is_ok());
+        // Character escapes are not unknown escapes, even in strict mode
+        assert!(Parser::new("\\r\\n").strict(true).parse().is_ok());
+        assert_eq!(
+            parse("ab\\xz").unwrap_err(),
+            "invalid hex escape at position 2"
+        );
+        assert_eq!(
+            parse("[\\x{zz}]").unwrap_err(),
+            "invalid hex escape at position 1"
+        );
+    }
+
+    #[test]
+    fn multi_digit_counts() {
+        let a = || Box::new(RegexNode::Char('a'));
+        assert_eq!(
+            parse("a{12,34}").unwrap(),
+            RegexNode::Repeat(a(), RepeatRange::new(12, Some(34)))
+        );
+        assert_eq!(
+            parse("a{10}").unwrap(),
+            RegexNode::Repeat(a(), RepeatRange::exactly(10))
+        );
+        assert_eq!(
+            parse("a{007,}").unwrap(),
+            RegexNode::Repeat(a(), RepeatRange::min(7))
+        );
+        assert_eq!(
+            parse("a{,20}").unwrap(),
+            RegexNode::Repeat(a(), RepeatRange::new(0, Some(20)))
+        );
+        assert_eq!(parse("a{21,12}").unwrap_err(), "Invalid repetition {21,12}");
+        assert_eq!(
+            parse("a{,}").unwrap_err(),
+            "Repetition {,} needs a minimum or a maximum"
+        );
+        assert_eq!(
+            parse("a{99999999999999999999}").unwrap_err(),
+            "Repetition count is too large"
+        );
+    }
+
+    #[test]
+    fn lazy_quantifiers() {
+        let a = || Box::new(RegexNode::Char('a'));
+        assert_eq!(
+            parse("a*?").unwrap(),
+            RegexNode::Lazy(Box::new(RegexNode::Star(a())))
+        );
+        assert_eq!(
+            parse("a??b").unwrap(),
+            RegexNode::Concat(vec![
+                RegexNode::Lazy(Box::new(RegexNode::Question(a()))),
+                RegexNode::Char('b'),
+            ])
+        );
+        assert_eq!(
+            parse("a{2,}?").unwrap(),
+            RegexNode::Lazy(Box::new(RegexNode::Repeat(a(), RepeatRange::min(2))))
+        );
+        assert_eq!(parse("a{0}?").unwrap(), RegexNode::Empty);
+    }
+
+    #[test]
+    fn malformed_patterns_are_errors() {
+        for pattern in [
+            "a)", "(a", "a{", "a{2", "{", "[", "]", "[a", "*", "a**", "|*", "a*??",
+        ] {
+            assert!(parse(pattern).is_err(), "{:?} should not parse", pattern);
+        }
+    }
+}
@@ -0,0 +1,721 @@
+use crate::ast::RegexNode;
+use crate::automaton::{NFA, NfaOptions};
+use crate::compiler::Program;
+use crate::parser::Parser;
+use std::sync::{Arc, Mutex};
+
+/// Number of compiled patterns kept by [`Regex::cached`].
+pub const CACHE_CAPACITY: usize = 32;
+
+/// A pattern together with the options it was compiled with.
+type CacheKey = (String, NfaOptions);
+
+/// Recently used patterns, least recently used first.
+static CACHE: Mutex<Vec<(CacheKey, Arc<Regex>)>> = Mutex::new(Vec::new());
+
+/// A compiled regular expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    /// Answers whether there is a match.
+    nfa: NFA,
+    /// Finds where matches and their groups are.
+    program: Program,
+    /// The text of a pattern that is a plain literal, searched for directly
+    /// instead of simulating the automaton.
+    literal: Option<String>,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        Self::with_options(pattern, &NfaOptions::default())
+    }
+
+    /// Compiles `pattern` with the given matching options.
+    pub fn with_options(pattern: &str, options: &NfaOptions) -> Result<Self, String> {
+        let node = Parser::new(pattern).parse()?;
+        let literal = if options.case_insensitive || options.anchored || options.whole_line {
+            None
+        } else {
+            node.as_literal()
+        };
+        Ok(Self {
+            nfa: NFA::from_regex_with_options(&node, options),
+            program: Program::compile(&node, options),
+            literal,
+        })
+    }
+
+    /// Combines already parsed patterns into one regex that matches wherever any of
+    /// them does, preferring the earliest pattern among matches that start together.
+    pub fn from_nodes(nodes: &[RegexNode], options: &NfaOptions) -> Self {
+        Self {
+            nfa: NFA::from_nodes_with_options(nodes, options),
+            program: Program::compile_all(nodes, options),
+            literal: None,
+        }
+    }
+
+    /// Compiles `pattern` with `options` once and hands out shared copies on later
+    /// calls with the same pattern and options.
+    ///
+    /// Up to [`CACHE_CAPACITY`] patterns are kept, evicting the least recently
+    /// used. Patterns that fail to parse are not cached.
+    pub fn cached(pattern: &str, options: &NfaOptions) -> Result<Arc<Regex>, String> {
+        let mut cache = CACHE
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(index) = cache.iter().position(|((cached, cached_options), _)| {
+            cached == pattern && cached_options == options
+        }) {
+            let entry = cache.remove(index);
+            let regex = entry.1.clone();
+            cache.push(entry);
+            return Ok(regex);
+        }
+
+        let regex = Arc::new(Regex::with_options(pattern, options)?);
+        if cache.len() == CACHE_CAPACITY {
+            cache.remove(0);
+        }
+        cache.push(((pattern.to_string(), *options), regex.clone()));
+        Ok(regex)
+    }
+
+    /// Returns true if the pattern matches anywhere in `input`.
+    pub fn is_match(&self, input: &str) -> bool {
+        match &self.literal {
+            Some(literal) => input.contains(literal.as_str()),
+            None => self.nfa.matches(input),
+        }
+    }
+
+    /// Returns the end offset of the match that completes first, which is cheaper
+    /// than finding the full leftmost span.
+    pub fn shortest_match(&self, input: &str) -> Option<usize> {
+        match &self.literal {
+            Some(literal) => input
+                .find(literal.as_str())
+                .map(|start| start + literal.len()),
+            None => self.nfa.shortest_match(input),
+        }
+    }
+
+    /// Tests for a match beginning exactly at byte offset `start` and returns the
+    /// end offset of the longest one.
+    ///
+    /// Returns `None` if there is no such match, or if `start` is past the end of
+    /// `input` or inside a multi-byte character.
+    pub fn is_match_at(&self, input: &str, start: usize) -> Option<usize> {
+        if !input.is_char_boundary(start) {
+            return None;
+        }
+        match &self.literal {
+            Some(literal) => input[start..]
+                .starts_with(literal.as_str())
+                .then_some(start + literal.len()),
+            None => self.nfa.longest_match_at(input, start),
+        }
+    }
+
+    /// Finds the leftmost match, with its byte offsets in `input`.
+    pub fn find<'t>(&self, input: &'t str) -> Option<Match<'t>> {
+        let slots = self.captures_from(input, 0)?;
+        Some(Match {
+            text: input,
+            start: slots[0].unwrap(),
+            end: slots[1].unwrap(),
+        })
+    }
+
+    /// Finds the leftmost match and the spans of all its groups in one pass.
+    ///
+    /// Groups are numbered by the position of their opening parenthesis, starting
+    /// from 1; group 0 is the whole match.
+    pub fn captures<'t>(&self, input: &'t str) -> Option<Captures<'t>> {
+        let slots = self.captures_from(input, 0)?;
+        Some(Captures { text: input, slots })
+    }
+
+    /// Tests for a match and extracts its groups in one pass, like `captures`, so
+    /// callers need not run `is_match` first. Returns `None` if there is no match.
+    pub fn matches_with_captures<'t>(&self, input: &'t str) -> Option<Captures<'t>> {
+        self.captures(input)
+    }
+
+    /// Returns the byte spans of all non-overlapping matches, left to right.
+    pub fn find_all(&self, input: &str) -> Vec<(usize, usize)> {
+        self.find_iter(input)
+            .map(|found| (found.start(), found.end()))
+            .collect()
+    }
+
+    /// Iterates over the non-overlapping matches left to right, searching for each
+    /// one only when it is asked for.
+    pub fn find_iter<'r, 't>(&'r self, input: &'t str) -> Matches<'r, 't> {
+        Matches {
+            captures: self.captures_iter(input),
+        }
+    }
+
+    /// Like `find_iter`, but yields the groups of each match too.
+    ///
+    /// An empty match right where the previous match ended is skipped, and after an
+    /// empty match the search resumes one character further on, so `a*` over `baa`
+    /// finds an empty match at 0 and then `aa`.
+    pub fn captures_iter<'r, 't>(&'r self, input: &'t str) -> CaptureMatches<'r, 't> {
+        CaptureMatches {
+            regex: self,
+            text: input,
+            pos: Some(0),
+            last_end: None,
+        }
+    }
+
+    /// Returns the span of the leftmost match at every start position, so matches may
+    /// overlap: after each match the search resumes one character past its start.
+    pub fn find_overlapping(&self, input: &str) -> Vec<(usize, usize)> {
+        let mut spans = Vec::new();
+        let mut pos = 0;
+        while pos <= input.len() {
+            let Some(slots) = self.captures_from(input, pos) else {
+                break;
+            };
+            let start = slots[0].unwrap();
+            spans.push((start, slots[1].unwrap()));
+            match next_boundary(input, start) {
+                Some(next) => pos = next,
+                None => break,
+            }
+        }
+        spans
+    }
+
+    /// Replaces the leftmost match with `template`, expanded as in `replace_all`.
+    pub fn replace(&self, input: &str, template: &str) -> String {
+        let Some(caps) = self.captures(input) else {
+            return input.to_string();
+        };
+        let (start, end) = caps.span(0).unwrap();
+        let mut result = String::with_capacity(input.len());
+        result.push_str(&input[..start]);
+        caps.expand(template, &mut result);
+        result.push_str(&input[end..]);
+        result
+    }
+
+    /// Replaces every non-overlapping match with `template`, in which `$N` or `${N}`
+    /// stands for the text of group N and `$$` for a literal `$`.
+    pub fn replace_all(&self, input: &str, template: &str) -> String {
+        let mut result = String::with_capacity(input.len());
+        let mut copied = 0;
+        for caps in self.captures_iter(input) {
+            let (start, end) = caps.span(0).unwrap();
+            result.push_str(&input[copied..start]);
+            caps.expand(template, &mut result);
+            copied = end;
+        }
+        result.push_str(&input[copied..]);
+        result
+    }
+}
+
+impl Regex {
+    /// Capture slots of the leftmost match at or after `start`, like `Program::captures_from`.
+    fn captures_from(&self, input: &str, start: usize) -> Option<Vec<Option<usize>>> {
+        match &self.literal {
+            Some(literal) => {
+                let found = start + input[start..].find(literal.as_str())?;
+                Some(vec![Some(found), Some(found + literal.len())])
+            }
+            None => self.program.captures_from(input, start),
+        }
+    }
+}
+
+/// Offset of the character after the one starting at `pos`, or `None` at the end.
+fn next_boundary(input: &str, pos: usize) -> Option<usize> {
+    input[pos..].chars().next().map(|ch| pos + ch.len_utf8())
+}
+
+/// One match found by [`Regex::find`] or [`Regex::find_iter`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match<'t> {
+    text: &'t str,
+    start: usize,
+    end: usize,
+}
+
+impl<'t> Match<'t> {
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// The matched text.
+    pub fn as_str(&self) -> &'t str {
+        &self.text[self.start..self.end]
+    }
+}
+
+/// Iterator returned by [`Regex::find_iter`].
+#[derive(Debug)]
+pub struct Matches<'r, 't> {
+    captures: CaptureMatches<'r, 't>,
+}
+
+impl<'t> Iterator for Matches<'_, 't> {
+    type Item = Match<'t>;
+
+    fn next(&mut self) -> Option<Match<'t>> {
+        let caps = self.captures.next()?;
+        let (start, end) = caps.span(0).unwrap();
+        Some(Match {
+            text: caps.text,
+            start,
+            end,
+        })
+    }
+}
+
+/// Iterator returned by [`Regex::captures_iter`].
+#[derive(Debug)]
+pub struct CaptureMatches<'r, 't> {
+    regex: &'r Regex,
+    text: &'t str,
+    /// Where the next search starts, or `None` once the input is exhausted.
+    pos: Option<usize>,
+    last_end: Option<usize>,
+}
+
+impl<'t> Iterator for CaptureMatches<'_, 't> {
+    type Item = Captures<'t>;
+
+    fn next(&mut self) -> Option<Captures<'t>> {
+        loop {
+            let pos = self.pos?;
+            let Some(slots) = self.regex.captures_from(self.text, pos) else {
+                self.pos = None;
+                return None;
+            };
+            let (start, end) = (slots[0].unwrap(), slots[1].unwrap());
+            // An empty match must still make progress
+            self.pos = if end > start {
+                Some(end)
+            } else {
+                next_boundary(self.text, end)
+            };
+            // An empty match right where the previous one ended is not a new match
+            if start != end || self.last_end != Some(start) {
+                self.last_end = Some(end);
+                return Some(Captures {
+                    text: self.text,
+                    slots,
+                });
+            }
+        }
+    }
+}
+
+/// Spans of the groups from one match, with group 0 covering the whole match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Captures<'t> {
+    text: &'t str,
+    slots: Vec<Option<usize>>,
+}
+
+impl<'t> Captures<'t> {
+    /// Byte range of group `index`, or `None` if it did not take part in the match.
+    pub fn span(&self, index: usize) -> Option<(usize, usize)> {
+        match (self.slots.get(2 * index)?, self.slots.get(2 * index + 1)?) {
+            (Some(start), Some(end)) => Some((*start, *end)),
+            _ => None,
+        }
+    }
+
+    /// Text matched by group `index`, or `None` if it did not take part in the match.
+    pub fn get(&self, index: usize) -> Option<&'t str> {
+        self.span(index).map(|(start, end)| &self.text[start..end])
+    }
+
+    /// Number of groups, including group 0.
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Appends `template` to `out` with group references substituted.
+    pub fn expand(&self, template: &str, out: &mut String) {
+        let mut rest = template;
+        while let Some(dollar) = rest.find('$') {
+            out.push_str(&rest[..dollar]);
+            rest = &rest[dollar + 1..];
+
+            let (reference, after) = if let Some(braced) = rest.strip_prefix('{') {
+                match braced.find('}') {
+                    Some(close) => (&braced[..close], &braced[close + 1..]),
+                    None => ("", rest),
+                }
+            } else {
+                let digits = rest
+                    .find(|ch: char| !ch.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                (&rest[..digits], &rest[digits..])
+            };
+
+            match reference.parse::<usize>() {
+                Ok(index) => out.push_str(self.get(index).unwrap_or("")),
+                Err(_) if rest.starts_with('$') => {
+                    out.push('$');
+                    rest = &rest[1..];
+                    continue;
+                }
+                // Not a reference; keep the `$` as written
+                Err(_) => {
+                    out.push('$');
+                    continue;
+                }
+            }
+            rest = after;
+        }
+        out.push_str(rest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal_patterns_agree_with_automaton() {
+        let inputs = ["", "abc", "xxabcabc", "aaaa", "é€é€", "a.b and a+b", "ab"];
+        for pattern in ["abc", "aa", "é€", "\\Qa.b\\E", "b"] {
+            let fast = Regex::new(pattern).unwrap();
+            assert!(fast.literal.is_some(), "{:?}", pattern);
+            let slow = Regex {
+                literal: None,
+                ..fast.clone()
+            };
+            for input in inputs {
+                let context = format!("{:?} on {:?}", pattern, input);
+                assert_eq!(fast.is_match(input), slow.is_match(input), "{context}");
+                assert_eq!(fast.find_all(input), slow.find_all(input), "{context}");
+                assert_eq!(fast.find_overlapping(input), slow.find_overlapping(input));
+                assert_eq!(fast.shortest_match(input), slow.shortest_match(input));
+                assert_eq!(fast.is_match_at(input, 2), slow.is_match_at(input, 2));
+                assert_eq!(
+                    fast.replace_all(input, "[$0]"),
+                    slow.replace_all(input, "[$0]"),
+                    "{context}"
+                );
+            }
+        }
+
+        for pattern in ["a.c", "(abc)", "^abc", "", "ab|c"] {
+            assert!(
+                Regex::new(pattern).unwrap().literal.is_none(),
+                "{:?}",
+                pattern
+            );
+        }
+        let options = NfaOptions {
+            case_insensitive: true,
+            ..NfaOptions::default()
+        };
+        assert!(
+            Regex::with_options("abc", &options)
+                .unwrap()
+                .literal
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn cached_reuses_and_evicts() {
+        let defaults = NfaOptions::default();
+        let first = Regex::cached("cache(d|s)", &defaults).unwrap();
+        let second = Regex::cached("cache(d|s)", &defaults).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(second.is_match("caches"));
+        let insensitive = NfaOptions {
+            case_insensitive: true,
+            ..defaults
+        };
+        let folded = Regex::cached("cache(d|s)", &insensitive).unwrap();
+        assert!(!Arc::ptr_eq(&first, &folded));
+        assert!(folded.is_match("CACHES"));
+        assert!(!first.is_match("CACHES"));
+        assert!(Regex::cached("(", &defaults).is_err());
+
+        // Keep the first pattern recently used while filling the rest of the cache
+        for i in 0..CACHE_CAPACITY {
+            Regex::cached(&format!("cache-fill-{}", i), &defaults).unwrap();
+            Regex::cached("cache(d|s)", &defaults).unwrap();
+        }
+        assert!(Arc::ptr_eq(
+            &first,
+            &Regex::cached("cache(d|s)", &defaults).unwrap()
+        ));
+        let evicted = Regex::cached("cache-fill-0", &defaults).unwrap();
+        for i in 0..CACHE_CAPACITY {
+            Regex::cached(&format!("cache-evict-{}", i), &defaults).unwrap();
+        }
+        assert!(!Arc::ptr_eq(
+            &evicted,
+            &Regex::cached("cache-fill-0", &defaults).unwrap()
+        ));
+    }
+
+    #[test]
+    fn overlapping_and_non_overlapping_matches() {
+        let re = Regex::new("aa").unwrap();
+        assert_eq!(re.find_all("aaaa"), [(0, 2), (2, 4)]);
+        assert_eq!(re.find_overlapping("aaaa"), [(0, 2), (1, 3), (2, 4)]);
+        assert_eq!(re.find_overlapping("aéaa"), [(3, 5)]);
+
+        let re = Regex::new("a*").unwrap();
+        assert_eq!(re.find_all("baa"), [(0, 0), (1, 3)]);
+        assert_eq!(re.find_overlapping("baa"), [(0, 0), (1, 3), (2, 3), (3, 3)]);
+    }
+
+    #[test]
+    fn find_reports_leftmost_match_offsets() {
+        let re = Regex::new("b+|c").unwrap();
+        let found = re.find("aébbcb").unwrap();
+        assert_eq!((found.start(), found.end(), found.as_str()), (3, 5, "bb"));
+        assert_eq!(re.find("xyz"), None);
+
+        // Offsets are in bytes, even past multi-byte characters
+        let literal = Regex::new("ü").unwrap();
+        let found = literal.find("aüü").unwrap();
+        assert_eq!((found.start(), found.end(), found.as_str()), (1, 3, "ü"));
+
+        let empty = Regex::new("x*").unwrap().find("abc").unwrap();
+        assert_eq!((empty.start(), empty.end(), empty.as_str()), (0, 0, ""));
+    }
+
+    #[test]
+    fn find_iter_searches_lazily() {
+        let re = Regex::new("[0-9]+").unwrap();
+        let line = "1 22 333 ".repeat(1000);
+        let mut matches = re.find_iter(&line);
+        let first: Vec<_> = matches.by_ref().take(2).collect();
+        assert_eq!(first.len(), 2);
+        assert_eq!(
+            (first[0].start(), first[0].end(), first[0].as_str()),
+            (0, 1, "1")
+        );
+        assert_eq!(
+            (first[1].start(), first[1].end(), first[1].as_str()),
+            (2, 4, "22")
+        );
+        // Nothing past the second match has been searched yet
+        assert_eq!(matches.captures.pos, Some(4));
+        assert_eq!(matches.next().unwrap().as_str(), "333");
+
+        let re = Regex::new("a*").unwrap();
+        let spans: Vec<_> = re.find_iter("baa").map(|m| (m.start(), m.end())).collect();
+        assert_eq!(spans, re.find_all("baa"));
+    }
+
+    #[test]
+    fn iterators_skip_empty_match_after_a_match() {
+        let re = Regex::new("x*").unwrap();
+        let spans: Vec<_> = re.find_iter("axxé").map(|m| (m.start(), m.end())).collect();
+        // No empty match at 3, where `xx` ended, but one at each later boundary
+        assert_eq!(spans, [(0, 0), (1, 3), (5, 5)]);
+        assert_eq!(re.replace_all("axxé", "-"), "-a-é-");
+
+        let re = Regex::new("(\\d)(\\w)?").unwrap();
+        let groups: Vec<_> = re
+            .captures_iter("1a 2 3b")
+            .map(|caps| (caps.get(1).unwrap(), caps.get(2)))
+            .collect();
+        assert_eq!(groups, [("1", Some("a")), ("2", None), ("3", Some("b"))]);
+    }
+
+    #[test]
+    fn leftmost_longest_semantics() {
+        let posix = NfaOptions {
+            leftmost_longest: true,
+            ..NfaOptions::default()
+        };
+        let perl = Regex::new("a|ab").unwrap();
+        let longest = Regex::with_options("a|ab", &posix).unwrap();
+        assert_eq!(perl.find_all("xab ab"), [(1, 2), (4, 5)]);
+        assert_eq!(longest.find_all("xab ab"), [(1, 3), (4, 6)]);
+
+        // Leftmost still beats longest
+        let longest = Regex::with_options("bcd|abc", &posix).unwrap();
+        assert_eq!(longest.find_all("abcd"), [(0, 3)]);
+        let perl = Regex::new("(ab|a)(c|bcd)").unwrap();
+        let longest = Regex::with_options("(ab|a)(c|bcd)", &posix).unwrap();
+        assert_eq!(perl.find_all("abcd"), [(0, 3)]);
+        assert_eq!(longest.find_all("abcd"), [(0, 4)]);
+    }
+
+    #[test]
+    fn shortest_match_stops_at_first_accept() {
+        assert_eq!(Regex::new("a+").unwrap().shortest_match("aaa"), Some(1));
+        assert_eq!(Regex::new("b|abc").unwrap().shortest_match("xabc"), Some(3));
+        assert_eq!(Regex::new("a*").unwrap().shortest_match("bbb"), Some(0));
+        assert_eq!(Regex::new("z").unwrap().shortest_match("aaa"), None);
+    }
+
+    #[test]
+    fn match_at_start() {
+        let re = Regex::new("ab+").unwrap();
+        assert_eq!(re.is_match_at("abbbc", 0), Some(4));
+        assert_eq!(re.is_match_at("cabbb", 0), None);
+        assert_eq!(Regex::new("x*").unwrap().is_match_at("abc", 0), Some(0));
+    }
+
+    #[test]
+    fn match_at_mid_string_offset() {
+        let re = Regex::new("\\d+").unwrap();
+        assert_eq!(re.is_match_at("abc123def", 3), Some(6));
+        assert_eq!(re.is_match_at("abc123def", 4), Some(6));
+        assert_eq!(re.is_match_at("abc123def", 2), None);
+
+        let re = Regex::new("\\bde").unwrap();
+        assert_eq!(re.is_match_at("abc de", 4), Some(6));
+        assert_eq!(re.is_match_at("abcde", 3), None);
+    }
+
+    #[test]
+    fn captures_in_one_pass() {
+        let re = Regex::new("(\\w+)\\s+(\\w+)").unwrap();
+        let caps = re.matches_with_captures("hello world").unwrap();
+        assert_eq!(caps.len(), 3);
+        assert_eq!(caps.get(0), Some("hello world"));
+        assert_eq!(caps.get(1), Some("hello"));
+        assert_eq!(caps.get(2), Some("world"));
+        assert_eq!(caps.span(2), Some((6, 11)));
+        assert!(re.matches_with_captures("hello").is_none());
+    }
+
+    #[test]
+    fn groups_numbered_by_opening_parenthesis() {
+        let re = Regex::new("((a)(b))c|(d)").unwrap();
+        let caps = re.captures("xabc").unwrap();
+        assert_eq!(caps.len(), 5);
+        assert_eq!(caps.get(1), Some("ab"));
+        assert_eq!(caps.get(2), Some("a"));
+        assert_eq!(caps.span(3), Some((2, 3)));
+        assert_eq!(caps.get(4), None);
+        assert_eq!(caps.get(5), None);
+
+        let caps = re.captures("d").unwrap();
+        assert_eq!(
+            (caps.get(0), caps.get(1), caps.get(4)),
+            (Some("d"), None, Some("d"))
+        );
+    }
+
+    #[test]
+    fn captures_prefer_leftmost_then_first_alternative() {
+        let re = Regex::new("(a|ab)(c|bcd)?").unwrap();
+        let caps = re.captures("xabcd").unwrap();
+        assert_eq!(caps.get(0), Some("abcd"));
+        assert_eq!(caps.get(1), Some("a"));
+        assert_eq!(caps.get(2), Some("bcd"));
+
+        let caps = Regex::new("x(y)?").unwrap().captures("zx").unwrap();
+        assert_eq!(caps.get(0), Some("x"));
+        assert_eq!(caps.get(1), None);
+    }
+
+    #[test]
+    fn captures_on_input_that_makes_backtracking_explode() {
+        // Exponential for a backtracking engine; every thread advances in step here
+        let input = "x".repeat(5000);
+        let re = Regex::new("(x+x+)+y").unwrap();
+        assert!(re.captures(&input).is_none());
+
+        let input = format!("{input}y");
+        let caps = re.captures(&input).unwrap();
+        assert_eq!(caps.span(0), Some((0, 5001)));
+        assert_eq!(caps.span(1), Some((0, 5000)));
+
+        let re = Regex::new("(a|aa)*b").unwrap();
+        let input = format!("{}b", "a".repeat(5000));
+        assert_eq!(re.captures(&input).unwrap().span(1), Some((4999, 5000)));
+    }
+
+    #[test]
+    fn lazy_quantifiers_match_as_little_as_possible() {
+        assert_eq!(
+            Regex::new("<.+?>").unwrap().find_all("<a><b>"),
+            vec![(0, 3), (3, 6)]
+        );
+        assert_eq!(Regex::new("<.+>").unwrap().find_all("<a><b>"), vec![(0, 6)]);
+
+        let quoted = Regex::new("\"(.*?)\"").unwrap();
+        let caps = quoted.captures("say \"hi\" and \"bye\"").unwrap();
+        assert_eq!(caps.get(1), Some("hi"));
+
+        let caps = Regex::new("(a+?)(a*)").unwrap().captures("aaa").unwrap();
+        assert_eq!((caps.get(1), caps.get(2)), (Some("a"), Some("aa")));
+        assert_eq!(
+            Regex::new("a{2,4}?").unwrap().find_all("aaaaa"),
+            vec![(0, 2), (2, 4)]
+        );
+        assert_eq!(Regex::new("ab??").unwrap().find_all("ab"), vec![(0, 1)]);
+        // A lazy match still has to reach the rest of the pattern
+        assert_eq!(Regex::new("a*?b").unwrap().find_all("aab"), vec![(0, 3)]);
+    }
+
+    #[test]
+    fn non_capturing_group() {
+        let re = Regex::new("(?:ab)+(c)").unwrap();
+        assert!(re.is_match("ababc"));
+        let caps = re.captures("xababc").unwrap();
+        assert_eq!(caps.len(), 2);
+        assert_eq!(caps.get(0), Some("ababc"));
+        assert_eq!(caps.get(1), Some("c"));
+
+        assert_eq!(
+            Regex::new("(?:ab)+").unwrap().is_match_at("abab", 0),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn replace_all_with_group_references() {
+        let re = Regex::new("(\\w+)=(\\d+)").unwrap();
+        assert_eq!(re.replace_all("a=1, b=22", "$2:$1"), "1:a, 22:b");
+        assert_eq!(re.replace_all("a=1", "${1}x $$ $9 $"), "ax $  $");
+        assert_eq!(re.replace("a=1, b=22", "$2:$1"), "1:a, b=22");
+        assert_eq!(re.replace("none", "$1"), "none");
+        assert_eq!(Regex::new("x*").unwrap().replace_all("abc", "-"), "-a-b-c-");
+        assert_eq!(Regex::new("a*").unwrap().replace_all("baaa", "-"), "-b-");
+        assert_eq!(Regex::new("é").unwrap().replace_all("aéb", "e"), "aeb");
+    }
+
+    #[test]
+    fn repeat_up_to_maximum() {
+        let re = Regex::new("^a{,3}$").unwrap();
+        for count in 0..=3 {
+            assert!(re.is_match(&"a".repeat(count)), "{} a's", count);
+        }
+        assert!(!re.is_match("aaaa"));
+        assert_eq!(
+            Regex::new("xa{,3}").unwrap().is_match_at("xaaaaa", 0),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn match_at_invalid_offset() {
+        let re = Regex::new(".").unwrap();
+        assert_eq!(re.is_match_at("é", 1), None);
+        assert_eq!(re.is_match_at("é", 0), Some(2));
+        assert_eq!(re.is_match_at("ab", 3), None);
+        assert_eq!(Regex::new("$").unwrap().is_match_at("ab", 2), Some(2));
+    }
+}
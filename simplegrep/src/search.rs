@@ -0,0 +1,192 @@
+use crate::automaton::NFA;
+use crate::lazy_dfa::LazyDfa;
+use std::io::{self, BufRead};
+
+/// Reads records ending in `terminator`, usually lines, until one matches (or, with
+/// `invert`, until one does not match), returning as soon as the answer is known.
+/// With `binary`, records that are not valid UTF-8 are matched byte by byte, as
+/// `NFA::matches_bytes` does, instead of with U+FFFD in place of the invalid bytes.
+///
+/// Takes the reader by value so it is dropped (closing any file) on return rather
+/// than being kept open until the caller's scope ends.
+pub fn any_match<R: BufRead>(
+    reader: R,
+    terminator: u8,
+    nfa: &NFA,
+    invert: bool,
+    binary: bool,
+) -> io::Result<bool> {
+    // One lazy DFA for the whole input, so states found on one line serve the rest
+    let mut dfa = LazyDfa::new(nfa);
+    for line in lossy_records(reader, terminator) {
+        let line = line?;
+        let matches = match &line.raw {
+            Some(raw) if binary => nfa.matches_bytes(raw),
+            _ => dfa.is_match(&line.text),
+        };
+        if matches != invert {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// One input line, or record, without its line ending or terminator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub text: String,
+    /// The bytes the line was read as, kept only when they are not valid UTF-8 and
+    /// `text` has U+FFFD in place of the invalid ones.
+    pub raw: Option<Vec<u8>>,
+}
+
+impl Line {
+    /// Whether invalid UTF-8 in the line was replaced with U+FFFD.
+    pub fn is_lossy(&self) -> bool {
+        self.raw.is_some()
+    }
+}
+
+/// Splits `reader` into lines like `BufRead::lines`, but decodes invalid UTF-8
+/// lossily instead of failing, so one bad line does not end the search.
+pub fn lossy_lines<R: BufRead>(reader: R) -> LossyLines<R> {
+    lossy_records(reader, b'\n')
+}
+
+/// Like `lossy_lines`, but splits at each `terminator` byte, such as the NUL ending
+/// each record of `sg -z`. A `\r` before the terminator is only dropped for `\n`.
+pub fn lossy_records<R: BufRead>(reader: R, terminator: u8) -> LossyLines<R> {
+    LossyLines {
+        reader,
+        buf: Vec::new(),
+        terminator,
+    }
+}
+
+pub struct LossyLines<R> {
+    reader: R,
+    buf: Vec<u8>,
+    terminator: u8,
+}
+
+impl<R: BufRead> Iterator for LossyLines<R> {
+    type Item = io::Result<Line>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buf.clear();
+        match self.reader.read_until(self.terminator, &mut self.buf) {
+            Ok(0) => None,
+            Ok(_) => {
+                let body = self
+                    .buf
+                    .strip_suffix(&[self.terminator])
+                    .unwrap_or(&self.buf);
+                let body = match self.terminator {
+                    b'\n' => body.strip_suffix(b"\r").unwrap_or(body),
+                    _ => body,
+                };
+                Some(Ok(match std::str::from_utf8(body) {
+                    Ok(text) => Line {
+                        text: text.to_string(),
+                        raw: None,
+                    },
+                    Err(_) => Line {
+                        text: String::from_utf8_lossy(body).into_owned(),
+                        raw: Some(body.to_vec()),
+                    },
+                }))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Read};
+
+    /// Counts how many bytes the search pulled out of the underlying reader.
+    struct CountingReader<R> {
+        inner: R,
+        consumed: usize,
+    }
+
+    impl<R: BufRead> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.consumed += n;
+            Ok(n)
+        }
+    }
+
+    impl<R: BufRead> BufRead for CountingReader<R> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            self.inner.fill_buf()
+        }
+
+        fn consume(&mut self, amount: usize) {
+            self.consumed += amount;
+            self.inner.consume(amount);
+        }
+    }
+
+    fn nfa(pattern: &str) -> NFA {
+        NFA::from_patterns(&[pattern]).unwrap()
+    }
+
+    #[test]
+    fn stops_after_first_matching_line() {
+        let first = "needle in the first line\n";
+        let input = format!("{}{}", first, "hay\n".repeat(100_000));
+        let mut reader = CountingReader {
+            inner: BufReader::new(input.as_bytes()),
+            consumed: 0,
+        };
+        assert!(any_match(&mut reader, b'\n', &nfa("needle"), false, false).unwrap());
+        assert_eq!(reader.consumed, first.len());
+    }
+
+    #[test]
+    fn reads_to_end_without_match() {
+        let input = "a\nb\nc\n";
+        let mut reader = CountingReader {
+            inner: BufReader::new(input.as_bytes()),
+            consumed: 0,
+        };
+        assert!(!any_match(&mut reader, b'\n', &nfa("d"), false, false).unwrap());
+        assert_eq!(reader.consumed, input.len());
+        assert!(any_match("x\r\nab\r\n".as_bytes(), b'\n', &nfa("b$"), false, false).unwrap());
+        assert!(!any_match("ab\nb\n".as_bytes(), b'\n', &nfa("b$"), true, false).unwrap());
+        assert!(any_match("ab\nc\n".as_bytes(), b'\n', &nfa("b$"), true, false).unwrap());
+    }
+
+    #[test]
+    fn invalid_utf8_is_decoded_lossily() {
+        let input: &[u8] = b"ok\nbad \xff\xfe byte\r\nnext";
+        let lines: Vec<Line> = lossy_lines(input).map(Result::unwrap).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].text, "ok");
+        assert!(!lines[0].is_lossy());
+        assert_eq!(lines[1].text, "bad \u{fffd}\u{fffd} byte");
+        assert_eq!(lines[1].raw.as_deref(), Some(&b"bad \xff\xfe byte"[..]));
+        assert_eq!(lines[2].text, "next");
+        assert!(any_match(input, b'\n', &nfa("^next$"), false, false).unwrap());
+
+        // Invalid bytes are U+FFFD to the text, but not when matched as bytes
+        assert!(any_match(input, b'\n', &nfa("\u{fffd}"), false, false).unwrap());
+        assert!(!any_match(input, b'\n', &nfa("\u{fffd}"), false, true).unwrap());
+        assert!(any_match(input, b'\n', &nfa("d [^a]. b"), false, true).unwrap());
+    }
+
+    #[test]
+    fn records_split_at_terminator() {
+        let input: &[u8] = b"one\r\ntwo\0three\n\0";
+        let records: Vec<String> = lossy_records(input, 0)
+            .map(|record| record.unwrap().text)
+            .collect();
+        assert_eq!(records, ["one\r\ntwo", "three\n"]);
+        assert!(any_match(input, 0, &nfa("(?s)one.*two"), false, false).unwrap());
+        assert!(!any_match(input, b'\n', &nfa("(?s)one.*two"), false, false).unwrap());
+    }
+}
@@ -0,0 +1,170 @@
+use crate::automaton::{NFA, NFAState, StateSet};
+
+/// A match reported by [`StreamMatcher`]: which pattern matched and the byte
+/// offset in the stream where the match ends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamMatch {
+    pub pattern: usize,
+    pub end: usize,
+}
+
+/// Runs an automaton over input that arrives in chunks, keeping only the set of
+/// active states between calls, so matches may span chunk boundaries.
+///
+/// Matches are found at every offset, as in `NFA::matches`. A match is reported once
+/// the character after it has been seen (or the stream is finished), because anchors
+/// such as `$` and `\b` depend on it.
+pub struct StreamMatcher<'n> {
+    nfa: &'n NFA,
+    /// States reached at `offset` before following epsilon transitions.
+    pending: Vec<usize>,
+    closure: StateSet,
+    offset: usize,
+    prev: Option<char>,
+    /// Trailing bytes of a character split across chunks.
+    partial: Vec<u8>,
+}
+
+impl<'n> StreamMatcher<'n> {
+    pub fn new(nfa: &'n NFA) -> Self {
+        Self {
+            nfa,
+            pending: Vec::new(),
+            closure: StateSet::new(nfa.states.len()),
+            offset: 0,
+            prev: None,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Consumes the next chunk and returns the matches it completed. Invalid UTF-8
+    /// is matched as U+FFFD.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<StreamMatch> {
+        let mut matches = Vec::new();
+        let mut bytes = std::mem::take(&mut self.partial);
+        bytes.extend_from_slice(chunk);
+
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            let (valid, skip) = match std::str::from_utf8(rest) {
+                Ok(valid) => (valid, 0),
+                Err(e) => {
+                    let valid = std::str::from_utf8(&rest[..e.valid_up_to()]).unwrap();
+                    match e.error_len() {
+                        Some(len) => (valid, len),
+                        // The chunk ends inside a character; wait for the rest of it
+                        None => {
+                            self.partial = rest[e.valid_up_to()..].to_vec();
+                            (valid, 0)
+                        }
+                    }
+                }
+            };
+            for ch in valid.chars() {
+                self.advance(Some(ch), ch.len_utf8(), &mut matches);
+            }
+            if skip > 0 {
+                self.advance(Some(char::REPLACEMENT_CHARACTER), skip, &mut matches);
+            }
+            if skip == 0 {
+                break;
+            }
+            rest = &rest[valid.len() + skip..];
+        }
+        matches
+    }
+
+    /// Ends the stream and returns the matches that end at its very end.
+    pub fn finish(mut self) -> Vec<StreamMatch> {
+        let mut matches = Vec::new();
+        if !self.partial.is_empty() {
+            let len = self.partial.len();
+            self.advance(Some(char::REPLACEMENT_CHARACTER), len, &mut matches);
+        }
+        self.advance(None, 0, &mut matches);
+        matches
+    }
+
+    /// Settles the current offset now that the character there (`ch`) is known,
+    /// reports matches ending at it, and steps over `ch`, which is `len` bytes long.
+    fn advance(&mut self, ch: Option<char>, len: usize, matches: &mut Vec<StreamMatch>) {
+        let nfa = self.nfa;
+        self.closure.clear();
+        for state in self.pending.drain(..) {
+            nfa.add_state(&mut self.closure, state, self.prev, ch);
+        }
+        if self.offset == 0 || !nfa.options.anchored {
+            nfa.add_state(&mut self.closure, nfa.start, self.prev, ch);
+        }
+
+        for &state in &self.closure.states {
+            if let NFAState::Match(pattern) = nfa.states[state] {
+                matches.push(StreamMatch {
+                    pattern,
+                    end: self.offset,
+                });
+            }
+        }
+
+        if let Some(ch) = ch {
+            self.pending.extend(
+                self.closure
+                    .states
+                    .iter()
+                    .filter_map(|&state| nfa.step(state, ch)),
+            );
+            self.prev = Some(ch);
+            self.offset += len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nfa(pattern: &str) -> NFA {
+        NFA::from_patterns(&[pattern]).unwrap()
+    }
+
+    #[test]
+    fn match_split_across_chunks() {
+        let nfa = nfa("hel+o");
+        let mut matcher = StreamMatcher::new(&nfa);
+        assert_eq!(matcher.feed(b"say hel"), []);
+        assert_eq!(
+            matcher.feed(b"lo there"),
+            [StreamMatch { pattern: 0, end: 9 }]
+        );
+        assert_eq!(matcher.finish(), []);
+    }
+
+    #[test]
+    fn match_ending_at_chunk_boundary() {
+        let nfa = nfa("ab\\b");
+        let mut matcher = StreamMatcher::new(&nfa);
+        // Whether the boundary holds depends on the next chunk
+        assert_eq!(matcher.feed(b"xab"), []);
+        assert_eq!(matcher.feed(b"c ab"), []);
+        assert_eq!(matcher.finish(), [StreamMatch { pattern: 0, end: 7 }]);
+
+        let mut matcher = StreamMatcher::new(&nfa);
+        assert_eq!(matcher.feed(b"ab"), []);
+        assert_eq!(matcher.feed(b" "), [StreamMatch { pattern: 0, end: 2 }]);
+    }
+
+    #[test]
+    fn character_split_across_chunks() {
+        let accented = nfa("é!");
+        let mut matcher = StreamMatcher::new(&accented);
+        let bytes = "é!".as_bytes();
+        assert_eq!(matcher.feed(&bytes[..1]), []);
+        assert_eq!(matcher.feed(&bytes[1..]), []);
+        assert_eq!(matcher.finish(), [StreamMatch { pattern: 0, end: 3 }]);
+
+        let any = nfa("a.b");
+        let mut matcher = StreamMatcher::new(&any);
+        assert_eq!(matcher.feed(b"a\xffb"), []);
+        assert_eq!(matcher.finish(), [StreamMatch { pattern: 0, end: 3 }]);
+    }
+}
@@ -1,137 +1,449 @@
-#[derive(Debug, Clone, PartialEq)]
-pub enum Token {
-    // Literals
-    Char(char),
-    Escape(char),
-
-    // Operators
-    Concat,      // implicit concatenation
-    Alternation, // |
-    Star,        // *
-    Plus,        // +
-    Question,    // ?
-    Range,       // {n,m}
-
-    // Groups
-    OpenParen,    // (
-    CloseParen,   // )
-    OpenBracket,  // [
-    CloseBracket, // ]
-
-    // Character classes
-    AnyChar,    // .
-    Digit,      // \d
-    WordChar,   // \w
-    Whitespace, // \s
-
-    // Anchors
-    StartLine,    // ^
-    EndLine,      // $
-    StartInput,   // \A
-    EndInput,     // \z
-    WordBoundary, // \b
-
-    EOF,
-}
-
-#[derive(Debug)]
-pub struct Lexer<'a> {
-    input: &'a str,
-    position: usize,
-}
-
-impl<'a> Lexer<'a> {
-    pub fn new(input: &'a str) -> Self {
-        Self { input, position: 0 }
-    }
-
-    pub fn next_token(&mut self) -> Token {
-        if self.position >= self.input.len() {
-            return Token::EOF;
-        }
-
-        let ch = self.input[self.position..].chars().next().unwrap();
-
-        match ch {
-            ' ' | '\t' | '\n' | '\r' => {
-                self.position += 1;
-                self.next_token() // skip whitespace
-            }
-            '(' => {
-                self.position += 1;
-                Token::OpenParen
-            }
-            ')' => {
-                self.position += 1;
-                Token::CloseParen
-            }
-            '[' => {
-                self.position += 1;
-                Token::OpenBracket
-            }
-            ']' => {
-                self.position += 1;
-                Token::CloseBracket
-            }
-            '*' => {
-                self.position += 1;
-                Token::Star
-            }
-            '+' => {
-                self.position += 1;
-                Token::Plus
-            }
-            '?' => {
-                self.position += 1;
-                Token::Question
-            }
-            '|' => {
-                self.position += 1;
-                Token::Alternation
-            }
-            '.' => {
-                self.position += 1;
-                Token::AnyChar
-            }
-            '^' => {
-                self.position += 1;
-                Token::StartLine
-            }
-            '$' => {
-                self.position += 1;
-                Token::EndLine
-            }
-            '\\' => {
-                self.position += 1;
-                self.handle_escape()
-            }
-            '{' => {
-                self.position += 1;
-                Token::Range
-            }
-            _ => {
-                self.position += 1;
-                Token::Char(ch)
-            }
-        }
-    }
-
-    fn handle_escape(&mut self) -> Token {
-        if self.position >= self.input.len() {
-            return Token::Char('\\');
-        }
-
-        let ch = self.input[self.position..].chars().next().unwrap();
-        self.position += 1;
-
-        match ch {
-            'd' => Token::Digit,
-            'w' => Token::WordChar,
-            's' => Token::Whitespace,
-            'A' => Token::StartInput,
-            'z' => Token::EndInput,
-            'b' => Token::WordBoundary,
-            _ => Token::Escape(ch),
-        }
-    }
-}
+use crate::ast::Flags;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    // Literals
+    Char(char),
+    Escape(char),
+
+    // Operators
+    Concat,      // implicit concatenation
+    Alternation, // |
+    Star,        // *
+    Plus,        // +
+    Question,    // ?
+    Range,       // {n,m}
+
+    // Groups
+    OpenParen,        // (
+    OpenNonCapturing, // (?:
+    OpenFlags(Flags), // (?ims-ims:
+    SetFlags(Flags),  // (?ims-ims)
+    CloseParen,       // )
+    OpenBracket,      // [
+    CloseBracket,     // ]
+
+    // Character classes
+    AnyChar,    // .
+    Digit,      // \d
+    WordChar,   // \w
+    Whitespace, // \s
+    /// `\p{name}` or `\pL`, or with `negated` `\P{name}` or `\PL`.
+    Property {
+        name: String,
+        negated: bool,
+    },
+
+    // Anchors
+    StartLine,       // ^
+    EndLine,         // $
+    StartInput,      // \A
+    EndInput,        // \z
+    WordBoundary,    // \b
+    NotWordBoundary, // \B
+
+    /// A malformed escape, with a description for the parser to report.
+    Error(String),
+
+    Eof,
+}
+
+/// A value with the byte range of the pattern it was read from.
+#[derive(Debug, Clone)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Compares the value only, so `spanned == Token::Eof` works wherever it appears.
+impl<T: PartialEq> PartialEq<T> for Spanned<T> {
+    fn eq(&self, other: &T) -> bool {
+        self.value == *other
+    }
+}
+
+#[derive(Debug)]
+pub struct Lexer<'a> {
+    input: &'a str,
+    position: usize,
+    /// Inside `\Q...\E`, where every character is a literal.
+    quoting: bool,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            position: 0,
+            quoting: false,
+        }
+    }
+
+    /// Byte offset of the next unread character.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The unread part of the input.
+    pub fn remaining(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+
+    /// Reads one raw character, bypassing tokenization (used inside bracket expressions).
+    pub fn next_char(&mut self) -> Option<char> {
+        let ch = self.remaining().chars().next()?;
+        self.position += ch.len_utf8();
+        Some(ch)
+    }
+
+    pub fn next_token(&mut self) -> Spanned<Token> {
+        self.skip_quote_markers();
+        let start = self.position;
+        let value = self.read_token();
+        Spanned {
+            value,
+            start,
+            end: self.position,
+        }
+    }
+
+    /// Steps over `\Q` and `\E`, which switch literal quoting on and off and are
+    /// not tokens themselves. Quoting runs to the end of the input if `\E` is missing.
+    fn skip_quote_markers(&mut self) {
+        loop {
+            let marker = if self.quoting { "\\E" } else { "\\Q" };
+            if !self.remaining().starts_with(marker) {
+                return;
+            }
+            self.position += marker.len();
+            self.quoting = !self.quoting;
+        }
+    }
+
+    fn read_token(&mut self) -> Token {
+        if self.position >= self.input.len() {
+            return Token::Eof;
+        }
+
+        let ch = self.input[self.position..].chars().next().unwrap();
+        if self.quoting {
+            self.position += ch.len_utf8();
+            return Token::Char(ch);
+        }
+
+        match ch {
+            '(' if self.input[self.position..].starts_with("(?:") => {
+                self.position += 3;
+                Token::OpenNonCapturing
+            }
+            '(' if let Some((token, len)) = self.read_flags() => {
+                self.position += len;
+                token
+            }
+            '(' => {
+                self.position += 1;
+                Token::OpenParen
+            }
+            ')' => {
+                self.position += 1;
+                Token::CloseParen
+            }
+            '[' => {
+                self.position += 1;
+                Token::OpenBracket
+            }
+            ']' => {
+                self.position += 1;
+                Token::CloseBracket
+            }
+            '*' => {
+                self.position += 1;
+                Token::Star
+            }
+            '+' => {
+                self.position += 1;
+                Token::Plus
+            }
+            '?' => {
+                self.position += 1;
+                Token::Question
+            }
+            '|' => {
+                self.position += 1;
+                Token::Alternation
+            }
+            '.' => {
+                self.position += 1;
+                Token::AnyChar
+            }
+            '^' => {
+                self.position += 1;
+                Token::StartLine
+            }
+            '$' => {
+                self.position += 1;
+                Token::EndLine
+            }
+            '\\' => {
+                self.position += 1;
+                self.handle_escape()
+            }
+            '{' => {
+                self.position += 1;
+                Token::Range
+            }
+            _ => {
+                self.position += ch.len_utf8();
+                Token::Char(ch)
+            }
+        }
+    }
+
+    /// Recognizes `(?flags)` or `(?flags:` at the current position, where the flags
+    /// are some of `i`, `m` and `s`, optionally followed by `-` and those to clear.
+    /// Returns the token and its length in bytes.
+    fn read_flags(&self) -> Option<(Token, usize)> {
+        let body = self.remaining().strip_prefix("(?")?;
+        let end = body.find([')', ':'])?;
+        let mut flags = Flags::default();
+        let mut enable = true;
+        for ch in body[..end].chars() {
+            let flag = match ch {
+                '-' if enable => {
+                    enable = false;
+                    continue;
+                }
+                'i' => &mut flags.case_insensitive,
+                'm' => &mut flags.multi_line,
+                's' => &mut flags.dot_all,
+                _ => return None,
+            };
+            *flag = Some(enable);
+        }
+        if flags == Flags::default() {
+            // `(?)` and `(?-)` set nothing, and `(?:` has its own token
+            return None;
+        }
+        let token = match &body[end..end + 1] {
+            ")" => Token::SetFlags(flags),
+            _ => Token::OpenFlags(flags),
+        };
+        Some((token, end + 3))
+    }
+
+    fn handle_escape(&mut self) -> Token {
+        if self.position >= self.input.len() {
+            return Token::Char('\\');
+        }
+
+        let ch = self.input[self.position..].chars().next().unwrap();
+        self.position += ch.len_utf8();
+
+        match self.char_escape(ch) {
+            Ok(Some(ch)) => return Token::Char(ch),
+            Ok(None) => {}
+            Err(message) => return Token::Error(message),
+        }
+        match ch {
+            'd' => Token::Digit,
+            'w' => Token::WordChar,
+            's' => Token::Whitespace,
+            'p' | 'P' => match self.property_name() {
+                Ok(name) => Token::Property {
+                    name: name.to_string(),
+                    negated: ch == 'P',
+                },
+                Err(message) => Token::Error(message),
+            },
+            'A' => Token::StartInput,
+            'z' => Token::EndInput,
+            'b' => Token::WordBoundary,
+            'B' => Token::NotWordBoundary,
+            _ => Token::Escape(ch),
+        }
+    }
+
+    /// Finishes an escape that stands for a single character, given the character
+    /// after the `\`: `\n`, `\t`, `\r`, `\0`, `\xFF` or `\x{10FFFF}`. Returns `None`
+    /// for any other escape, and an error for malformed hex.
+    pub fn char_escape(&mut self, ch: char) -> Result<Option<char>, String> {
+        Ok(Some(match ch {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            'x' => {
+                // The escape began with the two bytes `\x`
+                let start = self.position - 2;
+                let rest = self.remaining();
+                let (digits, len) = match rest.strip_prefix('{') {
+                    Some(braced) => match braced.find('}') {
+                        Some(close) if close <= 6 => (&braced[..close], close + 2),
+                        _ => ("", 0),
+                    },
+                    None => (rest.get(..2).unwrap_or(""), 2),
+                };
+                let value = Some(digits)
+                    .filter(|digits| {
+                        !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_hexdigit())
+                    })
+                    .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("invalid hex escape at position {}", start))?;
+                self.position += len;
+                value
+            }
+            _ => return Ok(None),
+        }))
+    }
+
+    /// Reads the name of a Unicode property after `\p` or `\P`: a single letter,
+    /// as in `\pL`, or a name in braces, as in `\p{Greek}`.
+    pub fn property_name(&mut self) -> Result<&'a str, String> {
+        // The escape began with the two bytes `\p`
+        let start = self.position - 2;
+        let rest = self.remaining();
+        let (name, len) = match rest.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(close) => (&braced[..close], close + 2),
+                None => ("", 0),
+            },
+            None => match rest.chars().next() {
+                Some(ch) if ch.is_ascii_alphabetic() => (&rest[..1], 1),
+                _ => ("", 0),
+            },
+        };
+        if name.is_empty() {
+            return Err(format!("invalid Unicode property at position {}", start));
+        }
+        self.position += len;
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_carry_byte_spans() {
+        let mut lexer = Lexer::new("a(b)");
+        let expected = [
+            (Token::Char('a'), 0, 1),
+            (Token::OpenParen, 1, 2),
+            (Token::Char('b'), 2, 3),
+            (Token::CloseParen, 3, 4),
+            (Token::Eof, 4, 4),
+        ];
+        for (token, start, end) in expected {
+            let spanned = lexer.next_token();
+            assert_eq!(spanned, token);
+            assert_eq!((spanned.start, spanned.end), (start, end), "{token:?}");
+        }
+
+        let mut lexer = Lexer::new("é(?:\\d");
+        let spans: Vec<_> = std::iter::from_fn(|| Some(lexer.next_token()))
+            .take(4)
+            .map(|spanned| (spanned.start, spanned.end))
+            .collect();
+        assert_eq!(spans, [(0, 2), (2, 5), (5, 7), (7, 7)]);
+    }
+
+    #[test]
+    fn quoted_text_is_literal() {
+        let mut lexer = Lexer::new("\\Q(*\\E*\\Q\\d");
+        let expected = [
+            (Token::Char('('), 2, 3),
+            (Token::Char('*'), 3, 4),
+            (Token::Star, 6, 7),
+            // Quoting runs to the end without a closing `\E`
+            (Token::Char('\\'), 9, 10),
+            (Token::Char('d'), 10, 11),
+            (Token::Eof, 11, 11),
+        ];
+        for (token, start, end) in expected {
+            let spanned = lexer.next_token();
+            assert_eq!(spanned, token);
+            assert_eq!((spanned.start, spanned.end), (start, end), "{token:?}");
+        }
+    }
+
+    #[test]
+    fn inline_flags() {
+        let on = Some(true);
+        let mut lexer = Lexer::new("(?i)(?ms-i:(?s-m)(?x)");
+        assert_eq!(
+            lexer.next_token(),
+            Token::SetFlags(Flags {
+                case_insensitive: on,
+                ..Flags::default()
+            })
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Token::OpenFlags(Flags {
+                case_insensitive: Some(false),
+                multi_line: on,
+                dot_all: on,
+            })
+        );
+        let spanned = lexer.next_token();
+        assert_eq!(
+            spanned,
+            Token::SetFlags(Flags {
+                multi_line: Some(false),
+                dot_all: on,
+                ..Flags::default()
+            })
+        );
+        assert_eq!((spanned.start, spanned.end), (11, 17));
+        // Unknown flags leave an ordinary group
+        assert_eq!(lexer.next_token(), Token::OpenParen);
+        assert_eq!(lexer.next_token(), Token::Question);
+
+        assert_eq!(Lexer::new("(?)").next_token(), Token::OpenParen);
+        assert_eq!(Lexer::new("(?i-)").next_token().end, 5);
+    }
+
+    #[test]
+    fn character_escapes() {
+        let mut lexer = Lexer::new("\\n\\t\\r\\0\\x41\\x{1F600}\\x{a}\\d");
+        for ch in ['\n', '\t', '\r', '\0', 'A', '😀', '\n'] {
+            assert_eq!(lexer.next_token(), Token::Char(ch));
+        }
+        assert_eq!(lexer.next_token(), Token::Digit);
+
+        for bad in [
+            "\\x",
+            "\\x4",
+            "\\xg1",
+            "\\x{}",
+            "\\x{110000}",
+            "\\x{d800}",
+            "\\x{41",
+        ] {
+            assert_eq!(
+                Lexer::new(bad).next_token(),
+                Token::Error("invalid hex escape at position 0".to_string()),
+                "{bad:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn unicode_properties() {
+        let mut lexer = Lexer::new("\\pL\\P{Greek}x\\p{}");
+        let property = |name: &str, negated| Token::Property {
+            name: name.to_string(),
+            negated,
+        };
+        assert_eq!(lexer.next_token(), property("L", false));
+        assert_eq!(lexer.next_token(), property("Greek", true));
+        assert_eq!(lexer.next_token(), Token::Char('x'));
+        assert_eq!(
+            lexer.next_token(),
+            Token::Error("invalid Unicode property at position 13".to_string())
+        );
+    }
+}
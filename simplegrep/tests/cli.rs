@@ -0,0 +1,627 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn sg(args: &[&str], stdin: &str) -> Output {
+    sg_bytes(args, stdin.as_bytes())
+}
+
+/// Like `sg`, but feeds raw bytes to standard input.
+fn sg_bytes(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sg"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start sg");
+    // sg may exit without reading its input, closing the pipe early
+    let _ = child.stdin.take().unwrap().write_all(stdin);
+    child.wait_with_output().unwrap()
+}
+
+/// A fresh, empty directory for one test.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sg-test-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn replace_prints_substituted_lines() {
+    let output = sg(
+        &["-p", "(\\w+)@(\\w+)", "--replace", "$2 at $1"],
+        "me@home\nnothing\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "home at me\n");
+}
+
+#[test]
+fn in_place_rewrites_file_and_keeps_backup() {
+    let dir = temp_dir("in-place");
+    let path = dir.join("input.txt");
+    let original = "foo bar\r\nbaz\nfoooo\n";
+    fs::write(&path, original).unwrap();
+
+    let file = path.to_str().unwrap();
+    let output = sg(
+        &[
+            "-p",
+            "f(o+)",
+            "--replace",
+            "F$1",
+            "--in-place=.bak",
+            "-f",
+            file,
+        ],
+        "",
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "Foo bar\r\nbaz\nFoooo\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("input.txt.bak")).unwrap(),
+        original
+    );
+    assert!(!dir.join("input.txt.sg-tmp").exists());
+
+    let output = sg(
+        &["-p", "baz", "--replace", "qux", "--in-place", "-f", file],
+        "",
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "Foo bar\r\nqux\nFoooo\n"
+    );
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn failed_in_place_rewrite_removes_temp_file() {
+    let dir = temp_dir("in-place-failure");
+    let path = dir.join("input.txt");
+    fs::write(&path, "foo\n").unwrap();
+    // The backup cannot be copied over a directory
+    fs::create_dir(dir.join("input.txt.bak")).unwrap();
+
+    let file = path.to_str().unwrap();
+    let output = sg(
+        &[
+            "-p",
+            "foo",
+            "--replace",
+            "bar",
+            "--in-place=.bak",
+            "-f",
+            file,
+        ],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "foo\n");
+    assert!(!dir.join("input.txt.sg-tmp").exists());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn in_place_rejects_stdin() {
+    let output = sg(&["-p", "a", "--replace", "b", "--in-place"], "a\n");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("standard input"));
+}
+
+#[test]
+fn strict_rejects_unknown_escape() {
+    let output = sg(&["-p", "\\q", "--strict"], "q\n");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("unrecognized escape sequence '\\q' at position 0")
+    );
+    assert_eq!(
+        sg(&["--strict", "-p", "[\\q]"], "q\n").status.code(),
+        Some(2)
+    );
+
+    let output = sg(&["-p", "\\q"], "q\n");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "q\n");
+}
+
+#[test]
+fn explain_summarizes_automaton() {
+    let output = sg(&["-p", "^ab", "--explain"], "");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // Combined start, match, two saves, the anchor and two chars
+    assert!(stdout.contains("states: 7\n"), "{}", stdout);
+    assert!(stdout.contains("anchored: yes\n"));
+    assert!(stdout.contains("literal prefix: \"ab\"\n"));
+    assert!(stdout.contains("matcher: DFA\n"));
+
+    let output = sg(&["-p", "a[ab]{9}[ab]{9}$", "--explain"], "");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("matcher: lazy DFA\n"), "{}", stdout);
+}
+
+#[test]
+fn quiet_reports_match_through_exit_status() {
+    let output = sg(&["-q", "-p", "b"], "a\nb\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+
+    let output = sg(&["-q", "-p", "z"], "a\nb\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn quiet_with_invert_looks_for_non_matching_lines() {
+    assert_eq!(
+        sg(&["-q", "-v", "-p", "b"], "a\nb\n").status.code(),
+        Some(0)
+    );
+    assert_eq!(
+        sg(&["-q", "-v", "-p", "b"], "b\nbb\n").status.code(),
+        Some(1)
+    );
+}
+
+#[test]
+fn exit_status_reflects_matches() {
+    assert_eq!(sg(&["-p", "b"], "a\nb\n").status.code(), Some(0));
+    assert_eq!(sg(&["-p", "z"], "a\nb\n").status.code(), Some(1));
+    assert_eq!(sg(&["-v", "-p", "a|b"], "a\nb\n").status.code(), Some(1));
+}
+
+#[test]
+fn exit_status_two_on_errors() {
+    let output = sg(&["-p", "(a"], "a\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error: invalid regex"));
+
+    let dir = temp_dir("missing-file");
+    let missing = dir.join("missing.txt");
+    let output = sg(&["-p", "a", "-f", missing.to_str().unwrap()], "");
+    assert_eq!(output.status.code(), Some(2));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn invalid_utf8_line_does_not_stop_search() {
+    let output = sg_bytes(&["-p", "a"], b"a1\n\xff a2\na3\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "a1\n\u{fffd} a2\na3\n"
+    );
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("(standard input): line 2 is not valid UTF-8")
+    );
+
+    let dir = temp_dir("invalid-utf8");
+    let file = dir.join("latin1.txt");
+    fs::write(&file, b"caf\xe9\n").unwrap();
+    let file = file.to_str().unwrap();
+    let output = sg(&["-p", "caf", "-f", file], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains(&format!("{}: line 1 is not valid UTF-8", file))
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn binary_matches_invalid_utf8_as_bytes() {
+    let input = b"a\xffb\na\xef\xbf\xbdb\n";
+    let output = sg_bytes(&["-p", "a\u{fffd}b"], input);
+    assert_eq!(output.stdout, "a\u{fffd}b\na\u{fffd}b\n".as_bytes());
+
+    // The invalid byte is one unit that `.` matches but U+FFFD does not
+    let output = sg_bytes(&["--binary", "-n", "-p", "a\u{fffd}b"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2:a\u{fffd}b\n");
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("line 1 is not valid UTF-8; matching it byte by byte")
+    );
+    let output = sg_bytes(&["--binary", "-c", "-p", "^a.b$"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n");
+    let output = sg_bytes(&["--binary", "-q", "-p", "^a\u{fffd}b$"], b"a\xffb\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn count_matches_totals_occurrences() {
+    let output = sg(&["--count-matches", "-p", "ab"], "ab ab\nnone\nxaby\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+
+    let output = sg(&["--count-matches", "-p", "ab", "-p", "y"], "ab ab\nxaby\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "4\n");
+
+    let output = sg(&["--count-matches", "-p", "z"], "ab\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0\n");
+}
+
+#[test]
+fn expand_tabs_in_output_only() {
+    let output = sg(
+        &["--expand-tabs=4", "-p", "a\tb"],
+        "a\tb\tc\nab\tcdefg\th\n",
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a   b   c\n");
+
+    let output = sg(&["--expand-tabs", "-p", "d"], "ab\tcdefg\th\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "ab      cdefg   h\n"
+    );
+
+    assert_eq!(
+        sg(&["--expand-tabs=0", "-p", "a"], "a\n").status.code(),
+        Some(2)
+    );
+}
+
+#[test]
+fn count_with_invert_counts_non_matching_lines() {
+    let input = "a1\nb\na2\nc\nd\n";
+    let output = sg(&["-v", "-c", "-p", "a"], input);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+
+    let output = sg(&["-c", "-p", "a"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n");
+
+    let output = sg(&["-v", "-c", "-p", "."], input);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0\n");
+}
+
+#[test]
+fn max_count_stops_after_selected_lines() {
+    let input = "a1\nb\na2\nc\nd\n";
+    let output = sg(&["-v", "-c", "-m", "2", "-p", "a"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n");
+
+    let output = sg(&["-v", "-m", "2", "-p", "a"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "b\nc\n");
+
+    let output = sg(&["-m", "0", "-p", "a"], input);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn posix_prefers_longest_alternative() {
+    let output = sg(&["-p", "a|ab", "--replace", "[$0]"], "xab\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "x[a]b\n");
+
+    let output = sg(&["--posix", "-p", "a|ab", "--replace", "[$0]"], "xab\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "x[ab]\n");
+}
+
+#[test]
+fn files_without_match_lists_only_files_lacking_the_pattern() {
+    let dir = temp_dir("files-without-match");
+    fs::write(dir.join("has.txt"), "one\nneedle\n").unwrap();
+    fs::write(dir.join("lacks.txt"), "one\ntwo\n").unwrap();
+    fs::write(dir.join("also.txt"), "needles\n").unwrap();
+    let paths: Vec<String> = ["has.txt", "lacks.txt", "also.txt"]
+        .iter()
+        .map(|name| dir.join(name).to_str().unwrap().to_string())
+        .collect();
+
+    let output = sg(
+        &[
+            "-L", "-p", "needle", "-f", &paths[0], "-f", &paths[1], "-f", &paths[2],
+        ],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}\n", paths[1])
+    );
+
+    let output = sg(
+        &["-L", "-p", "needle", "-f", &paths[0], "-f", &paths[2]],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+
+    let output = sg(&["-L", "-p", "needle"], "hay\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "(standard input)\n"
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn files_with_matches_lists_files_containing_the_pattern() {
+    let dir = temp_dir("files-with-matches");
+    // The big file is decided by its first line; any_match stops reading there
+    let big = format!("needle\n{}", "hay\n".repeat(100_000));
+    fs::write(dir.join("big.txt"), big).unwrap();
+    fs::write(dir.join("lacks.txt"), "one\ntwo\n").unwrap();
+    fs::write(dir.join("also.txt"), "needles\n").unwrap();
+    let paths: Vec<String> = ["big.txt", "lacks.txt", "also.txt"]
+        .iter()
+        .map(|name| dir.join(name).to_str().unwrap().to_string())
+        .collect();
+
+    let output = sg(
+        &[
+            "-l", "-p", "needle", "-f", &paths[0], "-f", &paths[1], "-f", &paths[2],
+        ],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}\n{}\n", paths[0], paths[2])
+    );
+
+    let output = sg(
+        &["--files-with-matches", "-p", "needle", "-f", &paths[1]],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+
+    let output = sg(&["-l", "-v", "-p", "needle"], "needle\nhay\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "(standard input)\n"
+    );
+    assert_eq!(sg(&["-l", "-L", "-p", "x"], "").status.code(), Some(2));
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn ignore_case_folds_pattern_not_input() {
+    let input = "Hello\nHELLO world\nhelp\nÉcole\n";
+    let output = sg(&["-i", "-p", "hello"], input);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Hello\nHELLO world\n"
+    );
+
+    let output = sg(&["--ignore-case", "-p", "[é]cole", "-p", "^H.LP$"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "help\nÉcole\n");
+
+    let output = sg(&["-i", "-p", "(L+)o", "--replace", "<$1>"], input);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "He<ll>\nHE<LL> world\n"
+    );
+
+    let output = sg(&["-i", "-v", "-c", "-p", "HEL"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+}
+
+#[test]
+fn inline_flag_turns_ignore_case_back_off() {
+    let input = "ABC\nabc\naBC\n";
+    let output = sg(&["-i", "-p", "(?-i)abc"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "abc\n");
+
+    let output = sg(&["-i", "-p", "(?-i:a)bc"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "abc\naBC\n");
+}
+
+#[test]
+fn character_escapes_match_tabs_and_hex() {
+    let output = sg(&["-p", "a\\tb", "-p", "\\x{e9}t\\x65"], "a b\na\tb\néte\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a\tb\néte\n");
+
+    let output = sg(&["-p", "\\x4"], "x\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid hex escape at position 0"));
+}
+
+#[test]
+fn null_data_records_span_lines() {
+    let input = "[a]\nkey = 1\n\0[b]\nkey = 2\n\0";
+    let output = sg(&["-z", "-p", "^\\[b\\]"], input);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "[b]\nkey = 2\n\0");
+
+    // `$` only matches at the end of the record unless multi-line mode is on
+    let output = sg(&["-z", "-c", "-p", "^key = 1$"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0\n");
+    let output = sg(&["-z", "--multi-line", "-c", "-p", "^key = 1$"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+    let output = sg(&["-z", "-c", "-p", "(?m)^key = \\d$"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n");
+}
+
+#[test]
+fn line_regexp_requires_the_whole_line_to_match() {
+    let input = "foo\nxfoox\nfood\n";
+    let output = sg(&["-p", "foo"], input);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "foo\nxfoox\nfood\n"
+    );
+    let output = sg(&["-x", "-p", "foo|food"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "foo\nfood\n");
+    let output = sg(
+        &["--line-regexp", "-p", "(o+)", "--replace", "[$1]"],
+        "oo\nfoo\n",
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "[oo]\n");
+}
+
+#[test]
+fn only_matching_prints_each_match() {
+    let input = "a1 b22\nnone\nc333\n";
+    let output = sg(&["-o", "-p", "\\d+"], input);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n22\n333\n");
+
+    // Empty matches print nothing, not even a blank line
+    let output = sg(&["--only-matching", "-p", "\\d*"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n22\n333\n");
+
+    let output = sg(&["-o", "-p", "b\\d", "-p", "c"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "b2\nc\n");
+
+    let output = sg(&["-o", "-v", "-p", "x"], input);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn color_highlights_matches_and_file_names() {
+    let input = "a1\tb22\nnone\n";
+    // Standard output is a pipe here, so the default of auto prints no color
+    let output = sg(&["-p", "\\d+"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a1\tb22\n");
+
+    let output = sg(&["--color=always", "-p", "\\d+"], input);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "a\x1b[1;31m1\x1b[0m\tb\x1b[1;31m22\x1b[0m\n"
+    );
+    // Tab stops ignore the escape sequences
+    let output = sg(&["--color=always", "--expand-tabs=4", "-p", "\\d+"], input);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "a\x1b[1;31m1\x1b[0m  b\x1b[1;31m22\x1b[0m\n"
+    );
+    let output = sg(&["--color=always", "-o", "-p", "2+"], input);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\x1b[1;31m22\x1b[0m\n"
+    );
+    let output = sg(&["--color=never", "-p", "\\d+"], input);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a1\tb22\n");
+
+    let dir = temp_dir("color");
+    let path = dir.join("input.txt");
+    fs::write(&path, input).unwrap();
+    let file = path.to_str().unwrap();
+    let output = sg(&["--color=always", "-n", "-p", "none", "-f", file], "");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "\x1b[35m{file}\x1b[0m\x1b[36m:\x1b[0m\x1b[32m2\x1b[0m\x1b[36m:\x1b[0m\x1b[1;31mnone\x1b[0m\n"
+        )
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn context_lines_around_selected_lines() {
+    let input = "1\n2\nhit a\n4\n5\n6\n7\nhit b\nhit c\n10\n";
+    let stdout = |args: &[&str]| String::from_utf8_lossy(&sg(args, input).stdout).into_owned();
+
+    assert_eq!(
+        stdout(&["-A", "1", "-p", "hit"]),
+        "hit a\n4\n--\nhit b\nhit c\n10\n"
+    );
+    assert_eq!(
+        stdout(&["-B", "2", "-p", "hit"]),
+        "1\n2\nhit a\n--\n6\n7\nhit b\nhit c\n"
+    );
+    // Groups that touch or overlap are merged without a separator
+    assert_eq!(
+        stdout(&["-C", "2", "-p", "hit"]),
+        "1\n2\nhit a\n4\n5\n6\n7\nhit b\nhit c\n10\n"
+    );
+    assert_eq!(
+        stdout(&["-C", "1", "-p", "hit"]),
+        "2\nhit a\n4\n--\n7\nhit b\nhit c\n10\n"
+    );
+    // -A and -B take precedence over -C
+    assert_eq!(
+        stdout(&["-C", "3", "-A", "0", "-B", "1", "-p", "a"]),
+        "2\nhit a\n"
+    );
+    // The after-context of the last line -m allows is still printed
+    assert_eq!(
+        stdout(&["-m", "1", "-A", "2", "-p", "hit"]),
+        "hit a\n4\n5\n"
+    );
+}
+
+#[test]
+fn context_lines_name_their_file() {
+    let dir = temp_dir("context");
+    let path = dir.join("input.txt");
+    fs::write(&path, "a\nb\nc\n").unwrap();
+    let file = path.to_str().unwrap();
+    let output = sg(&["-B", "1", "-n", "-p", "b", "-f", file, "-f", file], "");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{file}-1-a\n{file}:2:b\n--\n{file}-1-a\n{file}:2:b\n")
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn line_numbers_and_columns_prefix_each_line() {
+    let input = "abc\nxbyb\nzzz\n";
+    let stdout = |args: &[&str]| String::from_utf8_lossy(&sg(args, input).stdout).into_owned();
+    assert_eq!(stdout(&["-n", "-p", "b"]), "1:abc\n2:xbyb\n");
+    assert_eq!(stdout(&["--column", "-p", "b"]), "2:abc\n2:xbyb\n");
+    assert_eq!(stdout(&["-n", "--column", "-p", "y"]), "2:3:xbyb\n");
+    // With -o each match gets its own column
+    assert_eq!(
+        stdout(&["-no", "--column", "-p", "b"]),
+        "1:2:b\n2:2:b\n2:4:b\n"
+    );
+    // Inverted lines have no match to point at
+    assert_eq!(stdout(&["-nv", "--column", "-p", "b"]), "3:zzz\n");
+    // Columns count bytes, as in grep
+    assert_eq!(stdout(&["--column", "-p", "x"]), "1:xbyb\n");
+    assert_eq!(
+        String::from_utf8_lossy(&sg(&["--column", "-p", "b"], "éb\n").stdout),
+        "3:éb\n"
+    );
+}
+
+#[test]
+fn counts_per_file_with_a_total() {
+    let dir = temp_dir("counts");
+    let one = dir.join("one.txt");
+    let two = dir.join("two.txt");
+    fs::write(&one, "ab ab\nnone\n").unwrap();
+    fs::write(&two, "xaby\nab\nab\n").unwrap();
+    let (one, two) = (one.to_str().unwrap(), two.to_str().unwrap());
+
+    let output = sg(&["-c", "-p", "ab", "-f", one, "-f", two], "");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{one}:1\n{two}:3\ntotal:4\n")
+    );
+    let output = sg(&["--count-matches", "-p", "ab", "-f", one, "-f", two], "");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{one}:2\n{two}:3\ntotal:5\n")
+    );
+    // A single file has no total
+    let output = sg(&["-c", "-p", "none", "-f", one], "");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{one}:1\n")
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}